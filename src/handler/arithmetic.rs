@@ -0,0 +1,401 @@
+use crate::handler::lexer::is_valid_name;
+
+/// Variables an expression reads and assigns.
+pub trait Scope {
+    fn get(&self, name: &str) -> Option<String>;
+    fn set(&mut self, name: &str, value: String);
+}
+
+/// Operators longest first, so `<<=` is not read as `<` and `<=`.
+const OPERATORS: [&str; 40] = [
+    "<<=", ">>=", "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+=", "-=", "*=", "/=", "%=",
+    "&=", "|=", "^=", "+", "-", "*", "/", "%", "<", ">", "&", "|", "^", "!", "~", "?", ":", "=", "(", ")", ",",
+    "$",
+];
+
+/// Binary operators from the loosest binding to the tightest, `**` binds
+/// tighter still and goes right to left.
+const LEVELS: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+const ASSIGNMENTS: [&str; 11] = ["=", "+=", "-=", "*=", "/=", "%=", "<<=", ">>=", "&=", "|=", "^="];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Operator(&'static str),
+}
+
+fn tokenize(expression: &str) -> Result<Vec<(Token, usize)>, String> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = vec![];
+    let mut pos = 0;
+
+    while pos < chars.len() {
+        let start = pos;
+        let c = chars[pos];
+        if c.is_whitespace() {
+            pos += 1;
+            continue;
+        }
+
+        if c.is_ascii_alphanumeric() || c == '_' {
+            while pos < chars.len() && (chars[pos].is_ascii_alphanumeric() || chars[pos] == '_') {
+                pos += 1;
+            }
+            let text: String = chars[start..pos].iter().collect();
+            let token = if c.is_ascii_digit() {
+                let number = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+                    Some(hex) => i64::from_str_radix(hex, 16),
+                    None => text.parse(),
+                };
+                Token::Number(number.map_err(|_| format!("{}: value too great for base", text))?)
+            } else {
+                Token::Name(text)
+            };
+            tokens.push((token, start));
+            continue;
+        }
+
+        let rest: String = chars[pos..].iter().collect();
+        let Some(operator) = OPERATORS.iter().find(|operator| rest.starts_with(*operator)) else {
+            return Err(format!("syntax error: invalid arithmetic operator (error token is \"{}\")", rest));
+        };
+        pos += operator.len();
+
+        // `$x`, `${x}` and `$1` read the variable like a bare name
+        if *operator == "$" {
+            let braced = chars.get(pos) == Some(&'{');
+            let name_start = pos + braced as usize;
+            let mut end = name_start;
+            while end < chars.len() && (chars[end].is_ascii_alphanumeric() || matches!(chars[end], '_' | '#' | '?')) {
+                end += 1;
+            }
+            if end == name_start || braced && chars.get(end) != Some(&'}') {
+                return Err(format!("syntax error: operand expected (error token is \"{}\")", rest));
+            }
+            tokens.push((Token::Name(chars[name_start..end].iter().collect()), start));
+            pos = end + braced as usize;
+            continue;
+        }
+        tokens.push((Token::Operator(operator), start));
+    }
+
+    Ok(tokens)
+}
+
+fn apply(operator: &str, left: i64, right: i64) -> Result<i64, String> {
+    Ok(match operator {
+        "||" => (left != 0 || right != 0) as i64,
+        "&&" => (left != 0 && right != 0) as i64,
+        "|" => left | right,
+        "^" => left ^ right,
+        "&" => left & right,
+        "==" => (left == right) as i64,
+        "!=" => (left != right) as i64,
+        "<" => (left < right) as i64,
+        "<=" => (left <= right) as i64,
+        ">" => (left > right) as i64,
+        ">=" => (left >= right) as i64,
+        "<<" => left.wrapping_shl(right as u32),
+        ">>" => left.wrapping_shr(right as u32),
+        "+" => left.wrapping_add(right),
+        "-" => left.wrapping_sub(right),
+        "*" => left.wrapping_mul(right),
+        "/" | "%" if right == 0 => return Err("division by 0".to_string()),
+        "/" => left.wrapping_div(right),
+        "%" => left.wrapping_rem(right),
+        "**" if right < 0 => return Err("exponent less than 0".to_string()),
+        "**" => left.wrapping_pow(right.min(u32::MAX as i64) as u32),
+        _ => unreachable!("not a binary operator: {}", operator),
+    })
+}
+
+/// Recursive descent over the tokens that computes as it parses. With
+/// `eval` off a branch is only parsed, the short-circuited side of `&&`,
+/// `||` and `?:` neither assigns nor fails on division by 0.
+struct Evaluator<'a> {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    expression: &'a str,
+    scope: &'a mut dyn Scope,
+}
+
+impl Evaluator<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn peek_operator(&self, operators: &[&str]) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Operator(operator)) if operators.contains(operator) => Some(operator),
+            _ => None,
+        }
+    }
+
+    fn error_token(&self) -> String {
+        let start = self.tokens.get(self.pos).map_or(self.expression.chars().count(), |(_, start)| *start);
+        format!("(error token is \"{}\")", self.expression.chars().skip(start).collect::<String>().trim())
+    }
+
+    fn get(&self, name: &str) -> Result<i64, String> {
+        let value = self.scope.get(name).unwrap_or_default();
+        let value = value.trim();
+        if value.is_empty() {
+            return Ok(0);
+        }
+        value
+            .parse()
+            .map_err(|_| format!("{}: syntax error: operand expected (error token is \"{}\")", value, value))
+    }
+
+    fn set(&mut self, name: &str, value: i64, eval: bool) -> Result<(), String> {
+        if !is_valid_name(name) {
+            return Err(format!("attempted assignment to non-variable {}", self.error_token()));
+        }
+        if eval {
+            self.scope.set(name, value.to_string());
+        }
+        Ok(())
+    }
+
+    fn comma(&mut self, eval: bool) -> Result<i64, String> {
+        let mut value = self.assignment(eval)?;
+        while self.peek_operator(&[","]).is_some() {
+            self.pos += 1;
+            value = self.assignment(eval)?;
+        }
+        Ok(value)
+    }
+
+    fn assignment(&mut self, eval: bool) -> Result<i64, String> {
+        if let (Some(Token::Name(name)), Some((Token::Operator(operator), _))) =
+            (self.peek().cloned(), self.tokens.get(self.pos + 1).cloned())
+        {
+            if ASSIGNMENTS.contains(&operator) {
+                self.pos += 2;
+                let right = self.assignment(eval)?;
+                let value = match operator {
+                    "=" => right,
+                    _ if !eval => 0,
+                    _ => apply(operator.trim_end_matches('='), self.get(&name)?, right)?,
+                };
+                self.set(&name, value, eval)?;
+                return Ok(value);
+            }
+        }
+        self.conditional(eval)
+    }
+
+    fn conditional(&mut self, eval: bool) -> Result<i64, String> {
+        let condition = self.binary(0, eval)?;
+        if self.peek_operator(&["?"]).is_none() {
+            return Ok(condition);
+        }
+
+        self.pos += 1;
+        let then = self.assignment(eval && condition != 0)?;
+        if self.peek_operator(&[":"]).is_none() {
+            return Err(format!("syntax error: `:' expected for conditional expression {}", self.error_token()));
+        }
+        self.pos += 1;
+        let otherwise = self.conditional(eval && condition == 0)?;
+        Ok(if condition != 0 { then } else { otherwise })
+    }
+
+    fn binary(&mut self, level: usize, eval: bool) -> Result<i64, String> {
+        if level == LEVELS.len() {
+            return self.power(eval);
+        }
+
+        let mut left = self.binary(level + 1, eval)?;
+        while let Some(operator) = self.peek_operator(LEVELS[level]) {
+            self.pos += 1;
+            let short_circuit = match operator {
+                "&&" => left == 0,
+                "||" => left != 0,
+                _ => false,
+            };
+            let right = self.binary(level + 1, eval && !short_circuit)?;
+            left = if eval { apply(operator, left, right)? } else { 0 };
+        }
+        Ok(left)
+    }
+
+    fn power(&mut self, eval: bool) -> Result<i64, String> {
+        let base = self.unary(eval)?;
+        if self.peek_operator(&["**"]).is_none() {
+            return Ok(base);
+        }
+
+        self.pos += 1;
+        let exponent = self.power(eval)?;
+        if eval {
+            apply("**", base, exponent)
+        } else {
+            Ok(0)
+        }
+    }
+
+    fn unary(&mut self, eval: bool) -> Result<i64, String> {
+        let Some(operator) = self.peek_operator(&["!", "~", "+", "-", "++", "--"]) else {
+            return self.postfix(eval);
+        };
+        self.pos += 1;
+
+        match operator {
+            "!" => Ok((self.unary(eval)? == 0) as i64),
+            "~" => Ok(!self.unary(eval)?),
+            "+" => self.unary(eval),
+            "-" => Ok(self.unary(eval)?.wrapping_neg()),
+            // `++x` and `--x`
+            _ => {
+                let Some(Token::Name(name)) = self.peek().cloned() else {
+                    return Err(format!("syntax error: operand expected {}", self.error_token()));
+                };
+                let value = self.get(&name)?.wrapping_add(if operator == "++" { 1 } else { -1 });
+                self.set(&name, value, eval)?;
+                self.pos += 1;
+                Ok(value)
+            }
+        }
+    }
+
+    fn postfix(&mut self, eval: bool) -> Result<i64, String> {
+        let next = self.tokens.get(self.pos + 1).map(|(token, _)| token);
+        if let (Some(Token::Name(name)), Some(Token::Operator(operator @ ("++" | "--")))) = (self.peek().cloned(), next) {
+            let value = self.get(&name)?;
+            self.set(&name, value.wrapping_add(if *operator == "++" { 1 } else { -1 }), eval)?;
+            self.pos += 2;
+            return Ok(value);
+        }
+        self.primary(eval)
+    }
+
+    fn primary(&mut self, eval: bool) -> Result<i64, String> {
+        match self.peek().cloned() {
+            Some(Token::Number(number)) => {
+                self.pos += 1;
+                Ok(number)
+            }
+            Some(Token::Name(name)) => {
+                self.pos += 1;
+                self.get(&name)
+            }
+            Some(Token::Operator("(")) => {
+                self.pos += 1;
+                let value = self.comma(eval)?;
+                if self.peek_operator(&[")"]).is_none() {
+                    return Err(format!("missing `)' {}", self.error_token()));
+                }
+                self.pos += 1;
+                Ok(value)
+            }
+            _ => Err(format!("syntax error: operand expected {}", self.error_token())),
+        }
+    }
+}
+
+/// Evaluates `$((expression))` with 64-bit wrapping integers like bash. An
+/// empty expression is 0, errors start with the expression they are about.
+pub fn evaluate(expression: &str, scope: &mut dyn Scope) -> Result<i64, String> {
+    let trimmed = expression.trim();
+    let tokens = tokenize(trimmed).map_err(|err| format!("{}: {}", trimmed, err))?;
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+
+    let mut evaluator = Evaluator {
+        tokens,
+        pos: 0,
+        expression: trimmed,
+        scope,
+    };
+    let value = evaluator.comma(true);
+    let value = match value {
+        Ok(_) if evaluator.pos < evaluator.tokens.len() => {
+            Err(format!("syntax error in expression {}", evaluator.error_token()))
+        }
+        value => value,
+    };
+    value.map_err(|err| format!("{}: {}", trimmed, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    impl Scope for HashMap<String, String> {
+        fn get(&self, name: &str) -> Option<String> {
+            HashMap::get(self, name).cloned()
+        }
+
+        fn set(&mut self, name: &str, value: String) {
+            self.insert(name.to_string(), value);
+        }
+    }
+
+    fn eval(expression: &str) -> Result<i64, String> {
+        let mut scope: HashMap<String, String> = HashMap::from([("x".to_string(), "5".to_string())]);
+        evaluate(expression, &mut scope)
+    }
+
+    #[test]
+    fn test_operators() {
+        assert_eq!(eval("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval("7 / 2 + 7 % 2"), Ok(4));
+        assert_eq!(eval("-x + $x * ${x}"), Ok(20));
+        assert_eq!(eval("2 ** 3 ** 2"), Ok(512));
+        assert_eq!(eval("1 << 4 | 1"), Ok(17));
+        assert_eq!(eval("x > 3 && x <= 5"), Ok(1));
+        assert_eq!(eval("!x || ~0 == -1"), Ok(1));
+        assert_eq!(eval("x == 5 ? 10 : 20"), Ok(10));
+        assert_eq!(eval("0x1f + missing"), Ok(31));
+        assert_eq!(eval(""), Ok(0));
+    }
+
+    #[test]
+    fn test_assignments() {
+        let mut scope: HashMap<String, String> = HashMap::new();
+        assert_eq!(evaluate("i = 2, i += 3, i *= 2", &mut scope), Ok(10));
+        assert_eq!(evaluate("i++ + ++i", &mut scope), Ok(22));
+        assert_eq!(scope["i"], "12");
+        assert_eq!(evaluate("--i, i--", &mut scope), Ok(11));
+        assert_eq!(scope["i"], "10");
+
+        // the side that doesn't count is not evaluated
+        assert_eq!(evaluate("0 && (j = 1), 1 || (j = 2), i ? 3 : (j = 4)", &mut scope), Ok(3));
+        assert!(!scope.contains_key("j"));
+        assert_eq!(evaluate("0 && 1 / 0", &mut scope), Ok(0));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(eval("1 / 0"), Err("1 / 0: division by 0".to_string()));
+        assert_eq!(
+            eval("1 +"),
+            Err("1 +: syntax error: operand expected (error token is \"\")".to_string())
+        );
+        assert_eq!(
+            eval("2 3"),
+            Err("2 3: syntax error in expression (error token is \"3\")".to_string())
+        );
+        assert!(eval("(1 + 2").is_err());
+        assert!(eval("1 = 2").is_err());
+        assert!(eval("x @ 1").is_err());
+        assert!(eval("2 ** -1").is_err());
+    }
+}
@@ -0,0 +1,94 @@
+use regex::{Regex, RegexBuilder};
+
+use std::io::{Error, ErrorKind};
+
+/// Parsed `grep` command line: `-i` ignore case, `-v` non-matching lines,
+/// `-c` count only, `-n` line numbers, then a regex and the files to read.
+/// Without files stdin is searched.
+#[derive(Debug)]
+pub struct GrepQuery {
+    regex: Regex,
+    invert: bool,
+    count: bool,
+    line_numbers: bool,
+    pub files: Vec<String>,
+}
+
+impl GrepQuery {
+    pub fn parse(args: &[String]) -> Result<Self, Error> {
+        let (flags, mut rest): (Vec<String>, Vec<String>) =
+            args.iter().cloned().partition(|arg| arg.starts_with('-') && arg.len() > 1);
+        let flags: String = flags.iter().map(|flag| &flag[1..]).collect();
+        if let Some(flag) = flags.chars().find(|flag| !"ivcn".contains(*flag)) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("invalid option -- '{}'", flag)));
+        }
+        if rest.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "missing pattern"));
+        }
+
+        let pattern = rest.remove(0);
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(flags.contains('i'))
+            .build()
+            .map_err(|err| Error::new(ErrorKind::InvalidInput, format!("invalid regex {}: {}", pattern, err)))?;
+
+        Ok(GrepQuery {
+            regex,
+            invert: flags.contains('v'),
+            count: flags.contains('c'),
+            line_numbers: flags.contains('n'),
+            files: rest,
+        })
+    }
+
+    /// Selected lines of `text`, each prefixed with `name:` when a name is
+    /// given, and whether any line was selected.
+    pub fn search(&self, text: &str, name: Option<&str>) -> (String, bool) {
+        let prefix = name.map(|name| format!("{}:", name)).unwrap_or_default();
+        let selected: Vec<(usize, &str)> = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| self.regex.is_match(line) != self.invert)
+            .collect();
+
+        let found = !selected.is_empty();
+        if self.count {
+            return (format!("{}{}\n", prefix, selected.len()), found);
+        }
+
+        let mut output = String::new();
+        for (index, line) in selected {
+            output.push_str(&prefix);
+            if self.line_numbers {
+                output.push_str(&format!("{}:", index + 1));
+            }
+            output.push_str(line);
+            output.push('\n');
+        }
+        (output, found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grep(args: &str, text: &str) -> String {
+        let args: Vec<String> = args.split_whitespace().map(str::to_string).collect();
+        GrepQuery::parse(&args).unwrap().search(text, None).0
+    }
+
+    #[test]
+    fn test_grep() {
+        let text = "home.pl\ntest\nHistory.pl\n";
+        assert_eq!(grep("pl", text), "home.pl\nHistory.pl\n");
+        assert_eq!(grep("-v pl", text), "test\n");
+        assert_eq!(grep("-in ^h", text), "1:home.pl\n3:History.pl\n");
+        assert_eq!(grep("-c x", text), "0\n");
+        let query = GrepQuery::parse(&["pl".to_string()]).unwrap();
+        assert_eq!(query.search("a.pl", Some("f")), ("f:a.pl\n".to_string(), true));
+        assert_eq!(query.search("a.txt", None), (String::new(), false));
+        assert!(GrepQuery::parse(&["-x".to_string(), "a".to_string()]).is_err());
+        assert!(GrepQuery::parse(&["(".to_string()]).is_err());
+    }
+}
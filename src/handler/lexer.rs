@@ -0,0 +1,827 @@
+use std::fmt;
+
+use crate::vfs::pattern::escape_glob;
+
+/// A piece of a word as it was written, quoting decides how it is expanded.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WordPart {
+    // bare text, subject to globbing
+    Literal(String),
+    // text from quotes or a backslash escape, taken as is
+    Quoted(String),
+    // a `$` part, split into fields unless quoted
+    Expansion { expansion: Expansion, quoted: bool },
+}
+
+/// What a `$` part of a word stands for, its value comes from the session.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expansion {
+    // `$NAME` or `${NAME}`
+    Variable(String),
+    // `$(command)` or a backquoted command, the source is parsed when it runs
+    Command(String),
+    // `$((expression))`, the expression can have `$` parts of its own
+    Arithmetic(Word),
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Word {
+    pub parts: Vec<WordPart>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedirectKind {
+    // `>` and `>>`
+    Write,
+    Append,
+    // `<`
+    Read,
+    // `>&`, the target is another fd
+    Duplicate,
+    // `&>` and `&>>`, stdout and stderr together
+    WriteAll,
+    AppendAll,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Pipe,
+    // `;`, `&&` and `||` between pipelines, a newline separates like `;`
+    Semicolon,
+    Newline,
+    And,
+    Or,
+    // `;;` ends an arm of `case`
+    CaseEnd,
+    // `(` and `)` around `case` patterns
+    LeftParen,
+    RightParen,
+    // the fd is the number written right before the operator, like in `2>`
+    Redirect(Option<u32>, RedirectKind),
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operator::Pipe => write!(f, "|"),
+            Operator::Semicolon => write!(f, ";"),
+            Operator::Newline => write!(f, "newline"),
+            Operator::And => write!(f, "&&"),
+            Operator::Or => write!(f, "||"),
+            Operator::CaseEnd => write!(f, ";;"),
+            Operator::LeftParen => write!(f, "("),
+            Operator::RightParen => write!(f, ")"),
+            Operator::Redirect(fd, kind) => {
+                if let Some(fd) = fd {
+                    write!(f, "{}", fd)?;
+                }
+                let symbol = match kind {
+                    RedirectKind::Write => ">",
+                    RedirectKind::Append => ">>",
+                    RedirectKind::Read => "<",
+                    RedirectKind::Duplicate => ">&",
+                    RedirectKind::WriteAll => "&>",
+                    RedirectKind::AppendAll => "&>>",
+                };
+                write!(f, "{}", symbol)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    // tokens keep their 1-based column for error messages
+    Word(Word, usize),
+    Operator(Operator, usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    // 1-based, the column counts from the start of the line
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+    // the input stopped in the middle of something, more lines can finish it
+    pub incomplete: bool,
+}
+
+impl SyntaxError {
+    /// Turns a column counted over all of `input` into a line and a column
+    /// in that line, errors are made with the first kind.
+    pub fn locate(self, input: &str) -> Self {
+        let before: Vec<char> = input.chars().take(self.column - 1).collect();
+        let line_start = before.iter().rposition(|c| *c == '\n').map_or(0, |newline| newline + 1);
+
+        SyntaxError {
+            line: 1 + before.iter().filter(|c| **c == '\n').count(),
+            column: self.column - line_start,
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line > 1 {
+            write!(f, "syntax error at line {}, column {}: {}", self.line, self.column, self.message)
+        } else {
+            write!(f, "syntax error at column {}: {}", self.column, self.message)
+        }
+    }
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn is_operator_start(c: char) -> bool {
+    matches!(c, '|' | '<' | '>' | '&' | ';' | '(' | ')' | '\n')
+}
+
+/// Whether `name` can be used as a variable name.
+pub fn is_valid_name(name: &str) -> bool {
+    name.chars().next().is_some_and(is_name_start) && name.chars().all(is_name_char)
+}
+
+// `$?`, `$#`, `$1` and friends are one char long
+fn is_special_param(c: char) -> bool {
+    c.is_ascii_digit() || matches!(c, '?' | '#' | '@' | '*' | '$' | '!')
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Lexer {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn error(&self, column: usize, message: &str) -> SyntaxError {
+        SyntaxError {
+            column: column + 1,
+            message: message.to_string(),
+            incomplete: false,
+            line: 1,
+        }
+    }
+
+    /// An error at the end of the input, like an unterminated quote.
+    fn unfinished(&self, column: usize, message: &str) -> SyntaxError {
+        SyntaxError {
+            incomplete: true,
+            ..self.error(column, message)
+        }
+    }
+
+    /// Reads an operator, `fd` is an already consumed number in front of it.
+    fn operator(&mut self, fd: Option<u32>) -> Result<Operator, SyntaxError> {
+        let start = self.pos;
+        let c = self.peek().unwrap_or_default();
+        self.pos += 1;
+
+        let kind = match c {
+            '|' if self.peek() == Some('|') => {
+                self.pos += 1;
+                return Ok(Operator::Or);
+            }
+            '|' => return Ok(Operator::Pipe),
+            '&' if self.peek() == Some('&') => {
+                self.pos += 1;
+                return Ok(Operator::And);
+            }
+            ';' if self.peek() == Some(';') => {
+                self.pos += 1;
+                return Ok(Operator::CaseEnd);
+            }
+            ';' => return Ok(Operator::Semicolon),
+            '\n' => return Ok(Operator::Newline),
+            '(' => return Ok(Operator::LeftParen),
+            ')' => return Ok(Operator::RightParen),
+            '<' => RedirectKind::Read,
+            '>' => match self.peek() {
+                Some('>') => {
+                    self.pos += 1;
+                    RedirectKind::Append
+                }
+                Some('&') => {
+                    self.pos += 1;
+                    RedirectKind::Duplicate
+                }
+                _ => RedirectKind::Write,
+            },
+            '&' if self.peek() == Some('>') => {
+                self.pos += 1;
+                if self.peek() == Some('>') {
+                    self.pos += 1;
+                    RedirectKind::AppendAll
+                } else {
+                    RedirectKind::WriteAll
+                }
+            }
+            _ => return Err(self.error(start, &format!("unexpected token `{}'", c))),
+        };
+
+        Ok(Operator::Redirect(fd, kind))
+    }
+
+    /// A run of digits right before `<` or `>` is the fd of a redirection.
+    fn io_number(&mut self) -> Option<u32> {
+        let end = (self.pos..self.chars.len())
+            .find(|&end| !self.chars[end].is_ascii_digit())
+            .filter(|&end| end > self.pos && matches!(self.chars[end], '<' | '>'))?;
+
+        let fd = self.chars[self.pos..end].iter().collect::<String>().parse().ok()?;
+        self.pos = end;
+        Some(fd)
+    }
+
+    /// Source up to the `)` closing an already consumed `$(`. Quotes and
+    /// nested parens are skipped over, `$(` in the source nests too.
+    fn command_source(&mut self, start: usize) -> Result<String, SyntaxError> {
+        let source_start = self.pos;
+        let mut depth = 0;
+
+        loop {
+            let Some(c) = self.peek() else {
+                return Err(self.unfinished(start, "unterminated $("));
+            };
+            self.pos += 1;
+            match c {
+                '\\' => self.pos += 1,
+                '\'' => {
+                    while self.peek().is_some_and(|c| c != '\'') {
+                        self.pos += 1;
+                    }
+                    self.pos += 1;
+                }
+                '"' => {
+                    while let Some(c) = self.peek().filter(|c| *c != '"') {
+                        self.pos += if c == '\\' { 2 } else { 1 };
+                    }
+                    self.pos += 1;
+                }
+                '(' => depth += 1,
+                ')' if depth == 0 => break,
+                ')' => depth -= 1,
+                _ => {}
+            }
+        }
+
+        Ok(self.chars[source_start..self.pos - 1].iter().collect())
+    }
+
+    /// Expression up to the `))` closing an already consumed `$((`. Like
+    /// bash it is expanded as if double-quoted before it is evaluated.
+    fn arithmetic(&mut self, start: usize) -> Result<Word, SyntaxError> {
+        let source_start = self.pos;
+        let mut depth = 0;
+
+        loop {
+            match self.peek() {
+                None => return Err(self.unfinished(start, "unterminated $((")),
+                Some('(') => depth += 1,
+                Some(')') if depth == 0 => {
+                    if self.chars.get(self.pos + 1) != Some(&')') {
+                        return Err(self.error(start, "missing `)' in arithmetic expansion"));
+                    }
+                    break;
+                }
+                Some(')') => depth -= 1,
+                _ => {}
+            }
+            self.pos += 1;
+        }
+
+        let mut inner = Lexer {
+            chars: self.chars[source_start..self.pos].to_vec(),
+            pos: 0,
+        };
+        self.pos += 2;
+
+        let mut word = Word::default();
+        inner.quoted_text(&mut word, None).map_err(|err| SyntaxError {
+            column: err.column + source_start,
+            ..err
+        })?;
+        word.parts.retain(|part| !matches!(part, WordPart::Quoted(text) if text.is_empty()));
+        Ok(word)
+    }
+
+    /// Command between backquotes, the opening one is already consumed.
+    /// Inside, a backslash only escapes `$`, `` ` `` and itself.
+    fn backquoted(&mut self, quoted: bool) -> Result<WordPart, SyntaxError> {
+        let start = self.pos - 1;
+        let mut source = String::new();
+
+        loop {
+            match self.peek() {
+                None => return Err(self.unfinished(start, "unterminated `")),
+                Some('`') => break,
+                Some('\\') if matches!(self.chars.get(self.pos + 1), Some('$' | '`' | '\\')) => {
+                    source.push(self.chars[self.pos + 1]);
+                    self.pos += 1;
+                }
+                Some(c) => source.push(c),
+            }
+            self.pos += 1;
+        }
+
+        self.pos += 1;
+        Ok(WordPart::Expansion {
+            expansion: Expansion::Command(source),
+            quoted,
+        })
+    }
+
+    /// Reads what follows a `$`, the `$` itself is already consumed.
+    fn expansion(&mut self, quoted: bool) -> Result<WordPart, SyntaxError> {
+        let start = self.pos - 1;
+        let expansion = |expansion| WordPart::Expansion { expansion, quoted };
+
+        match self.peek() {
+            Some('(') if self.chars.get(self.pos + 1) == Some(&'(') => {
+                self.pos += 2;
+                Ok(expansion(Expansion::Arithmetic(self.arithmetic(start)?)))
+            }
+            Some('(') => {
+                self.pos += 1;
+                Ok(expansion(Expansion::Command(self.command_source(start)?)))
+            }
+            Some('{') => {
+                self.pos += 1;
+                let name_start = self.pos;
+                while self.peek().is_some_and(|c| c != '}') {
+                    self.pos += 1;
+                }
+                if self.peek().is_none() {
+                    return Err(self.unfinished(start, "unterminated ${"));
+                }
+
+                let name: String = self.chars[name_start..self.pos].iter().collect();
+                self.pos += 1;
+                // `${10}` and on need the braces
+                let positional = !name.is_empty() && name.chars().all(|c| c.is_ascii_digit());
+                let valid = is_valid_name(&name)
+                    || positional
+                    || name.len() == 1 && name.chars().all(is_special_param);
+                if !valid {
+                    return Err(self.error(start, &format!("bad substitution ${{{}}}", name)));
+                }
+                Ok(expansion(Expansion::Variable(name)))
+            }
+            Some(c) if is_special_param(c) => {
+                self.pos += 1;
+                Ok(expansion(Expansion::Variable(c.to_string())))
+            }
+            Some(c) if is_name_start(c) => {
+                let name_start = self.pos;
+                while self.peek().is_some_and(is_name_char) {
+                    self.pos += 1;
+                }
+                Ok(expansion(Expansion::Variable(self.chars[name_start..self.pos].iter().collect())))
+            }
+            // a lone `$` is just a dollar sign
+            _ => Ok(if quoted {
+                WordPart::Quoted("$".to_string())
+            } else {
+                WordPart::Literal("$".to_string())
+            }),
+        }
+    }
+
+    fn single_quoted(&mut self) -> Result<WordPart, SyntaxError> {
+        let start = self.pos - 1;
+        let text_start = self.pos;
+        while self.peek().is_some_and(|c| c != '\'') {
+            self.pos += 1;
+        }
+        if self.peek().is_none() {
+            return Err(self.unfinished(start, "unterminated single quote"));
+        }
+
+        let text = self.chars[text_start..self.pos].iter().collect();
+        self.pos += 1;
+        Ok(WordPart::Quoted(text))
+    }
+
+    fn double_quoted(&mut self, word: &mut Word) -> Result<(), SyntaxError> {
+        self.quoted_text(word, Some('"'))
+    }
+
+    /// Text under double quote rules up to `closing`, or up to the end of
+    /// the input without one.
+    fn quoted_text(&mut self, word: &mut Word, closing: Option<char>) -> Result<(), SyntaxError> {
+        let start = self.pos.saturating_sub(1);
+        let mut text = String::new();
+        // `""` still makes an (empty) word
+        word.parts.push(WordPart::Quoted(String::new()));
+
+        loop {
+            match self.peek() {
+                None if closing.is_none() => break,
+                None => return Err(self.unfinished(start, "unterminated double quote")),
+                Some(c) if Some(c) == closing => {
+                    self.pos += 1;
+                    break;
+                }
+                Some('\\') => {
+                    self.pos += 1;
+                    match self.peek() {
+                        // inside double quotes only these are escapes
+                        Some(c @ ('$' | '`' | '"' | '\\')) => {
+                            text.push(c);
+                            self.pos += 1;
+                        }
+                        Some('\n') => self.pos += 1,
+                        _ => text.push('\\'),
+                    }
+                }
+                Some('$') => {
+                    self.pos += 1;
+                    match self.expansion(true)? {
+                        WordPart::Quoted(dollar) => text.push_str(&dollar),
+                        expansion => {
+                            word.parts.push(WordPart::Quoted(std::mem::take(&mut text)));
+                            word.parts.push(expansion);
+                        }
+                    }
+                }
+                Some('`') => {
+                    self.pos += 1;
+                    let command = self.backquoted(true)?;
+                    word.parts.push(WordPart::Quoted(std::mem::take(&mut text)));
+                    word.parts.push(command);
+                }
+                Some(c) => {
+                    text.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+
+        word.parts.push(WordPart::Quoted(text));
+        Ok(())
+    }
+
+    fn word(&mut self) -> Result<Word, SyntaxError> {
+        let mut word = Word::default();
+        let mut literal = String::new();
+
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || is_operator_start(c) {
+                break;
+            }
+            self.pos += 1;
+
+            let part = match c {
+                '\\' => match self.peek() {
+                    // a line continuation disappears
+                    Some('\n') => {
+                        self.pos += 1;
+                        continue;
+                    }
+                    Some(escaped) => {
+                        self.pos += 1;
+                        WordPart::Quoted(escaped.to_string())
+                    }
+                    None => return Err(self.unfinished(self.pos - 1, "unexpected end of line after \\")),
+                },
+                '\'' => self.single_quoted()?,
+                '"' => {
+                    word.parts.push(WordPart::Literal(std::mem::take(&mut literal)));
+                    self.double_quoted(&mut word)?;
+                    continue;
+                }
+                '$' => self.expansion(false)?,
+                '`' => self.backquoted(false)?,
+                _ => {
+                    literal.push(c);
+                    continue;
+                }
+            };
+            word.parts.push(WordPart::Literal(std::mem::take(&mut literal)));
+            word.parts.push(part);
+        }
+        word.parts.push(WordPart::Literal(literal));
+
+        // empty literals are left over from the pushes above, quoted ones keep `""` alive
+        word.parts.retain(|part| !matches!(part, WordPart::Literal(text) if text.is_empty()));
+        Ok(word)
+    }
+}
+
+/// Splits a command line into words and operators. Quotes and escapes stay
+/// recorded in the parts, a `#` starting a word comments out the rest of the line.
+/// Newlines are kept as separators so whole scripts go through here.
+pub fn tokenize(input: &str) -> Result<Vec<Token>, SyntaxError> {
+    let mut lexer = Lexer {
+        chars: input.chars().collect(),
+        pos: 0,
+    };
+    let mut tokens = vec![];
+
+    while let Some(c) = lexer.peek() {
+        if c == '\\' && lexer.chars.get(lexer.pos + 1) == Some(&'\n') {
+            lexer.pos += 2;
+        } else if c.is_whitespace() && c != '\n' {
+            lexer.pos += 1;
+        } else if c == '#' {
+            while lexer.peek().is_some_and(|c| c != '\n') {
+                lexer.pos += 1;
+            }
+        } else if is_operator_start(c) {
+            let column = lexer.pos + 1;
+            tokens.push(Token::Operator(lexer.operator(None)?, column));
+        } else {
+            let column = lexer.pos + 1;
+            match lexer.io_number() {
+                Some(fd) => tokens.push(Token::Operator(lexer.operator(Some(fd))?, column)),
+                None => tokens.push(Token::Word(lexer.word()?, column)),
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+impl Word {
+    /// The text of a word written without any quoting or variables.
+    pub fn as_literal(&self) -> Option<&str> {
+        match self.parts.as_slice() {
+            [WordPart::Literal(literal)] => Some(literal),
+            _ => None,
+        }
+    }
+
+    /// Whether the word is exactly `text` with no quoting, how reserved words
+    /// like `!` and `if` are recognised.
+    pub fn is_literal(&self, text: &str) -> bool {
+        self.as_literal() == Some(text)
+    }
+
+    /// Splits a `NAME=value` word into the name and the value, only an
+    /// unquoted valid name in front of the `=` makes an assignment.
+    pub fn assignment(&self) -> Option<(String, Word)> {
+        let Some(WordPart::Literal(first)) = self.parts.first() else {
+            return None;
+        };
+        let (name, rest) = first.split_once('=')?;
+        if !is_valid_name(name) {
+            return None;
+        }
+
+        let mut value = Word {
+            parts: self.parts[1..].to_vec(),
+        };
+        if !rest.is_empty() {
+            value.parts.insert(0, WordPart::Literal(rest.to_string()));
+        }
+        Some((name.to_string(), value))
+    }
+
+    /// Expands into one string without splitting or globbing, like the value
+    /// of an assignment.
+    pub fn expand_value(&self, resolve: &mut dyn FnMut(&Expansion) -> Option<String>) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                WordPart::Literal(text) | WordPart::Quoted(text) => text.clone(),
+                WordPart::Expansion { expansion, .. } => resolve(expansion).unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    /// Expands the `$` parts with `resolve` into fields ready for globbing:
+    /// text from quotes is glob-escaped, unquoted expansions are split on spaces.
+    pub fn expand(&self, resolve: &mut dyn FnMut(&Expansion) -> Option<String>) -> Vec<String> {
+        let mut fields: Vec<String> = vec![];
+        // an unquoted empty expansion alone must not leave an empty field
+        let mut current: Option<String> = None;
+        // a `"$@"` without parameters, alone it leaves no field at all
+        let mut no_parameters = false;
+
+        for part in &self.parts {
+            match part {
+                WordPart::Literal(text) => current.get_or_insert_with(String::new).push_str(text),
+                WordPart::Quoted(text) => current.get_or_insert_with(String::new).push_str(&escape_glob(text)),
+                // `"$@"` is a field per parameter, the text around it sticks
+                // to the first and the last one
+                WordPart::Expansion { expansion: Expansion::Variable(name), quoted: true } if name == "@" => {
+                    let count = resolve(&Expansion::Variable("#".to_string()))
+                        .and_then(|count| count.parse::<usize>().ok())
+                        .unwrap_or_default();
+                    no_parameters |= count == 0;
+                    for index in 1..=count {
+                        if index > 1 {
+                            fields.extend(current.take());
+                        }
+                        let value = resolve(&Expansion::Variable(index.to_string())).unwrap_or_default();
+                        current.get_or_insert_with(String::new).push_str(&escape_glob(&value));
+                    }
+                }
+                WordPart::Expansion { expansion, quoted: true } => current
+                    .get_or_insert_with(String::new)
+                    .push_str(&escape_glob(&resolve(expansion).unwrap_or_default())),
+                WordPart::Expansion { expansion, quoted: false } => {
+                    let value = resolve(expansion).unwrap_or_default();
+                    let mut pieces = value.split_whitespace().peekable();
+
+                    if value.starts_with(char::is_whitespace) {
+                        fields.extend(current.take());
+                    }
+                    while let Some(piece) = pieces.next() {
+                        // a `\` in the value is just a char, `*`, `?` and `[` still glob
+                        current.get_or_insert_with(String::new).push_str(&piece.replace('\\', "\\\\"));
+                        if pieces.peek().is_some() {
+                            fields.extend(current.take());
+                        }
+                    }
+                    if value.ends_with(char::is_whitespace) {
+                        fields.extend(current.take());
+                    }
+                }
+            }
+        }
+        if no_parameters && fields.is_empty() && current.as_deref() == Some("") {
+            current = None;
+        }
+        fields.extend(current);
+
+        fields
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::pattern::unescape_glob;
+
+    fn lookup(expansion: &Expansion) -> Option<String> {
+        match expansion {
+            Expansion::Variable(name) if name == "HOME" => Some("/home/ilya".to_string()),
+            Expansion::Variable(name) if name == "LIST" => Some("a  b".to_string()),
+            Expansion::Variable(name) if name == "WIN" => Some("C:\\x *.pl".to_string()),
+            // `$1` and `$2` for `"$@"`
+            Expansion::Variable(name) if name == "#" => Some("2".to_string()),
+            Expansion::Variable(name) if name == "1" => Some("a b".to_string()),
+            Expansion::Variable(name) if name == "2" => Some("c".to_string()),
+            Expansion::Command(source) => Some(format!("<{}>", source)),
+            Expansion::Arithmetic(expression) => Some(format!("[{}]", expression.expand_value(&mut lookup))),
+            _ => None,
+        }
+    }
+
+    fn words(input: &str) -> Vec<Word> {
+        tokenize(input)
+            .unwrap()
+            .into_iter()
+            .filter_map(|token| match token {
+                Token::Word(word, _) => Some(word),
+                Token::Operator(..) => None,
+            })
+            .collect()
+    }
+
+    fn expand(input: &str) -> Vec<String> {
+        words(input)
+            .iter()
+            .flat_map(|word| word.expand(&mut lookup))
+            .map(|field| unescape_glob(&field))
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(expand("cd \"my dir\""), vec!["cd", "my dir"]);
+        assert_eq!(expand("echo 'a  b'"), vec!["echo", "a  b"]);
+        assert_eq!(expand("ls my\\ file"), vec!["ls", "my file"]);
+        assert_eq!(expand("ls # trailing comment"), vec!["ls"]);
+        assert_eq!(expand("echo a#b"), vec!["echo", "a#b"]);
+        assert_eq!(expand("echo '' \"\""), vec!["echo", "", ""]);
+        assert!(expand("# only a comment").is_empty());
+    }
+
+    #[test]
+    fn test_operators() {
+        let tokens = tokenize("ls|grep 'a|b' x\\|y").unwrap();
+        assert_eq!(tokens.len(), 5);
+        assert_eq!(tokens[1], Token::Operator(Operator::Pipe, 3));
+        assert_eq!(expand("ls|grep 'a|b' x\\|y"), vec!["ls", "grep", "a|b", "x|y"]);
+
+        let operators: Vec<String> = tokenize("ls>a 2>>b 2>&1 <c &>d &>>e x2>f '2'>g")
+            .unwrap()
+            .iter()
+            .filter_map(|token| match token {
+                Token::Operator(operator, _) => Some(operator.to_string()),
+                Token::Word(..) => None,
+            })
+            .collect();
+        assert_eq!(operators, vec![">", "2>>", "2>&", "<", "&>", "&>>", ">", ">"]);
+        assert_eq!(tokenize("ls 2>&1").unwrap()[1], Token::Operator(Operator::Redirect(Some(2), RedirectKind::Duplicate), 4));
+        assert_eq!(expand("echo a\\>b 2"), vec!["echo", "a>b", "2"]);
+        assert_eq!(tokenize("ls & x").unwrap_err().to_string(), "syntax error at column 4: unexpected token `&'");
+
+        let operators: Vec<String> = tokenize("a;b&&c||d|e")
+            .unwrap()
+            .iter()
+            .filter_map(|token| match token {
+                Token::Operator(operator, _) => Some(operator.to_string()),
+                Token::Word(..) => None,
+            })
+            .collect();
+        assert_eq!(operators, vec![";", "&&", "||", "|"]);
+
+        let operators: Vec<String> = tokenize("a) x;;\n(b # c;\nd\\\ne")
+            .unwrap()
+            .iter()
+            .filter_map(|token| match token {
+                Token::Operator(operator, _) => Some(operator.to_string()),
+                Token::Word(..) => None,
+            })
+            .collect();
+        assert_eq!(operators, vec![")", ";;", "newline", "(", "newline"]);
+        assert_eq!(expand("echo a \\\n b\\\nc # x\nls"), vec!["echo", "a", "bc", "ls"]);
+        assert!(words("! '!'")[0].is_literal("!"));
+        assert!(!words("! '!'")[1].is_literal("!"));
+    }
+
+    #[test]
+    fn test_expand_variables() {
+        assert_eq!(expand("cd $HOME/x"), vec!["cd", "/home/ilya/x"]);
+        assert_eq!(expand("echo \"${HOME}s\" '$HOME'"), vec!["echo", "/home/ilyas", "$HOME"]);
+        assert_eq!(expand("echo $LIST \"$LIST\""), vec!["echo", "a", "b", "a  b"]);
+        assert_eq!(expand("echo $MISSING x"), vec!["echo", "x"]);
+        assert_eq!(expand("echo \"\\$HOME\" $"), vec!["echo", "$HOME", "$"]);
+        assert_eq!(expand("echo \"$@\" x\"$@\"y ${10}"), vec!["echo", "a b", "c", "xa b", "cy"]);
+    }
+
+    #[test]
+    fn test_substitutions() {
+        assert_eq!(expand("echo $(ls -l) \"$(ls)\"x"), vec!["echo", "<ls", "-l>", "<ls>x"]);
+        assert_eq!(expand("echo $(echo ')' \"(\" $(pwd) (x))"), vec!["echo", "<echo", "')'", "\"(\"", "$(pwd)", "(x)>"]);
+        assert_eq!(expand("echo `ls \\`pwd\\``a \"`x y`\""), vec!["echo", "<ls", "`pwd`>a", "<x y>"]);
+        assert_eq!(expand("echo $((1 + (2 * $HOME)))"), vec!["echo", "[1", "+", "(2", "*", "/home/ilya)]"]);
+        assert_eq!(expand("echo $(( $(pwd)+\"x\" ))"), vec!["echo", "[", "<pwd>+\"x\"", "]"]);
+        assert_eq!(expand("echo \"$(( 1+1 ))\""), vec!["echo", "[ 1+1 ]"]);
+
+        for input in ["echo $(ls", "echo `ls", "echo $((1 + 2", "echo \"$(ls\""] {
+            assert!(tokenize(input).unwrap_err().incomplete, "{}", input);
+        }
+        assert!(!tokenize("echo $((1 + 2) x").unwrap_err().incomplete);
+    }
+
+    #[test]
+    fn test_assignment() {
+        let assignment = |input: &str| {
+            words(input)[0]
+                .assignment()
+                .map(|(name, value)| (name, value.expand_value(&mut lookup)))
+        };
+
+        assert_eq!(assignment("A=1"), Some(("A".to_string(), "1".to_string())));
+        assert_eq!(assignment("DIR=$HOME/*"), Some(("DIR".to_string(), "/home/ilya/*".to_string())));
+        assert_eq!(assignment("X=\"a  b\"$LIST"), Some(("X".to_string(), "a  ba  b".to_string())));
+        assert_eq!(assignment("EMPTY="), Some(("EMPTY".to_string(), String::new())));
+        assert_eq!(assignment("1A=1"), None);
+        assert_eq!(assignment("\"A\"=1"), None);
+        assert_eq!(assignment("ls"), None);
+        assert!(is_valid_name("_a1") && !is_valid_name("a-b") && !is_valid_name(""));
+    }
+
+    #[test]
+    fn test_glob_escaping() {
+        let words = words("ls *.pl '*.pl' \\*");
+        let fields: Vec<String> = words.iter().flat_map(|word| word.expand(&mut lookup)).collect();
+        assert_eq!(fields, vec!["ls", "*.pl", "\\*.pl", "\\*"]);
+        // a `\` from an unquoted expansion stays, its `*` still globs
+        let fields: Vec<String> = self::words("echo $WIN \"$WIN\"")
+            .iter()
+            .flat_map(|word| word.expand(&mut lookup))
+            .collect();
+        assert_eq!(fields, vec!["echo", "C:\\\\x", "*.pl", "C:\\\\x \\*.pl"]);
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        assert_eq!(tokenize("echo 'abc").unwrap_err().column, 6);
+        assert_eq!(tokenize("echo \"abc").unwrap_err().column, 6);
+        assert_eq!(tokenize("echo ${HOME").unwrap_err().column, 6);
+        assert_eq!(
+            tokenize("ls \\").unwrap_err().to_string(),
+            "syntax error at column 4: unexpected end of line after \\"
+        );
+        assert!(tokenize("echo ${1a}").is_err());
+
+        // more lines can finish these
+        assert!(tokenize("echo 'abc").unwrap_err().incomplete);
+        assert!(tokenize("ls \\").unwrap_err().incomplete);
+        assert!(!tokenize("ls & x").unwrap_err().incomplete);
+    }
+}
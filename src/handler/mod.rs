@@ -1 +1,9 @@
-pub mod shell;
\ No newline at end of file
+pub mod arithmetic;
+pub mod grep;
+pub mod lexer;
+pub mod options;
+pub mod parser;
+pub mod session;
+pub mod shell;
+pub mod stream;
+pub mod variables;
@@ -0,0 +1,126 @@
+use std::io::{Error, ErrorKind};
+
+/// Options of `set`, by name with `-o` or by letter like `-e`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ShellOptions {
+    // `-e`, a failing command stops the script
+    pub errexit: bool,
+    // `-u`, expanding an unset variable is an error
+    pub nounset: bool,
+    // `-x`, commands are printed before they run
+    pub xtrace: bool,
+}
+
+/// What is left of the arguments of `set` once the options are applied.
+#[derive(Debug, Default, PartialEq)]
+pub struct SetRest {
+    // `-o` alone, the options should be listed
+    pub list: bool,
+    // new `$1` and on, the words after `--` or after the options
+    pub positional: Option<Vec<String>>,
+}
+
+impl ShellOptions {
+    pub const NAMES: [&'static str; 3] = ["errexit", "nounset", "xtrace"];
+
+    /// Long name of a one letter option.
+    pub fn name_of(letter: char) -> Option<&'static str> {
+        match letter {
+            'e' => Some("errexit"),
+            'u' => Some("nounset"),
+            'x' => Some("xtrace"),
+            _ => None,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<bool> {
+        match name {
+            "errexit" => Some(self.errexit),
+            "nounset" => Some(self.nounset),
+            "xtrace" => Some(self.xtrace),
+            _ => None,
+        }
+    }
+
+    pub fn set(&mut self, name: &str, value: bool) -> Result<(), Error> {
+        match name {
+            "errexit" => self.errexit = value,
+            "nounset" => self.nounset = value,
+            "xtrace" => self.xtrace = value,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{}: invalid option name", name),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the arguments of `set`: `-e`, `+e`, `-eux`, `-o name` and
+    /// `+o name`, up to `--` or the first word that is not an option.
+    pub fn apply(&mut self, args: &[String]) -> Result<SetRest, Error> {
+        let mut args = args.iter();
+        let mut rest = SetRest::default();
+
+        while let Some(arg) = args.next() {
+            if arg == "--" || !arg.starts_with(['-', '+']) {
+                let first = (arg != "--").then_some(arg);
+                rest.positional = Some(first.into_iter().chain(args).cloned().collect());
+                break;
+            }
+
+            let value = arg.starts_with('-');
+            let letters = match arg.strip_prefix('-').or_else(|| arg.strip_prefix('+')) {
+                Some(letters) if !letters.is_empty() => letters,
+                _ => return Err(Error::new(ErrorKind::InvalidInput, format!("{}: invalid option", arg))),
+            };
+
+            if letters == "o" {
+                match args.next() {
+                    Some(name) => self.set(name, value)?,
+                    None => rest.list = true,
+                }
+                continue;
+            }
+            for letter in letters.chars() {
+                let name = ShellOptions::name_of(letter)
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("-{}: invalid option", letter)))?;
+                self.set(name, value)?;
+            }
+        }
+
+        Ok(rest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut options = ShellOptions::default();
+        assert_eq!(options.apply(&args("-eu")).unwrap(), SetRest::default());
+        assert!(options.errexit && options.nounset && !options.xtrace);
+
+        options.apply(&args("+e -o xtrace")).unwrap();
+        assert!(!options.errexit && options.xtrace);
+        options.apply(&args("+o nounset")).unwrap();
+        assert_eq!(options.get("nounset"), Some(false));
+
+        assert!(options.apply(&args("-o")).unwrap().list);
+        assert!(options.apply(&args("-q")).is_err());
+        assert!(options.apply(&args("-o nothing")).is_err());
+        assert!(options.apply(&args("-")).is_err());
+
+        assert_eq!(options.apply(&args("--")).unwrap().positional, Some(vec![]));
+        assert_eq!(options.apply(&args("+x -- -a b")).unwrap().positional, Some(args("-a b")));
+        assert_eq!(options.apply(&args("-e a -u")).unwrap().positional, Some(args("a -u")));
+        assert!(options.errexit && !options.xtrace);
+    }
+}
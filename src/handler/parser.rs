@@ -0,0 +1,776 @@
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use crate::handler::lexer::{is_valid_name, tokenize, Expansion, Operator, RedirectKind, SyntaxError, Token, Word, WordPart};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redirect {
+    pub fd: Option<u32>,
+    pub kind: RedirectKind,
+    pub target: Word,
+}
+
+/// Words of one command and its redirections, both still unexpanded.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SimpleCommand {
+    pub words: Vec<Word>,
+    pub redirects: Vec<Redirect>,
+    // line of the input the command starts on, for diagnostics
+    pub line: usize,
+}
+
+impl SimpleCommand {
+    fn is_empty(&self) -> bool {
+        self.words.is_empty() && self.redirects.is_empty()
+    }
+}
+
+/// One pattern alternative list of `case` and what runs when it matches.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaseArm {
+    pub patterns: Vec<Word>,
+    pub body: List,
+}
+
+/// A command of a pipeline, compound commands hold lists of their own.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Simple(SimpleCommand),
+    // conditions of `if` and `elif` with their bodies, the first success runs
+    If {
+        branches: Vec<(List, List)>,
+        otherwise: Option<List>,
+    },
+    // `while`, or `until` which loops as long as the condition fails
+    Loop {
+        until: bool,
+        condition: List,
+        body: List,
+    },
+    For {
+        variable: String,
+        words: Vec<Word>,
+        body: List,
+    },
+    Case {
+        word: Word,
+        arms: Vec<CaseArm>,
+    },
+    // `{ ...; }`
+    Group(List),
+    // `name() body`, running it only defines the function
+    Function {
+        name: String,
+        body: Rc<Command>,
+    },
+}
+
+/// Commands connected with `|`, `!` in front inverts the exit status.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Pipeline {
+    pub negated: bool,
+    // 1-based line of the input the pipeline starts on
+    pub line: usize,
+    pub commands: Vec<Command>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Connector {
+    // `&&` runs the next pipeline after a success, `||` after a failure
+    And,
+    Or,
+}
+
+/// Pipelines chained with `&&` and `||`, evaluated left to right.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+}
+
+/// And-or lists separated by `;` or newlines, a whole line or script or the
+/// body of a compound command.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct List {
+    pub items: Vec<AndOr>,
+}
+
+/// Reserved words that close a list, only recognised where a command starts.
+const TERMINATORS: [&str; 8] = ["then", "elif", "else", "fi", "do", "done", "esac", "}"];
+
+/// Reserved words that open a compound command.
+const COMPOUND_STARTS: [&str; 6] = ["if", "while", "until", "for", "case", "{"];
+
+fn unexpected(operator: Operator, column: usize) -> SyntaxError {
+    SyntaxError {
+        column,
+        message: format!("unexpected token `{}'", operator),
+        incomplete: false,
+        line: 1,
+    }
+}
+
+fn end_of_line(operator: Operator, column: usize) -> SyntaxError {
+    SyntaxError {
+        column,
+        message: format!("unexpected end of line after {}", operator),
+        incomplete: true,
+        line: 1,
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    // column just past the input, for errors at the end of the line
+    end: usize,
+    // columns of the newlines in the input
+    newlines: Vec<usize>,
+    aliases: &'a BTreeMap<String, String>,
+    // aliases being expanded and the token index their text ends at, an
+    // alias is not expanded again inside its own text
+    expanding: Vec<(String, usize)>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_operator(&self) -> Option<(Operator, usize)> {
+        match self.peek() {
+            Some(Token::Operator(operator, column)) => Some((*operator, *column)),
+            _ => None,
+        }
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(word, _)) if word.is_literal(keyword))
+    }
+
+    fn skip_newlines(&mut self) {
+        while let Some((Operator::Newline, _)) = self.peek_operator() {
+            self.pos += 1;
+        }
+    }
+
+    /// Error for the current token, running out of input can still be
+    /// finished by more lines.
+    fn unexpected_here(&self, expected: Option<&str>) -> SyntaxError {
+        match self.peek() {
+            Some(Token::Operator(operator, column)) => unexpected(*operator, *column),
+            Some(Token::Word(word, column)) => SyntaxError {
+                column: *column,
+                message: format!("unexpected token `{}'", word.expand_value(&mut |_| None)),
+                incomplete: false,
+                line: 1,
+            },
+            None => SyntaxError {
+                column: self.end,
+                message: match expected {
+                    Some(keyword) => format!("unexpected end of file, expected `{}'", keyword),
+                    None => "unexpected end of file".to_string(),
+                },
+                incomplete: true,
+                line: 1,
+            },
+        }
+    }
+
+    fn expect(&mut self, keyword: &str) -> Result<(), SyntaxError> {
+        if !self.peek_keyword(keyword) {
+            return Err(self.unexpected_here(Some(keyword)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn expect_operator(&mut self, operator: Operator) -> Result<(), SyntaxError> {
+        if !matches!(self.peek_operator(), Some((next, _)) if next == operator) {
+            return Err(self.unexpected_here(Some(&operator.to_string())));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn column(&self) -> usize {
+        match self.peek() {
+            Some(Token::Word(_, column) | Token::Operator(_, column)) => *column,
+            None => self.end,
+        }
+    }
+
+    fn line(&self, column: usize) -> usize {
+        1 + self.newlines.iter().filter(|newline| **newline < column).count()
+    }
+
+    fn word(&mut self) -> Result<Word, SyntaxError> {
+        match self.peek().cloned() {
+            Some(Token::Word(word, _)) => {
+                self.pos += 1;
+                Ok(word)
+            }
+            _ => Err(self.unexpected_here(None)),
+        }
+    }
+
+    fn at_list_end(&self) -> bool {
+        match self.peek() {
+            None => true,
+            Some(Token::Word(word, _)) => TERMINATORS.iter().any(|keyword| word.is_literal(keyword)),
+            Some(Token::Operator(operator, _)) => matches!(operator, Operator::CaseEnd | Operator::RightParen),
+        }
+    }
+
+    fn list(&mut self) -> Result<List, SyntaxError> {
+        let mut list = List::default();
+
+        loop {
+            self.skip_newlines();
+            if self.at_list_end() {
+                break;
+            }
+            list.items.push(self.and_or()?);
+            match self.peek_operator() {
+                Some((Operator::Semicolon | Operator::Newline, _)) => self.pos += 1,
+                Some((Operator::CaseEnd | Operator::RightParen, _)) => break,
+                Some((operator, column)) => return Err(unexpected(operator, column)),
+                None if self.at_list_end() => break,
+                None => return Err(self.unexpected_here(None)),
+            }
+        }
+
+        Ok(list)
+    }
+
+    /// The list of a compound command, which can't be empty.
+    fn compound_list(&mut self) -> Result<List, SyntaxError> {
+        let list = self.list()?;
+        if list.items.is_empty() {
+            return Err(self.unexpected_here(None));
+        }
+        Ok(list)
+    }
+
+    fn and_or(&mut self) -> Result<AndOr, SyntaxError> {
+        let first = self.pipeline()?;
+        let mut rest = vec![];
+
+        while let Some((operator @ (Operator::And | Operator::Or), column)) = self.peek_operator() {
+            self.pos += 1;
+            self.skip_newlines();
+            if self.peek().is_none() {
+                return Err(end_of_line(operator, column));
+            }
+            let connector = if operator == Operator::And { Connector::And } else { Connector::Or };
+            rest.push((connector, self.pipeline()?));
+        }
+
+        Ok(AndOr { first, rest })
+    }
+
+    fn pipeline(&mut self) -> Result<Pipeline, SyntaxError> {
+        let mut pipeline = Pipeline {
+            line: self.line(self.column()),
+            ..Pipeline::default()
+        };
+        if self.peek_keyword("!") {
+            pipeline.negated = true;
+            self.pos += 1;
+        }
+
+        pipeline.commands.push(self.command()?);
+        while let Some((Operator::Pipe, column)) = self.peek_operator() {
+            self.pos += 1;
+            self.skip_newlines();
+            if self.peek().is_none() {
+                return Err(end_of_line(Operator::Pipe, column));
+            }
+            pipeline.commands.push(self.command()?);
+        }
+
+        Ok(pipeline)
+    }
+
+    /// Replaces an alias where a command starts with the tokens of its text.
+    fn expand_alias(&mut self) -> Result<(), SyntaxError> {
+        loop {
+            let pos = self.pos;
+            let Some(Token::Word(word, column)) = self.peek() else {
+                return Ok(());
+            };
+            let column = *column;
+            let Some(name) = word.as_literal() else {
+                return Ok(());
+            };
+            let Some(text) = self.aliases.get(name) else {
+                return Ok(());
+            };
+            if self.expanding.iter().any(|(active, end)| active == name && *end > pos) {
+                return Ok(());
+            }
+
+            let name = name.to_string();
+            // errors inside the alias text point at where the alias was used
+            let tokens: Vec<Token> = tokenize(text)
+                .map_err(|err| SyntaxError { column, ..err })?
+                .into_iter()
+                .map(|token| match token {
+                    Token::Word(word, _) => Token::Word(word, column),
+                    Token::Operator(operator, _) => Token::Operator(operator, column),
+                })
+                .collect();
+
+            let count = tokens.len();
+            for (_, end) in self.expanding.iter_mut().filter(|(_, end)| *end > pos) {
+                *end = *end + count - 1;
+            }
+            self.expanding.push((name, pos + count));
+            self.tokens.splice(pos..pos + 1, tokens);
+        }
+    }
+
+    fn command(&mut self) -> Result<Command, SyntaxError> {
+        self.expand_alias()?;
+        let keyword = match self.peek() {
+            Some(Token::Word(word, _)) => word.as_literal().unwrap_or_default().to_string(),
+            _ => String::new(),
+        };
+        match keyword.as_str() {
+            "if" => self.if_command(),
+            "while" | "until" => self.loop_command(),
+            "for" => self.for_command(),
+            "case" => self.case_command(),
+            "{" => self.group(),
+            "function" => {
+                self.pos += 1;
+                let column = self.column();
+                let name = self.word()?;
+                self.function(name, column)
+            }
+            _ => match (self.tokens.get(self.pos + 1), self.tokens.get(self.pos + 2)) {
+                (
+                    Some(Token::Operator(Operator::LeftParen, _)),
+                    Some(Token::Operator(Operator::RightParen, _)),
+                ) => {
+                    let column = self.column();
+                    let name = self.word()?;
+                    self.function(name, column)
+                }
+                _ => Ok(Command::Simple(self.simple_command()?)),
+            },
+        }
+    }
+
+    fn group(&mut self) -> Result<Command, SyntaxError> {
+        self.pos += 1;
+        let list = self.compound_list()?;
+        self.expect("}")?;
+        Ok(Command::Group(list))
+    }
+
+    /// The rest of `name() body` or `function name body`, the name is
+    /// already read. The body has to be a compound command.
+    fn function(&mut self, name: Word, column: usize) -> Result<Command, SyntaxError> {
+        let Some(name) = name.as_literal().filter(|name| !name.contains('=')).map(str::to_string) else {
+            return Err(SyntaxError {
+                column,
+                message: format!("`{}': not a valid function name", name.expand_value(&mut |_| None)),
+                incomplete: false,
+                line: 1,
+            });
+        };
+        if let Some((Operator::LeftParen, _)) = self.peek_operator() {
+            self.pos += 1;
+            self.expect_operator(Operator::RightParen)?;
+        }
+
+        self.skip_newlines();
+        if !COMPOUND_STARTS.iter().any(|keyword| self.peek_keyword(keyword)) {
+            return Err(self.unexpected_here(Some("{")));
+        }
+        let body = Rc::new(self.command()?);
+        Ok(Command::Function { name, body })
+    }
+
+    fn if_command(&mut self) -> Result<Command, SyntaxError> {
+        self.pos += 1;
+        let mut branches = vec![];
+        let mut otherwise = None;
+
+        loop {
+            let condition = self.compound_list()?;
+            self.expect("then")?;
+            branches.push((condition, self.compound_list()?));
+
+            if self.peek_keyword("elif") {
+                self.pos += 1;
+                continue;
+            }
+            if self.peek_keyword("else") {
+                self.pos += 1;
+                otherwise = Some(self.compound_list()?);
+            }
+            self.expect("fi")?;
+            return Ok(Command::If { branches, otherwise });
+        }
+    }
+
+    fn loop_command(&mut self) -> Result<Command, SyntaxError> {
+        let until = self.peek_keyword("until");
+        self.pos += 1;
+
+        let condition = self.compound_list()?;
+        self.expect("do")?;
+        let body = self.compound_list()?;
+        self.expect("done")?;
+        Ok(Command::Loop { until, condition, body })
+    }
+
+    fn for_command(&mut self) -> Result<Command, SyntaxError> {
+        self.pos += 1;
+        let column = self.column();
+        let name = self.word()?;
+        let variable = match name.as_literal() {
+            Some(variable) if is_valid_name(variable) => variable.to_string(),
+            _ => {
+                return Err(SyntaxError {
+                    column,
+                    message: format!("`{}': not a valid identifier", name.expand_value(&mut |_| None)),
+                    incomplete: false,
+                    line: 1,
+                })
+            }
+        };
+
+        self.skip_newlines();
+        let words = if self.peek_keyword("in") {
+            self.pos += 1;
+            let mut words = vec![];
+            while let Some(Token::Word(word, _)) = self.peek().cloned() {
+                words.push(word);
+                self.pos += 1;
+            }
+            match self.peek_operator() {
+                Some((Operator::Semicolon | Operator::Newline, _)) => self.pos += 1,
+                _ => return Err(self.unexpected_here(Some("do"))),
+            }
+            words
+        } else {
+            if let Some((Operator::Semicolon, _)) = self.peek_operator() {
+                self.pos += 1;
+            }
+            // without `in` the loop goes over the positional parameters
+            vec![Word {
+                parts: vec![WordPart::Expansion {
+                    expansion: Expansion::Variable("@".to_string()),
+                    quoted: true,
+                }],
+            }]
+        };
+
+        self.skip_newlines();
+        self.expect("do")?;
+        let body = self.compound_list()?;
+        self.expect("done")?;
+        Ok(Command::For { variable, words, body })
+    }
+
+    fn case_command(&mut self) -> Result<Command, SyntaxError> {
+        self.pos += 1;
+        let word = self.word()?;
+        self.skip_newlines();
+        self.expect("in")?;
+        let mut arms = vec![];
+
+        loop {
+            self.skip_newlines();
+            if self.peek_keyword("esac") {
+                self.pos += 1;
+                return Ok(Command::Case { word, arms });
+            }
+
+            if let Some((Operator::LeftParen, _)) = self.peek_operator() {
+                self.pos += 1;
+            }
+            let mut patterns = vec![self.word()?];
+            while let Some((Operator::Pipe, _)) = self.peek_operator() {
+                self.pos += 1;
+                patterns.push(self.word()?);
+            }
+            self.expect_operator(Operator::RightParen)?;
+
+            arms.push(CaseArm {
+                patterns,
+                body: self.list()?,
+            });
+            match self.peek_operator() {
+                Some((Operator::CaseEnd, _)) => self.pos += 1,
+                _ => {
+                    self.expect("esac")?;
+                    return Ok(Command::Case { word, arms });
+                }
+            }
+        }
+    }
+
+    fn simple_command(&mut self) -> Result<SimpleCommand, SyntaxError> {
+        let mut command = SimpleCommand {
+            line: self.line(self.column()),
+            ..SimpleCommand::default()
+        };
+
+        loop {
+            match self.peek().cloned() {
+                Some(Token::Word(word, _)) => {
+                    // a reserved word out of place where the command starts
+                    if command.is_empty() && TERMINATORS.iter().any(|keyword| word.is_literal(keyword)) {
+                        return Err(self.unexpected_here(None));
+                    }
+                    command.words.push(word);
+                    self.pos += 1;
+                }
+                Some(Token::Operator(Operator::Redirect(fd, kind), column)) => {
+                    self.pos += 1;
+                    match self.peek().cloned() {
+                        Some(Token::Word(target, _)) => {
+                            command.redirects.push(Redirect { fd, kind, target });
+                            self.pos += 1;
+                        }
+                        Some(Token::Operator(next, column)) => return Err(unexpected(next, column)),
+                        None => {
+                            return Err(SyntaxError {
+                                incomplete: false,
+                                ..end_of_line(Operator::Redirect(fd, kind), column)
+                            })
+                        }
+                    }
+                }
+                _ => break,
+            }
+        }
+
+        if command.is_empty() {
+            return Err(match self.peek_operator() {
+                Some((operator, column)) => unexpected(operator, column),
+                None => SyntaxError {
+                    column: self.end,
+                    message: "unexpected end of line".to_string(),
+                    incomplete: false,
+                    line: 1,
+                },
+            });
+        }
+        Ok(command)
+    }
+}
+
+/// Parses a command line or a whole script, an empty one gives an empty list.
+/// Aliases are expanded on the way. Errors with `incomplete` set mean more
+/// lines could still make it valid.
+pub fn parse(input: &str, aliases: &BTreeMap<String, String>) -> Result<List, SyntaxError> {
+    let parse_list = || {
+        let mut parser = Parser {
+            tokens: tokenize(input)?,
+            pos: 0,
+            end: input.chars().count() + 1,
+            newlines: input
+                .chars()
+                .enumerate()
+                .filter(|(_, c)| *c == '\n')
+                .map(|(index, _)| index + 1)
+                .collect(),
+            aliases,
+            expanding: vec![],
+        };
+        let list = parser.list()?;
+        if parser.peek().is_some() {
+            return Err(parser.unexpected_here(None));
+        }
+        Ok(list)
+    };
+    parse_list().map_err(|err| err.locate(input))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> Result<List, SyntaxError> {
+        super::parse(input, &BTreeMap::new())
+    }
+
+    fn pipeline(input: &str) -> Pipeline {
+        parse(input).unwrap().items.remove(0).first
+    }
+
+    fn simple(command: &Command) -> &SimpleCommand {
+        match command {
+            Command::Simple(simple) => simple,
+            other => panic!("not a simple command: {:?}", other),
+        }
+    }
+
+    fn command(input: &str) -> Command {
+        pipeline(input).commands.remove(0)
+    }
+
+    #[test]
+    fn test_parse_pipeline() {
+        assert!(parse("  # nothing").unwrap().items.is_empty());
+        assert_eq!(pipeline("ls").commands.len(), 1);
+
+        let lengths: Vec<usize> = pipeline("ls / | grep pl | grep -v home")
+            .commands
+            .iter()
+            .map(|command| simple(command).words.len())
+            .collect();
+        assert_eq!(lengths, vec![2, 2, 3]);
+
+        assert_eq!(
+            parse("| ls").unwrap_err().to_string(),
+            "syntax error at column 1: unexpected token `|'"
+        );
+        assert_eq!(parse("ls | | grep").unwrap_err().column, 6);
+        assert_eq!(
+            parse("ls |").unwrap_err().to_string(),
+            "syntax error at column 4: unexpected end of line after |"
+        );
+    }
+
+    #[test]
+    fn test_parse_redirects() {
+        let pipeline = pipeline("cat < in 2>&1 | grep x >> out");
+        let first = simple(&pipeline.commands[0]);
+        assert_eq!(first.words.len(), 1);
+        assert_eq!(first.redirects[0].kind, RedirectKind::Read);
+        assert_eq!(first.redirects[1].fd, Some(2));
+        assert_eq!(first.redirects[1].kind, RedirectKind::Duplicate);
+        assert_eq!(simple(&pipeline.commands[1]).redirects[0].kind, RedirectKind::Append);
+
+        // only redirections is still a command, it creates the file
+        assert_eq!(simple(&command("> out")).redirects.len(), 1);
+        assert_eq!(
+            parse("ls >").unwrap_err().to_string(),
+            "syntax error at column 4: unexpected end of line after >"
+        );
+        assert_eq!(parse("ls > | x").unwrap_err().column, 6);
+    }
+
+    #[test]
+    fn test_parse_lists() {
+        let list = parse("cd missing || echo fallback; ! ls && pwd;").unwrap();
+        assert_eq!(list.items.len(), 2);
+        assert_eq!(list.items[0].rest[0].0, Connector::Or);
+        assert!(list.items[1].first.negated);
+        assert_eq!(simple(&list.items[1].first.commands[0]).words.len(), 1);
+        assert_eq!(list.items[1].rest[0].0, Connector::And);
+
+        assert!(!pipeline("'!' x").negated);
+        assert_eq!(parse("; ls").unwrap_err().column, 1);
+        assert_eq!(parse("ls && ; pwd").unwrap_err().to_string(), "syntax error at column 7: unexpected token `;'");
+        assert_eq!(
+            parse("ls ||").unwrap_err().to_string(),
+            "syntax error at column 4: unexpected end of line after ||"
+        );
+        assert_eq!(parse("! ").unwrap_err().to_string(), "syntax error at column 3: unexpected end of line");
+    }
+
+    #[test]
+    fn test_parse_compound() {
+        let Command::If { branches, otherwise } = command("if a; then b; elif c\nthen d; e\nelse f; fi") else {
+            panic!("not an if");
+        };
+        assert_eq!(branches.len(), 2);
+        assert_eq!(branches[1].1.items.len(), 2);
+        assert_eq!(otherwise.unwrap().items.len(), 1);
+
+        let Command::Loop { until, condition, body } = command("until a\ndo\n  b | c\n  d\ndone") else {
+            panic!("not a loop");
+        };
+        assert!(until);
+        assert_eq!(condition.items.len(), 1);
+        assert_eq!(body.items[0].first.commands.len(), 2);
+
+        let Command::For { variable, words, body } = command("for x in a 'b c' *; do echo $x; done") else {
+            panic!("not a for");
+        };
+        assert_eq!(variable, "x");
+        assert_eq!(words.len(), 3);
+        assert_eq!(body.items.len(), 1);
+        assert!(matches!(command("for x\ndo y; done"), Command::For { words, .. } if words.len() == 1));
+
+        let Command::Case { arms, .. } = command("case $1 in\n  a|b) x;;\n  (*) y; z\nesac") else {
+            panic!("not a case");
+        };
+        assert_eq!(arms[0].patterns.len(), 2);
+        assert_eq!(arms[1].body.items.len(), 2);
+        assert!(matches!(command("case x in esac"), Command::Case { arms, .. } if arms.is_empty()));
+
+        // reserved words only count where a command starts
+        assert_eq!(simple(&command("echo if done")).words.len(), 3);
+        let list = parse("while a; do if b; then break; fi; done | grep x; echo done").unwrap();
+        assert_eq!(list.items.len(), 2);
+        assert_eq!(list.items[0].first.commands.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_incomplete() {
+        for input in ["if a; then b", "while a", "for x in a", "case x in a) b;;", "ls |", "ls &&\n", "echo 'a"] {
+            assert!(parse(input).unwrap_err().incomplete, "{}", input);
+        }
+        assert_eq!(
+            parse("if a; then b").unwrap_err().to_string(),
+            "syntax error at column 13: unexpected end of file, expected `fi'"
+        );
+
+        for input in ["fi", "if a; fi", "if; then b; fi", "while a; done", "for 1 in a; do b; done", "ls >", "ls )", "if a; then b; fi x"] {
+            assert!(!parse(input).unwrap_err().incomplete, "{}", input);
+        }
+        assert_eq!(parse("if a; fi").unwrap_err().to_string(), "syntax error at column 7: unexpected token `fi'");
+        assert_eq!(parse("echo a\ndone").unwrap_err().to_string(), "syntax error at line 2, column 1: unexpected token `done'");
+        let list = parse("echo a\nif b\nthen\n  c\nfi").unwrap();
+        let Command::If { branches, .. } = &list.items[1].first.commands[0] else {
+            panic!("not an if");
+        };
+        assert_eq!(simple(&list.items[0].first.commands[0]).line, 1);
+        assert_eq!(simple(&branches[0].1.items[0].first.commands[0]).line, 4);
+    }
+
+    #[test]
+    fn test_parse_functions() {
+        let Command::Function { name, body } = command("greet() {\n  echo hi $1\n  return 3\n}") else {
+            panic!("not a function");
+        };
+        assert_eq!(name, "greet");
+        assert!(matches!(body.as_ref(), Command::Group(list) if list.items.len() == 2));
+        assert!(matches!(command("function f if a; then b; fi"), Command::Function { name, .. } if name == "f"));
+        assert!(matches!(command("{ a; b | c; }"), Command::Group(list) if list.items.len() == 2));
+        assert_eq!(simple(&command("echo { }")).words.len(), 3);
+
+        assert!(parse("f() {").unwrap_err().incomplete);
+        assert!(parse("{ echo }").unwrap_err().incomplete);
+        assert_eq!(parse("f() echo").unwrap_err().to_string(), "syntax error at column 5: unexpected token `echo'");
+        assert!(parse("}").is_err());
+    }
+
+    #[test]
+    fn test_aliases() {
+        let aliases: BTreeMap<String, String> = [("ll", "ls -l"), ("loop", "loop x"), ("a", "b"), ("b", "a | c")]
+            .into_iter()
+            .map(|(name, text)| (name.to_string(), text.to_string()))
+            .collect();
+        let words = |input: &str| -> Vec<usize> {
+            let list = super::parse(input, &aliases).unwrap();
+            list.items[0].first.commands.iter().map(|command| simple(command).words.len()).collect()
+        };
+
+        assert_eq!(words("ll /test"), vec![3]);
+        // only where a command starts, and not again inside its own text
+        assert_eq!(words("echo ll"), vec![2]);
+        assert_eq!(words("loop y"), vec![3]);
+        assert_eq!(words("a x"), vec![1, 2]);
+        assert_eq!(words("'ll'"), vec![1]);
+        assert_eq!(super::parse("true; ll | ll", &aliases).unwrap().items[1].first.commands.len(), 2);
+    }
+}
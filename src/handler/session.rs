@@ -0,0 +1,2414 @@
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+use std::process;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::handler::arithmetic::{evaluate, Scope};
+use crate::handler::grep::GrepQuery;
+use crate::handler::lexer::{is_valid_name, Expansion, RedirectKind, SyntaxError, Word};
+use crate::handler::options::ShellOptions;
+use crate::handler::parser::{parse, AndOr, Command, Connector, List, Pipeline, Redirect};
+use crate::handler::shell::{Commands, SystemCall};
+use crate::handler::stream::{CommandIo, Stream, Target};
+use crate::handler::variables::{quote, Variable, Variables};
+use crate::vfs::find::FindQuery;
+use crate::vfs::glob::GlobOptions;
+use crate::vfs::pattern::{glob_match, unescape_glob};
+use crate::vfs::permissions::mode_string;
+use crate::vfs::storage::VFS;
+use crate::vfs::time::format_time;
+use crate::vfs::tree::{VFSNode, VFSNodeKind};
+use crate::vfs::tree_view::TreeOptions;
+use crate::window::polina_vfs::SHELL_USER;
+
+const HISTORY_SIZE: usize = 30;
+
+/// Functions and sourced scripts nested deeper than this fail, a runaway
+/// recursion ends with an error instead of taking the window down.
+const FUNCTION_DEPTH: usize = 64;
+
+/// Variables the shell keeps up to date from its own state, exported like in
+/// a login shell.
+const STATE_VARIABLES: [&str; 4] = ["HOME", "OLDPWD", "PWD", "USER"];
+
+fn storage_not_set() -> Error {
+    Error::new(ErrorKind::NotFound, "VFS storage not set")
+}
+
+fn bad_descriptor(fd: u32) -> Error {
+    Error::new(ErrorKind::InvalidInput, format!("{}: bad file descriptor", fd))
+}
+
+fn random_seed() -> u32 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.subsec_nanos())
+        .unwrap_or_default();
+    // xorshift gets stuck on zero
+    (nanos ^ process::id()) | 1
+}
+
+/// An alias the way `alias` prints it, always single-quoted like bash does.
+fn alias_definition(name: &str, text: &str) -> String {
+    format!("alias {}='{}'\n", name, text.replace('\'', "'\\''"))
+}
+
+/// A syntax error in the script `name`, at `line` of it.
+fn script_syntax_error(name: &str, line: usize, err: &SyntaxError) -> String {
+    format!("{}:{}: syntax error at column {}: {}\n", name, line, err.column, err.message)
+}
+
+/// Where output to `fd` currently goes, only stdout and stderr can be redirected.
+fn stream_target(io: &mut CommandIo, fd: u32) -> Result<&mut Target, Error> {
+    match fd {
+        1 => Ok(&mut io.stdout),
+        2 => Ok(&mut io.stderr),
+        _ => Err(bad_descriptor(fd)),
+    }
+}
+
+/// What stops a list before its end.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Flow {
+    Normal,
+    // `break N` and `continue N` with the number of loops still to leave
+    Break(u32),
+    Continue(u32),
+    // `return` from the innermost function
+    Return,
+    Exit,
+    // a command failed under `set -e`
+    ErrExit,
+}
+
+/// A script being run line by line, its errors are reported with the
+/// script name and line.
+#[derive(Debug)]
+struct Script {
+    name: String,
+    // line the script is at, and the one the command being run started on
+    line: usize,
+    start: usize,
+    errors: usize,
+    // `set -e` stopped the script at this line
+    stopped: Option<usize>,
+    // `$0` and `$1` and on from before the script
+    program: String,
+    positional: Vec<String>,
+}
+
+/// Who was logged in before `su`, `exit` goes back to it.
+#[derive(Debug, Clone)]
+struct Login {
+    user: String,
+    path: String,
+    umask: u16,
+}
+
+/// Shell state that outlives a single command line: the VFS, who is logged
+/// in, the variables, aliases and functions, the history and the shell options.
+pub struct Session {
+    pub vfs: Option<VFS>,
+    pub user: String,
+    // the logins `su` left, innermost last
+    logins: Vec<Login>,
+    pub variables: Variables,
+    pub aliases: BTreeMap<String, String>,
+    functions: BTreeMap<String, Rc<Command>>,
+    // `$0`, the shell or the script it runs
+    program: String,
+    // `$1` and on
+    positional: Vec<String>,
+    // where `getopts` stopped: the `OPTIND` it left and the letter of a
+    // group like `-ab` it goes on from
+    getopts_position: (usize, usize),
+    // one per running function, what its `local` variables hid
+    frames: Vec<Vec<(String, Option<Variable>)>>,
+    // scripts run by `source` around the running command
+    sourcing: usize,
+    pub history: Vec<String>,
+    pub glob_options: GlobOptions,
+    pub options: ShellOptions,
+    old_pwd: Option<String>,
+    // `$?`
+    last_status: i32,
+    // xorshift state behind `$RANDOM`
+    random_state: Cell<u32>,
+    // lines of a command that is not finished yet
+    pending: Option<String>,
+    flow: Flow,
+    // loops around the running command, `break` outside of one is an error
+    loop_depth: u32,
+    // what the current line has for the terminal so far
+    terminal: Vec<SystemCall>,
+    // the first error while expanding the words of a command
+    expansion_error: Option<String>,
+    // status of the last `$(...)`, a command of only assignments returns it
+    substitution_status: Option<i32>,
+    // conditions of `if`, `while` and `until` being run, `set -e` ignores
+    // failures in them
+    condition_depth: u32,
+    // line of the running command within the input, and of the call while
+    // a function runs, its body counts lines from where it was defined
+    line: usize,
+    call_line: Option<usize>,
+    script: Option<Script>,
+}
+
+impl Session {
+    pub fn new(vfs: Option<VFS>) -> Self {
+        let user = match vfs {
+            Some(ref vfs) => vfs.user.clone(),
+            None => "".to_string(),
+        };
+
+        Session {
+            vfs,
+            user,
+            logins: vec![],
+            variables: Variables::default(),
+            aliases: BTreeMap::new(),
+            functions: BTreeMap::new(),
+            program: env!("CARGO_PKG_NAME").to_string(),
+            positional: vec![],
+            getopts_position: (1, 1),
+            frames: vec![],
+            sourcing: 0,
+            history: vec![],
+            glob_options: GlobOptions::default(),
+            options: ShellOptions::default(),
+            old_pwd: None,
+            last_status: 0,
+            random_state: Cell::new(random_seed()),
+            pending: None,
+            flow: Flow::Normal,
+            loop_depth: 0,
+            terminal: vec![],
+            expansion_error: None,
+            substitution_status: None,
+            condition_depth: 0,
+            line: 1,
+            call_line: None,
+            script: None,
+        }
+    }
+
+    /// Runs a command line and returns what the terminal has to show. A line
+    /// that leaves an `if`, a loop or a quote open is kept until the lines
+    /// after it finish the command, see [`Session::is_continuing`].
+    pub fn run_line(&mut self, line: &str) -> Vec<SystemCall> {
+        let line = line.trim_end_matches('\n');
+        let input = match self.pending.take() {
+            Some(pending) => format!("{}\n{}", pending, line),
+            None => {
+                if let Some(script) = self.script.as_mut() {
+                    script.start = script.line;
+                }
+                line.to_string()
+            }
+        };
+
+        let parsed = parse(&input, &self.aliases);
+        if matches!(parsed, Err(ref err) if err.incomplete) {
+            self.pending = Some(input);
+            return vec![SystemCall::DisplayNewLine];
+        }
+
+        self.history.push(input);
+        if self.history.len() > HISTORY_SIZE {
+            self.history.remove(0);
+        }
+
+        self.flow = Flow::Normal;
+        self.terminal = vec![SystemCall::DisplayNewLine];
+        match parsed {
+            Ok(list) => {
+                self.run_list(&list, None);
+                if self.flow == Flow::ErrExit {
+                    let line = self.script_line();
+                    if let Some(script) = self.script.as_mut() {
+                        script.stopped = Some(line);
+                    }
+                }
+            }
+            Err(err) => {
+                self.last_status = 2;
+                let text = self.syntax_error(&err);
+                self.terminal.push(SystemCall::Display(text));
+            }
+        }
+
+        std::mem::take(&mut self.terminal)
+    }
+
+    /// Starts running the script `name` with `args` as `$1` and on, errors
+    /// from now on are reported as `name:LINE: error` and counted for
+    /// [`Session::end_script`].
+    pub fn begin_script(&mut self, name: &str, args: Vec<String>) {
+        self.script = Some(Script {
+            name: name.to_string(),
+            line: 1,
+            start: 1,
+            errors: 0,
+            stopped: None,
+            program: std::mem::replace(&mut self.program, name.to_string()),
+            positional: std::mem::replace(&mut self.positional, args),
+        });
+    }
+
+    /// The 1-based line of the script the next [`Session::run_line`] gets.
+    pub fn set_script_line(&mut self, line: usize) {
+        if let Some(script) = self.script.as_mut() {
+            script.line = line;
+        }
+    }
+
+    /// Whether `set -e` stopped the script, the rest of it should not run.
+    pub fn is_script_stopped(&self) -> bool {
+        self.script.as_ref().is_some_and(|script| script.stopped.is_some())
+    }
+
+    /// Ends the script, an unfinished command at its end is an error too.
+    /// Returns those errors and a summary of how the script went.
+    pub fn end_script(&mut self) -> Vec<SystemCall> {
+        let mut terminal = self.end_of_input();
+        let Some(script) = self.script.take() else {
+            return terminal;
+        };
+        self.program = script.program;
+        self.positional = script.positional;
+
+        let summary = match (script.stopped, script.errors) {
+            (Some(line), _) => format!("{}: startapp stopped at line {} by set -e", script.name, line),
+            (None, 0) => format!("{}: startapp succeeded", script.name),
+            (None, 1) => format!("{}: startapp failed: 1 error", script.name),
+            (None, errors) => format!("{}: startapp failed: {} errors", script.name, errors),
+        };
+        if terminal.is_empty() {
+            terminal.push(SystemCall::DisplayNewLine);
+        }
+        terminal.push(SystemCall::Display(format!("{}\n", summary)));
+        terminal
+    }
+
+    /// A syntax error the way the terminal shows it, with the script line
+    /// in front while a script runs.
+    fn syntax_error(&mut self, err: &SyntaxError) -> String {
+        match self.script.as_mut() {
+            Some(script) => {
+                script.errors += 1;
+                script_syntax_error(&script.name, script.start + err.line - 1, err)
+            }
+            None => format!("{}\n", err),
+        }
+    }
+
+    /// Line of the script the running command is on, inside a function the
+    /// line it was called from.
+    fn script_line(&self) -> usize {
+        let start = self.script.as_ref().map_or(1, |script| script.start);
+        start + self.call_line.unwrap_or(self.line) - 1
+    }
+
+    /// Puts an error onto the terminal, prefixed with the script name and
+    /// the line of the running command while a script runs.
+    fn report(&mut self, text: String) {
+        let line = self.script_line();
+        let text = match self.script.as_mut() {
+            Some(script) => {
+                script.errors += 1;
+                let prefix = format!("{}:{}: ", script.name, line);
+                text.split_inclusive('\n').map(|line| format!("{}{}", prefix, line)).collect()
+            }
+            None => text,
+        };
+        self.terminal.push(SystemCall::Display(text));
+    }
+
+    /// Whether the lines so far are an unfinished command waiting for more.
+    pub fn is_continuing(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// The input is over, like at the end of a script: an unfinished command
+    /// is a syntax error.
+    pub fn end_of_input(&mut self) -> Vec<SystemCall> {
+        let Some(pending) = self.pending.take() else {
+            return vec![];
+        };
+
+        self.last_status = 2;
+        let mut terminal = vec![SystemCall::DisplayNewLine];
+        if let Err(err) = parse(&pending, &self.aliases) {
+            terminal.push(SystemCall::Display(self.syntax_error(&err)));
+        }
+        terminal
+    }
+
+    /// Runs the and-or lists one after another, `break`, `continue` and
+    /// `exit` stop the rest. Output goes into `out`, or onto the terminal
+    /// without one.
+    fn run_list(&mut self, list: &List, mut out: Option<&mut CommandIo>) {
+        for and_or in &list.items {
+            let mut status = self.run_pipeline(&and_or.first, out.as_deref_mut());
+            // the pipeline the status came from
+            let mut last = &and_or.first;
+            for (connector, pipeline) in &and_or.rest {
+                if self.flow != Flow::Normal {
+                    return;
+                }
+                let run = match connector {
+                    Connector::And => status == 0,
+                    Connector::Or => status != 0,
+                };
+                if run {
+                    status = self.run_pipeline(pipeline, out.as_deref_mut());
+                    last = pipeline;
+                }
+            }
+
+            if self.flow != Flow::Normal {
+                return;
+            }
+            if status != 0 && self.exits_on_error(and_or, last) {
+                self.flow = Flow::ErrExit;
+                return;
+            }
+        }
+    }
+
+    /// Whether `set -e` stops on a failed and-or list: only the last
+    /// pipeline of it counts, and not when negated or inside a condition.
+    fn exits_on_error(&self, and_or: &AndOr, last: &Pipeline) -> bool {
+        let is_last = and_or.rest.last().map_or(&and_or.first, |(_, pipeline)| pipeline);
+        self.options.errexit && self.condition_depth == 0 && !last.negated && std::ptr::eq(is_last, last)
+    }
+
+    /// Runs the commands of a pipeline, each one reads the stdout of the one
+    /// before it. Stderr and the stdout of the last one go to `out`, the
+    /// status of the last one is the status of the pipeline.
+    fn run_pipeline(&mut self, pipeline: &Pipeline, mut out: Option<&mut CommandIo>) -> i32 {
+        // the first command that reads the enclosing stdin takes all of it
+        let mut stdin = out.as_deref_mut().map(|io| std::mem::take(&mut io.stdin)).unwrap_or_default();
+        let to_terminal = out.as_deref().is_none_or(CommandIo::is_terminal);
+        let mut status = 0;
+        let last = pipeline.commands.len() - 1;
+        self.line = pipeline.line;
+
+        for (index, command) in pipeline.commands.iter().enumerate() {
+            // a compound command alone writes straight through, so `clear`
+            // inside a loop stays in order with the output
+            if last == 0 && !matches!(command, Command::Simple(_)) {
+                if let Some(io) = out.as_deref_mut() {
+                    io.stdin = std::mem::take(&mut stdin);
+                }
+                self.run_compound(command, out.as_deref_mut());
+                status = self.last_status;
+                break;
+            }
+
+            let mut io = CommandIo::new(std::mem::take(&mut stdin), index != last || !to_terminal);
+            // errors of this stage are reported at its line, not the line
+            // of whatever ran inside it last
+            let line = match command {
+                Command::Simple(simple) => simple.line,
+                _ => self.line,
+            };
+            self.line = line;
+            match command {
+                // a failed redirection skips the command, like in bash
+                Command::Simple(simple) => match self.apply_redirects(&simple.redirects, &mut io) {
+                    Ok(()) => {
+                        let calls = self.run_command(&simple.words, &mut io);
+                        self.terminal.extend(calls);
+                    }
+                    Err(err) => io.error(&format!("{}\n", err)),
+                },
+                compound => {
+                    self.run_compound(compound, Some(&mut io));
+                    io.status = self.last_status;
+                }
+            }
+            self.line = line;
+
+            status = io.status;
+            for (target, text) in io.routed() {
+                match target {
+                    Target::Stdout if index != last => stdin.push_str(&text),
+                    Target::Stdout => self.emit(out.as_deref_mut(), Stream::Stdout, text),
+                    Target::Stderr => self.emit(out.as_deref_mut(), Stream::Stderr, text),
+                    Target::File(path) => {
+                        let written = match self.vfs.as_mut() {
+                            Some(vfs) => vfs.write_file(&path, text.as_bytes(), true),
+                            None => Err(storage_not_set()),
+                        };
+                        if let Err(err) = written {
+                            self.emit(out.as_deref_mut(), Stream::Stderr, format!("{}\n", err));
+                        }
+                    }
+                }
+            }
+        }
+
+        if pipeline.negated {
+            status = (status == 0) as i32;
+        }
+        self.last_status = status;
+        status
+    }
+
+    /// Output leaving a pipeline, into the compound command around it or
+    /// onto the terminal.
+    fn emit(&mut self, out: Option<&mut CommandIo>, stream: Stream, text: String) {
+        match (out, stream) {
+            (Some(io), stream) => io.push(stream, &text),
+            (None, Stream::Stdout) => self.terminal.push(SystemCall::Display(text)),
+            (None, Stream::Stderr) => self.report(text),
+        }
+    }
+
+    /// Runs `if`, loops, `case` and groups, the status is left in `last_status`.
+    fn run_compound(&mut self, command: &Command, mut out: Option<&mut CommandIo>) {
+        match command {
+            Command::Simple(_) => unreachable!("simple commands run in a pipeline"),
+            Command::If { branches, otherwise } => {
+                for (condition, body) in branches {
+                    self.run_condition(condition, out.as_deref_mut());
+                    if self.flow != Flow::Normal {
+                        return;
+                    }
+                    if self.last_status == 0 {
+                        self.run_list(body, out);
+                        return;
+                    }
+                }
+                match otherwise {
+                    Some(body) => self.run_list(body, out),
+                    None => self.last_status = 0,
+                }
+            }
+            Command::Loop { until, condition, body } => {
+                let mut status = 0;
+                self.loop_depth += 1;
+                loop {
+                    self.run_condition(condition, out.as_deref_mut());
+                    if self.flow != Flow::Normal {
+                        if self.loop_continues() {
+                            continue;
+                        }
+                        break;
+                    }
+                    if (self.last_status == 0) == *until {
+                        break;
+                    }
+
+                    self.run_list(body, out.as_deref_mut());
+                    status = self.last_status;
+                    if !self.loop_continues() {
+                        break;
+                    }
+                }
+                self.loop_depth -= 1;
+                self.last_status = status;
+            }
+            Command::For { variable, words, body } => {
+                let values = match self.expand_words(words) {
+                    Ok(values) => values,
+                    Err(err) => {
+                        self.emit(out, Stream::Stderr, format!("{}\n", err));
+                        self.last_status = 1;
+                        return;
+                    }
+                };
+
+                let mut status = 0;
+                self.loop_depth += 1;
+                for value in values {
+                    self.variables.set(variable, value);
+                    self.run_list(body, out.as_deref_mut());
+                    status = self.last_status;
+                    if !self.loop_continues() {
+                        break;
+                    }
+                }
+                self.loop_depth -= 1;
+                self.last_status = status;
+            }
+            Command::Case { word, arms } => {
+                let value = word.expand_value(&mut |expansion| self.resolve(expansion));
+                let arm = arms.iter().find(|arm| {
+                    arm.patterns.iter().any(|pattern| {
+                        let pattern: String = pattern.expand(&mut |expansion| self.resolve(expansion)).concat();
+                        glob_match(&pattern, &value)
+                    })
+                });
+                if let Some(err) = self.expansion_error.take() {
+                    self.emit(out, Stream::Stderr, format!("{}\n", err));
+                    self.last_status = 1;
+                    return;
+                }
+
+                // an arm without commands succeeds too
+                self.last_status = 0;
+                if let Some(arm) = arm {
+                    self.run_list(&arm.body, out);
+                }
+            }
+            Command::Group(list) => self.run_list(list, out),
+            Command::Function { name, body } => {
+                self.functions.insert(name.clone(), body.clone());
+                self.last_status = 0;
+            }
+        }
+    }
+
+    /// Runs the condition of `if`, `while` or `until`, its failures don't
+    /// stop the shell under `set -e`.
+    fn run_condition(&mut self, condition: &List, out: Option<&mut CommandIo>) {
+        self.condition_depth += 1;
+        self.run_list(condition, out);
+        self.condition_depth -= 1;
+    }
+
+    /// Settles `break` and `continue` after a loop body, returns whether
+    /// this loop goes on.
+    fn loop_continues(&mut self) -> bool {
+        match self.flow {
+            Flow::Normal => true,
+            Flow::Continue(1) => {
+                self.flow = Flow::Normal;
+                true
+            }
+            Flow::Break(1) => {
+                self.flow = Flow::Normal;
+                false
+            }
+            // the rest is for the loops around this one
+            Flow::Continue(count) => {
+                self.flow = Flow::Continue(count - 1);
+                false
+            }
+            Flow::Break(count) => {
+                self.flow = Flow::Break(count - 1);
+                false
+            }
+            Flow::Return | Flow::Exit | Flow::ErrExit => false,
+        }
+    }
+
+    /// Runs a function with `args` as `$1` and on. Its output goes into `io`,
+    /// its status is the one it returned with.
+    fn run_function(&mut self, name: &str, body: &Command, args: Vec<String>, io: &mut CommandIo) {
+        if self.frames.len() + self.sourcing >= FUNCTION_DEPTH {
+            io.error(&format!("{}: maximum function nesting level exceeded ({})\n", name, FUNCTION_DEPTH));
+            return;
+        }
+
+        let positional = std::mem::replace(&mut self.positional, args);
+        let line = self.line;
+        let outermost = self.call_line.is_none();
+        if outermost {
+            self.call_line = Some(line);
+        }
+        // loops of the caller can't be left from inside the function
+        let loop_depth = std::mem::take(&mut self.loop_depth);
+        self.frames.push(vec![]);
+
+        self.run_compound(body, Some(io));
+
+        for (name, variable) in self.frames.pop().unwrap_or_default().into_iter().rev() {
+            self.variables.restore(&name, variable);
+        }
+        self.loop_depth = loop_depth;
+        self.positional = positional;
+        self.line = line;
+        if outermost {
+            self.call_line = None;
+        }
+        if self.flow == Flow::Return {
+            self.flow = Flow::Normal;
+        }
+        io.status = self.last_status;
+    }
+
+    /// Runs a script from the VFS in this shell, so what it sets stays set.
+    /// With `args` they are `$1` and on while it runs. It is read a command
+    /// at a time like typed lines, an alias it defines works below it, and a
+    /// syntax error stops it there. `return` leaves it.
+    fn run_source(&mut self, path: &str, args: Vec<String>, io: &mut CommandIo) {
+        if self.frames.len() + self.sourcing >= FUNCTION_DEPTH {
+            io.error(&format!("{}: maximum nesting level exceeded ({})\n", path, FUNCTION_DEPTH));
+            return;
+        }
+        let text = match self.vfs.as_mut().map(|vfs| vfs.read_file(&path.to_string())) {
+            Some(Ok(bytes)) => String::from_utf8_lossy(bytes).to_string(),
+            Some(Err(err)) => {
+                io.error(&format!("source: {}\n", err));
+                return;
+            }
+            None => {
+                io.error(&format!("source: {}\n", storage_not_set()));
+                return;
+            }
+        };
+
+        let positional = match args.is_empty() {
+            true => None,
+            false => Some(std::mem::replace(&mut self.positional, args)),
+        };
+        let line = self.line;
+        let call_line = self.call_line;
+        self.call_line.get_or_insert(line);
+        self.sourcing += 1;
+        self.last_status = 0;
+
+        // lines of the command read so far, and the line it starts on
+        let mut input = String::new();
+        let mut start = 1;
+        for (index, text) in text.lines().enumerate() {
+            if input.is_empty() {
+                start = index + 1;
+            } else {
+                input.push('\n');
+            }
+            input.push_str(text);
+
+            match parse(&input, &self.aliases) {
+                Err(err) if err.incomplete => continue,
+                Err(err) => {
+                    io.error(&script_syntax_error(path, start + err.line - 1, &err));
+                    self.last_status = 2;
+                    input.clear();
+                    break;
+                }
+                Ok(list) => self.run_list(&list, Some(io)),
+            }
+            input.clear();
+            if self.flow != Flow::Normal {
+                break;
+            }
+        }
+        // a command the script never finished
+        if let (false, Err(err)) = (input.is_empty(), parse(&input, &self.aliases)) {
+            io.error(&script_syntax_error(path, start + err.line - 1, &err));
+            self.last_status = 2;
+        }
+
+        self.sourcing -= 1;
+        self.line = line;
+        self.call_line = call_line;
+        if let Some(positional) = positional {
+            self.positional = positional;
+        }
+        if self.flow == Flow::Return {
+            self.flow = Flow::Normal;
+        }
+        io.status = self.last_status;
+    }
+
+    /// One step of `getopts`: the next option of `args` goes into `name`
+    /// and its argument into `OPTARG`, `OPTIND` is the index of the next
+    /// argument to look at. Returns 1 once the options are over. With `:`
+    /// in front of `letters` mistakes are left to the script instead of
+    /// being reported, `name` is then `?` or `:` and `OPTARG` the letter.
+    fn getopts(&mut self, letters: &str, name: &str, args: &[String], io: &mut CommandIo) -> i32 {
+        let (silent, letters) = match letters.strip_prefix(':') {
+            Some(letters) => (true, letters),
+            None => (false, letters),
+        };
+        let mut index = self.variables.get("OPTIND").and_then(|index| index.parse().ok()).unwrap_or(1usize).max(1);
+        // the script reset `OPTIND` or it is a new round, start at a new word
+        let mut offset = match self.getopts_position {
+            (position, offset) if position == index => offset,
+            _ => 1,
+        };
+        self.variables.unset("OPTARG");
+
+        let arg: Vec<char> = args.get(index - 1).map(|arg| arg.chars().collect()).unwrap_or_default();
+        let options_over = arg.len() < 2 || arg[0] != '-' || offset == 1 && arg == ['-', '-'];
+        if options_over {
+            if arg == ['-', '-'] {
+                index += 1;
+            }
+            self.variables.set("OPTIND", index.to_string());
+            self.variables.set(name, "?".to_string());
+            self.getopts_position = (index, 1);
+            return 1;
+        }
+
+        let letter = arg[offset.min(arg.len() - 1)];
+        offset += 1;
+        let word_done = offset >= arg.len();
+        if word_done {
+            index += 1;
+            offset = 1;
+        }
+
+        let value = match letters.find(letter).filter(|_| letter != ':') {
+            None => {
+                if silent {
+                    self.variables.set("OPTARG", letter.to_string());
+                } else {
+                    io.error(&format!("getopts: illegal option -- {}\n", letter));
+                }
+                "?".to_string()
+            }
+            Some(at) if letters[at + letter.len_utf8()..].starts_with(':') => {
+                // the rest of the word, or the next word
+                let argument = match word_done {
+                    false => Some(arg[offset..].iter().collect()),
+                    true => args.get(index - 1).cloned(),
+                };
+                match argument {
+                    Some(argument) => {
+                        self.variables.set("OPTARG", argument);
+                        index += 1;
+                        offset = 1;
+                        letter.to_string()
+                    }
+                    None if silent => {
+                        self.variables.set("OPTARG", letter.to_string());
+                        ":".to_string()
+                    }
+                    None => {
+                        io.error(&format!("getopts: option requires an argument -- {}\n", letter));
+                        "?".to_string()
+                    }
+                }
+            }
+            Some(_) => letter.to_string(),
+        };
+
+        self.variables.set(name, value);
+        self.variables.set("OPTIND", index.to_string());
+        self.getopts_position = (index, offset);
+        0
+    }
+
+    /// Expands and runs one command, returns the calls only the terminal can
+    /// carry out, like `clear` and `exit`. Leading `NAME=value` words set
+    /// shell variables, or with a command after them export the values to
+    /// that command only.
+    fn run_command(&mut self, words: &[Word], io: &mut CommandIo) -> Vec<SystemCall> {
+        self.substitution_status = None;
+        let assignments: Vec<(String, String)> = words
+            .iter()
+            .map_while(Word::assignment)
+            .map(|(name, value)| (name, value.expand_value(&mut |expansion| self.resolve(expansion))))
+            .collect();
+        let words = &words[assignments.len()..];
+
+        if let Some(err) = self.expansion_error.take() {
+            io.error(&format!("{}\n", err));
+            return vec![];
+        }
+        if words.is_empty() {
+            if self.options.xtrace {
+                self.trace(&assignments, &[]);
+            }
+            for (name, value) in assignments {
+                self.variables.set(&name, value);
+            }
+            io.status = self.substitution_status.unwrap_or(0);
+            return vec![];
+        }
+
+        let fields = match self.expand_words(words) {
+            Ok(fields) => fields,
+            Err(err) => {
+                io.error(&format!("{}\n", err));
+                return vec![];
+            }
+        };
+
+        if self.options.xtrace {
+            self.trace(&assignments, &fields);
+        }
+
+        let saved: Vec<(String, Option<Variable>)> = assignments
+            .iter()
+            .map(|(name, _)| (name.clone(), self.variables.get_variable(name).cloned()))
+            .collect();
+        for (name, value) in assignments {
+            self.variables.set(&name, value);
+            self.variables.export(&name, true);
+        }
+
+        // functions win over builtins with the same name
+        let terminal_calls = match self.functions.get(&fields[0]).cloned() {
+            Some(body) => {
+                let mut fields = fields;
+                let name = fields.remove(0);
+                self.run_function(&name, &body, fields, io);
+                vec![]
+            }
+            None => Commands::parse_from_words(fields)
+                .execute()
+                .into_iter()
+                .filter_map(|call| self.system_call(call, io))
+                .collect(),
+        };
+
+        for (name, variable) in saved.into_iter().rev() {
+            self.variables.restore(&name, variable);
+        }
+        terminal_calls
+    }
+
+    /// `set -x` output, the command as it runs after expansion.
+    fn trace(&mut self, assignments: &[(String, String)], fields: &[String]) {
+        let words: Vec<String> = assignments
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, quote(value)))
+            .chain(fields.iter().map(|field| quote(field)))
+            .collect();
+        self.terminal.push(SystemCall::Display(format!("+ {}\n", words.join(" "))));
+    }
+
+    /// Value of a `$` part of a word. Errors are kept for the command to
+    /// report, the part itself expands to nothing.
+    fn resolve(&mut self, expansion: &Expansion) -> Option<String> {
+        match expansion {
+            Expansion::Variable(name) => {
+                let value = self.lookup(name);
+                if value.is_none() && self.options.nounset {
+                    self.expansion_error.get_or_insert(format!("{}: unbound variable", name));
+                }
+                value
+            }
+            Expansion::Command(source) => Some(self.substitute(source)),
+            Expansion::Arithmetic(expression) => {
+                let expression = expression.expand_value(&mut |expansion| self.resolve(expansion));
+                match evaluate(&expression, self) {
+                    Ok(value) => Some(value.to_string()),
+                    Err(err) => {
+                        self.expansion_error.get_or_insert(err);
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    /// Runs `$(source)` like a subshell: its stdout is the value without the
+    /// trailing newlines, its stderr goes to the terminal and whatever it
+    /// does to the shell state is undone. Changes to the VFS stay.
+    fn substitute(&mut self, source: &str) -> String {
+        let list = match parse(source, &self.aliases) {
+            Ok(list) => list,
+            Err(err) => {
+                let text = self.syntax_error(&err);
+                self.terminal.push(SystemCall::Display(text));
+                self.substitution_status = Some(2);
+                return String::new();
+            }
+        };
+
+        let variables = self.variables.clone();
+        let aliases = self.aliases.clone();
+        let functions = self.functions.clone();
+        let positional = self.positional.clone();
+        let frames = self.frames.clone();
+        let old_pwd = self.old_pwd.clone();
+        let user = self.user.clone();
+        let logins = self.logins.clone();
+        let loop_depth = std::mem::take(&mut self.loop_depth);
+        let line = self.line;
+        // errors inside are reported at the line of the command around it
+        let call_line = self.call_line;
+        self.call_line.get_or_insert(line);
+        let location = self.vfs.as_ref().map(|vfs| (vfs.current_path(), vfs.user.clone(), vfs.umask));
+        // `exit` and `clear` only end or clear the subshell
+        let terminal_len = self.terminal.len();
+
+        let mut io = CommandIo::new(String::new(), true);
+        self.run_list(&list, Some(&mut io));
+        self.substitution_status = Some(self.last_status);
+        let calls = self.terminal.split_off(terminal_len);
+        // errors and traces from inside stay
+        self.terminal
+            .extend(calls.into_iter().filter(|call| matches!(call, SystemCall::Display(_))));
+
+        self.variables = variables;
+        self.aliases = aliases;
+        self.functions = functions;
+        self.positional = positional;
+        self.frames = frames;
+        self.old_pwd = old_pwd;
+        self.user = user;
+        self.logins = logins;
+        self.loop_depth = loop_depth;
+        self.line = line;
+        self.call_line = call_line;
+        self.flow = Flow::Normal;
+        if let (Some(vfs), Some((path, user, umask))) = (self.vfs.as_mut(), location) {
+            vfs.user = user;
+            vfs.umask = umask;
+            // the dir can be gone by now, then the shell stays where it is
+            let _ = vfs.change_dir(vec![path]);
+        }
+
+        let mut output = String::new();
+        for (stream, text) in io.output {
+            match stream {
+                Stream::Stdout => output.push_str(&text),
+                Stream::Stderr => self.report(text),
+            }
+        }
+        output.truncate(output.trim_end_matches('\n').len());
+        output
+    }
+
+    /// Value of `$name`, what the shell knows from its own state wins over
+    /// the variable table.
+    fn lookup(&self, name: &str) -> Option<String> {
+        match name {
+            "?" => Some(self.last_status.to_string()),
+            "$" => Some(process::id().to_string()),
+            "RANDOM" => Some(self.random().to_string()),
+            "0" => Some(self.program.clone()),
+            "#" => Some(self.positional.len().to_string()),
+            "@" | "*" => Some(self.positional.join(" ")),
+            "USER" => Some(self.user.clone()),
+            "OLDPWD" => self.old_pwd.clone(),
+            "PWD" => self.vfs.as_ref().map(VFS::current_path),
+            "HOME" => self.vfs.as_ref().map(VFS::home_dir),
+            _ => match name.parse::<usize>() {
+                Ok(index) if index > 0 => self.positional.get(index - 1).cloned(),
+                _ => self.variables.get(name).map(str::to_string),
+            },
+        }
+    }
+
+    /// 0 to 32767 like bash.
+    fn random(&self) -> u32 {
+        let mut state = self.random_state.get();
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        self.random_state.set(state);
+        state % 32768
+    }
+
+    /// Every variable with the state ones filled in.
+    fn all_variables(&self) -> Variables {
+        let mut variables = self.variables.clone();
+        for name in STATE_VARIABLES {
+            if let Some(value) = self.lookup(name) {
+                variables.set(name, value);
+                variables.export(name, true);
+            }
+        }
+        variables
+    }
+
+    /// The environment a child script gets, only exported variables.
+    pub fn environment(&self) -> Variables {
+        self.all_variables().exported()
+    }
+
+    /// Opens the redirections from left to right: files are created or
+    /// truncated now and kept by their absolute path so a `cd` in the command
+    /// doesn't move them, `<` replaces stdin, `N>&M` copies where `M` goes.
+    fn apply_redirects(&mut self, redirects: &[Redirect], io: &mut CommandIo) -> Result<(), Error> {
+        for redirect in redirects {
+            let target = self.expand_target(&redirect.target)?;
+            let vfs = self.vfs.as_mut();
+
+            match redirect.kind {
+                RedirectKind::Read => {
+                    if let Some(fd) = redirect.fd.filter(|fd| *fd != 0) {
+                        return Err(bad_descriptor(fd));
+                    }
+                    let vfs = vfs.ok_or_else(storage_not_set)?;
+                    io.stdin = String::from_utf8_lossy(vfs.read_file(&target)?).to_string();
+                }
+                RedirectKind::Write | RedirectKind::Append => {
+                    let vfs = vfs.ok_or_else(storage_not_set)?;
+                    vfs.write_file(&target, b"", redirect.kind == RedirectKind::Append)?;
+                    let target = vfs.canonical_path(&target)?;
+                    *stream_target(io, redirect.fd.unwrap_or(1))? = Target::File(target);
+                }
+                RedirectKind::WriteAll | RedirectKind::AppendAll => {
+                    let vfs = vfs.ok_or_else(storage_not_set)?;
+                    vfs.write_file(&target, b"", redirect.kind == RedirectKind::AppendAll)?;
+                    let target = vfs.canonical_path(&target)?;
+                    io.stdout = Target::File(target.clone());
+                    io.stderr = Target::File(target);
+                }
+                RedirectKind::Duplicate => {
+                    let source = match target.parse::<u32>() {
+                        Ok(fd) => stream_target(io, fd)?.clone(),
+                        Err(_) => return Err(Error::new(ErrorKind::InvalidInput, format!("{}: ambiguous redirect", target))),
+                    };
+                    *stream_target(io, redirect.fd.unwrap_or(1))? = source;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// A redirection target has to expand to exactly one word.
+    fn expand_target(&mut self, word: &Word) -> Result<String, Error> {
+        let fields = self.expand_words(std::slice::from_ref(word))?;
+        match fields.as_slice() {
+            [field] => Ok(field.clone()),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{}: ambiguous redirect", fields.join(" ")),
+            )),
+        }
+    }
+
+    /// Words to final fields: `$` parts from the session, then globbing
+    /// against the VFS.
+    fn expand_words(&mut self, words: &[Word]) -> Result<Vec<String>, Error> {
+        let mut fields: Vec<String> = vec![];
+        for word in words {
+            fields.extend(word.expand(&mut |expansion| self.resolve(expansion)));
+        }
+        if let Some(err) = self.expansion_error.take() {
+            return Err(Error::new(ErrorKind::InvalidInput, err));
+        }
+
+        match self.vfs.as_ref() {
+            Some(vfs) => vfs.expand_globs(fields, self.glob_options),
+            None => Ok(fields.iter().map(|field| unescape_glob(field)).collect()),
+        }
+    }
+
+    /// Runs a VFS operation for `command` and writes its output or its error.
+    fn run_vfs_call(
+        &mut self,
+        io: &mut CommandIo,
+        command: &str,
+        call: impl FnOnce(&mut VFS) -> Result<String, Error>,
+    ) {
+        let Some(vfs) = self.vfs.as_mut() else {
+            io.error("VFS storage not set\n");
+            return;
+        };
+
+        match call(vfs) {
+            Ok(output) => io.write(&output),
+            Err(err) => io.error(&format!("{}: {}\n", command, err)),
+        }
+    }
+
+    fn system_call(&mut self, system_call: SystemCall, io: &mut CommandIo) -> Option<SystemCall> {
+        match system_call {
+            SystemCall::ChangeDir(mut command_args) => {
+                let Some(vfs) = self.vfs.as_mut() else {
+                    io.error("VFS storage not set\n");
+                    return None;
+                };
+
+                // `cd -` goes back and prints where it went
+                let back = command_args == ["-"];
+                if back {
+                    match &self.old_pwd {
+                        Some(path) => command_args = vec![path.clone()],
+                        None => {
+                            io.error("cd: OLDPWD not set\n");
+                            return None;
+                        }
+                    }
+                }
+
+                let previous = vfs.current_path();
+                match vfs.change_dir(command_args) {
+                    Ok(_) => {
+                        if back {
+                            io.write(&format!("{}\n", vfs.current_path()));
+                        }
+                        self.old_pwd = Some(previous);
+                    }
+                    Err(err) => io.error(&format!("cd: {}\n", err)),
+                }
+            }
+            SystemCall::ListDir(command_args) => {
+                // one name per line when the output is not read by a person
+                let one_per_line = !io.is_terminal();
+                let Some(vfs) = self.vfs.as_mut() else {
+                    io.error("VFS storage not set\n");
+                    return None;
+                };
+
+                let (flags, mut paths): (Vec<String>, Vec<String>) =
+                    command_args.into_iter().partition(|arg| arg.starts_with('-'));
+                let owner_info = flags.concat().contains('l');
+                if paths.is_empty() {
+                    paths.push(".".to_string());
+                }
+
+                // a listed item is a name, or a whole line with `-l`
+                let item = |name: &str, node: &VFSNode| {
+                    if !owner_info {
+                        return name.to_string();
+                    }
+                    let name = match &node.kind {
+                        VFSNodeKind::Symlink { target } => format!("{} -> {}", name, target),
+                        _ => name.to_string(),
+                    };
+                    format!(
+                        "{} {} {} {} {:>5} {} {}",
+                        mode_string(node.mode, node.file_type()),
+                        node.links,
+                        node.owner,
+                        node.group,
+                        node.size(),
+                        &format_time(node.mtime)[..16],
+                        name
+                    )
+                };
+                let push_items = |output: &mut String, items: Vec<String>| {
+                    if owner_info || one_per_line {
+                        items.iter().for_each(|item| output.push_str(&format!("{}\n", item)));
+                    } else if !items.is_empty() {
+                        items.iter().for_each(|item| output.push_str(&format!("{} ", item)));
+                        output.push('\n');
+                    }
+                };
+
+                // files given by name come first, all together like the
+                // entries of one dir, then each dir under its own header
+                let (dirs, mut files): (Vec<String>, Vec<String>) =
+                    paths.iter().cloned().partition(|path| vfs.is_dir(path));
+                files.sort();
+
+                // a bad operand is reported and the others are still listed
+                let mut failed = false;
+                let mut output = String::new();
+                let mut items = vec![];
+                let mut listed = 0;
+                for path in &files {
+                    match vfs.list_dir(path) {
+                        Ok(entries) => {
+                            items.extend(entries.into_iter().map(|(name, node)| item(&name, node)));
+                            listed += 1;
+                        }
+                        Err(err) => {
+                            io.error(&format!("ls: {}\n", err));
+                            failed = true;
+                        }
+                    }
+                }
+                push_items(&mut output, items);
+
+                for (index, path) in dirs.iter().enumerate() {
+                    if paths.len() > 1 {
+                        if index > 0 || listed > 0 {
+                            output.push('\n');
+                        }
+                        output.push_str(&format!("{}:\n", path));
+                    }
+
+                    match vfs.list_dir(path) {
+                        Ok(mut entries) => {
+                            entries.sort_by(|a, b| a.0.cmp(&b.0));
+                            push_items(&mut output, entries.into_iter().map(|(name, node)| item(&name, node)).collect());
+                        }
+                        Err(err) => {
+                            io.write(&std::mem::take(&mut output));
+                            io.error(&format!("ls: {}\n", err));
+                            failed = true;
+                        }
+                    }
+                }
+                io.write(&output);
+                if failed {
+                    io.status = 2;
+                }
+            }
+            SystemCall::ReadFile(command_args) => {
+                if command_args.is_empty() {
+                    let stdin = std::mem::take(&mut io.stdin);
+                    io.write(&stdin);
+                    return None;
+                }
+
+                let Some(vfs) = self.vfs.as_mut() else {
+                    io.error("VFS storage not set\n");
+                    return None;
+                };
+
+                for path in command_args {
+                    if path == "-" {
+                        let stdin = std::mem::take(&mut io.stdin);
+                        io.write(&stdin);
+                        continue;
+                    }
+
+                    match vfs.read_file(&path) {
+                        Ok(content) => {
+                            let mut content = String::from_utf8_lossy(content).to_string();
+                            if !content.is_empty() && !content.ends_with('\n') {
+                                content.push('\n');
+                            }
+                            io.write(&content);
+                        }
+                        Err(err) => io.error(&format!("cat: {}\n", err)),
+                    }
+                }
+            }
+            SystemCall::PrintWorkDir => match self.vfs.as_ref() {
+                Some(vfs) => io.write(&format!("{}\n", vfs.current_path())),
+                None => io.error("VFS storage not set\n"),
+            },
+            SystemCall::ChangeMode(command_args) => {
+                let mode = command_args[0].clone();
+                self.run_vfs_call(io, "chmod", |vfs| {
+                    for path in command_args[1..].iter() {
+                        vfs.set_node_mode(path.clone(), &mode)?;
+                    }
+                    Ok(String::new())
+                });
+            }
+            SystemCall::ChangeOwner(command_args) => {
+                let user = command_args[0].clone();
+                let path = command_args[1].clone();
+                self.run_vfs_call(io, "chown", |vfs| {
+                    vfs.set_node_owner(path, user)?;
+                    Ok(String::new())
+                });
+            }
+            SystemCall::Umask(command_args) => {
+                self.run_vfs_call(io, "umask", |vfs| match command_args.first() {
+                    None => Ok(format!("{:04o}\n", vfs.umask)),
+                    Some(mask) => vfs.set_umask(mask).map(|_| String::new()),
+                });
+            }
+            SystemCall::UserAdd(command_args) => {
+                self.run_vfs_call(io, "useradd", |vfs| {
+                    let mut group: Option<String> = None;
+                    let mut extra_groups: Vec<String> = vec![];
+                    let mut create_home = false;
+                    let mut name: Option<String> = None;
+
+                    let mut args = command_args.into_iter();
+                    while let Some(arg) = args.next() {
+                        match arg.as_str() {
+                            "-m" => create_home = true,
+                            "-g" => group = args.next(),
+                            "-G" => {
+                                extra_groups = args
+                                    .next()
+                                    .unwrap_or_default()
+                                    .split(',')
+                                    .filter(|s| !s.is_empty())
+                                    .map(|s| s.to_string())
+                                    .collect()
+                            }
+                            _ => name = Some(arg),
+                        }
+                    }
+
+                    let name = name.ok_or_else(|| Error::new(ErrorKind::InvalidInput, "missing user name"))?;
+                    vfs.add_user(&name, group.as_deref(), &extra_groups, create_home)?;
+                    Ok(String::new())
+                });
+            }
+            SystemCall::UserDel(command_args) => {
+                self.run_vfs_call(io, "userdel", |vfs| {
+                    let remove_home = command_args.contains(&"-r".to_string());
+                    for name in command_args.iter().filter(|arg| *arg != "-r") {
+                        vfs.remove_user(name, remove_home)?;
+                    }
+                    Ok(String::new())
+                });
+            }
+            SystemCall::GroupAdd(command_args) => {
+                self.run_vfs_call(io, "groupadd", |vfs| {
+                    for name in command_args.iter() {
+                        vfs.add_group(name)?;
+                    }
+                    Ok(String::new())
+                });
+            }
+            SystemCall::GroupDel(command_args) => {
+                self.run_vfs_call(io, "groupdel", |vfs| {
+                    for name in command_args.iter() {
+                        vfs.remove_group(name)?;
+                    }
+                    Ok(String::new())
+                });
+            }
+            SystemCall::SwitchUser(command_args) => {
+                let login = self.vfs.as_ref().map(|vfs| Login {
+                    user: vfs.user.clone(),
+                    path: vfs.current_path(),
+                    umask: vfs.umask,
+                });
+                let mut switched = false;
+                self.run_vfs_call(io, "su", |vfs| {
+                    let login = command_args.contains(&"-".to_string());
+                    let name = command_args
+                        .iter()
+                        .find(|arg| *arg != "-")
+                        .cloned()
+                        .unwrap_or(SHELL_USER.to_string());
+
+                    vfs.switch_user(&name)?;
+                    switched = true;
+                    if login {
+                        vfs.change_dir(vec![])?;
+                    }
+                    Ok(String::new())
+                });
+
+                if let (true, Some(login)) = (switched, login) {
+                    self.logins.push(login);
+                }
+
+                if let Some(vfs) = self.vfs.as_ref() {
+                    self.user = vfs.user.clone();
+                }
+            }
+            SystemCall::Id(command_args) => {
+                let user = self.user.clone();
+                self.run_vfs_call(io, "id", |vfs| {
+                    let name = command_args.first().unwrap_or(&user);
+                    Ok(format!("{}\n", vfs.id_string(name)?))
+                });
+            }
+            SystemCall::Link(command_args) => {
+                self.run_vfs_call(io, "ln", |vfs| {
+                    let (flags, paths): (Vec<String>, Vec<String>) =
+                        command_args.into_iter().partition(|arg| arg.starts_with('-'));
+                    let flags = flags.concat();
+                    let symbolic = flags.contains('s');
+                    let force = flags.contains('f');
+
+                    vfs.create_link(&paths[0], &paths[1], symbolic, force)?;
+                    Ok(String::new())
+                });
+            }
+            SystemCall::ReadLink(command_args) => {
+                self.run_vfs_call(io, "readlink", |vfs| {
+                    let canonicalize = command_args.contains(&"-f".to_string());
+                    let mut output = String::new();
+                    for path in command_args.iter().filter(|arg| !arg.starts_with('-')) {
+                        output.push_str(&vfs.read_link(path, canonicalize)?);
+                        output.push('\n');
+                    }
+                    Ok(output)
+                });
+            }
+            SystemCall::Stat(command_args) => {
+                self.run_vfs_call(io, "stat", |vfs| {
+                    let mut output = String::new();
+                    for path in &command_args {
+                        output.push_str(&vfs.stat(path)?);
+                    }
+                    Ok(output)
+                });
+            }
+            SystemCall::Touch(command_args) => {
+                self.run_vfs_call(io, "touch", |vfs| {
+                    let (flags, paths): (Vec<String>, Vec<String>) =
+                        command_args.into_iter().partition(|arg| arg.starts_with('-'));
+                    let flags = flags.concat();
+                    // neither -a nor -m means both
+                    let only_access = flags.contains('a') && !flags.contains('m');
+                    let only_modify = flags.contains('m') && !flags.contains('a');
+
+                    for path in paths {
+                        vfs.touch(&path, !only_modify, !only_access, flags.contains('c'))?;
+                    }
+                    Ok(String::new())
+                });
+            }
+            SystemCall::MakeDir(command_args) => {
+                self.run_vfs_call(io, "mkdir", |vfs| {
+                    let parents = command_args.contains(&"-p".to_string());
+                    for path in command_args.iter().filter(|arg| !arg.starts_with('-')) {
+                        vfs.make_dir(path, parents)?;
+                    }
+                    Ok(String::new())
+                });
+            }
+            SystemCall::RemoveDir(command_args) => {
+                self.run_vfs_call(io, "rmdir", |vfs| {
+                    for path in &command_args {
+                        vfs.remove_dir(path)?;
+                    }
+                    Ok(String::new())
+                });
+            }
+            SystemCall::Remove(command_args) => {
+                self.run_vfs_call(io, "rm", |vfs| {
+                    let (flags, paths): (Vec<String>, Vec<String>) =
+                        command_args.into_iter().partition(|arg| arg.starts_with('-'));
+                    let flags = flags.concat();
+                    let recursive = flags.contains('r') || flags.contains('R');
+
+                    for path in paths {
+                        vfs.remove_node(&path, recursive, flags.contains('f'))?;
+                    }
+                    Ok(String::new())
+                });
+            }
+            SystemCall::Move(command_args) => {
+                self.run_vfs_call(io, "mv", |vfs| {
+                    // nothing ever prompts, `-f` and the like change nothing
+                    let (_, paths): (Vec<String>, Vec<String>) =
+                        command_args.into_iter().partition(|arg| arg.starts_with('-'));
+
+                    let (destination, sources) = paths.split_last().unwrap();
+                    if sources.len() > 1 && !vfs.is_dir(destination) {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("target '{}' is not a directory", destination),
+                        ));
+                    }
+
+                    for source in sources {
+                        vfs.move_node(source, destination)?;
+                    }
+                    Ok(String::new())
+                });
+            }
+            SystemCall::Copy(command_args) => {
+                self.run_vfs_call(io, "cp", |vfs| {
+                    let (flags, paths): (Vec<String>, Vec<String>) =
+                        command_args.into_iter().partition(|arg| arg.starts_with('-'));
+                    let flags = flags.concat();
+                    let recursive = flags.contains('r') || flags.contains('R');
+
+                    let (destination, sources) = paths.split_last().unwrap();
+                    if sources.len() > 1 && !vfs.is_dir(destination) {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("target '{}' is not a directory", destination),
+                        ));
+                    }
+
+                    for source in sources {
+                        vfs.copy_node(source, destination, recursive)?;
+                    }
+                    Ok(String::new())
+                });
+            }
+            SystemCall::Find(command_args) => {
+                self.run_vfs_call(io, "find", |vfs| vfs.find(&FindQuery::parse(&command_args)?));
+            }
+            SystemCall::Tree(command_args) => {
+                self.run_vfs_call(io, "tree", |vfs| vfs.render_tree(&TreeOptions::parse(&command_args)?));
+            }
+            SystemCall::Shopt(command_args) => {
+                let mut options = self.glob_options;
+                let value = match command_args.first().map(String::as_str) {
+                    Some("-s") => Some(true),
+                    Some("-u") => Some(false),
+                    _ => None,
+                };
+
+                let result = match value {
+                    Some(value) => command_args[1..]
+                        .iter()
+                        .try_for_each(|name| options.set(name, value))
+                        .map(|_| String::new()),
+                    None => {
+                        let names: Vec<&str> = if command_args.is_empty() {
+                            GlobOptions::NAMES.to_vec()
+                        } else {
+                            command_args.iter().map(String::as_str).collect()
+                        };
+                        names
+                            .into_iter()
+                            .map(|name| match options.get(name) {
+                                Some(on) => Ok(format!("{}\t{}\n", name, if on { "on" } else { "off" })),
+                                None => Err(Error::new(
+                                    ErrorKind::InvalidInput,
+                                    format!("{}: invalid shell option name", name),
+                                )),
+                            })
+                            .collect()
+                    }
+                };
+
+                match result {
+                    Ok(output) => {
+                        self.glob_options = options;
+                        io.write(&output);
+                    }
+                    Err(err) => io.error(&format!("shopt: {}\n", err)),
+                }
+            }
+            SystemCall::Echo(command_args) => {
+                let newline = command_args.first().is_none_or(|arg| arg != "-n");
+                let words = if newline { &command_args[..] } else { &command_args[1..] };
+                io.write(&words.join(" "));
+                if newline {
+                    io.write("\n");
+                }
+            }
+            SystemCall::Grep(command_args) => {
+                // 0 when a line was selected, 1 when none, 2 on errors
+                let query = match GrepQuery::parse(&command_args) {
+                    Ok(query) => query,
+                    Err(err) => {
+                        io.status = 2;
+                        io.error(&format!("grep: {}\n", err));
+                        return None;
+                    }
+                };
+
+                let mut found = false;
+                if query.files.is_empty() {
+                    let (output, matched) = query.search(&io.stdin, None);
+                    io.write(&output);
+                    found = matched;
+                } else {
+                    let Some(vfs) = self.vfs.as_mut() else {
+                        io.status = 2;
+                        io.error("VFS storage not set\n");
+                        return None;
+                    };
+                    let named = query.files.len() > 1;
+                    for path in &query.files {
+                        match vfs.read_file(path) {
+                            Ok(content) => {
+                                let text = String::from_utf8_lossy(content).to_string();
+                                let (output, matched) = query.search(&text, named.then_some(path.as_str()));
+                                io.write(&output);
+                                found |= matched;
+                            }
+                            Err(err) => {
+                                io.status = 2;
+                                io.error(&format!("grep: {}\n", err));
+                            }
+                        }
+                    }
+                }
+                if !found && io.status == 0 {
+                    io.status = 1;
+                }
+            }
+            SystemCall::Export(command_args) => {
+                let exported = command_args.first().is_none_or(|arg| arg != "-n");
+                let names = if exported { &command_args[..] } else { &command_args[1..] };
+
+                if names.is_empty() {
+                    for (name, variable) in self.variables.iter().filter(|(_, variable)| variable.exported) {
+                        io.write(&format!("export {}={}\n", name, quote(&variable.value)));
+                    }
+                }
+                for arg in names {
+                    let (name, value) = match arg.split_once('=') {
+                        Some((name, value)) => (name, Some(value)),
+                        None => (arg.as_str(), None),
+                    };
+                    if !is_valid_name(name) {
+                        io.error(&format!("export: `{}': not a valid identifier\n", arg));
+                        continue;
+                    }
+
+                    if let Some(value) = value {
+                        self.variables.set(name, value.to_string());
+                    }
+                    self.variables.export(name, exported);
+                }
+            }
+            SystemCall::Unset(command_args) => {
+                // `-f` removes functions, `-v` (the default) variables
+                let functions = command_args.first().is_some_and(|arg| arg == "-f");
+                let names = match command_args.first().map(String::as_str) {
+                    Some("-f" | "-v") => &command_args[1..],
+                    _ => &command_args[..],
+                };
+
+                for name in names {
+                    if functions {
+                        self.functions.remove(name);
+                    } else if is_valid_name(name) {
+                        self.variables.unset(name);
+                    } else {
+                        io.error(&format!("unset: `{}': not a valid identifier\n", name));
+                    }
+                }
+            }
+            SystemCall::Alias(command_args) => {
+                if command_args.is_empty() {
+                    for (name, text) in &self.aliases {
+                        io.write(&alias_definition(name, text));
+                    }
+                }
+                for arg in &command_args {
+                    match arg.split_once('=') {
+                        Some((name, _)) if name.is_empty() || name.contains(|c: char| c.is_whitespace() || "/$`'\"|&;<>()".contains(c)) => {
+                            io.error(&format!("alias: `{}': invalid alias name\n", name));
+                        }
+                        Some((name, text)) => {
+                            self.aliases.insert(name.to_string(), text.to_string());
+                        }
+                        None => match self.aliases.get(arg) {
+                            Some(text) => io.write(&alias_definition(arg, text)),
+                            None => io.error(&format!("alias: {}: not found\n", arg)),
+                        },
+                    }
+                }
+            }
+            SystemCall::Unalias(command_args) => {
+                if command_args.first().is_some_and(|arg| arg == "-a") {
+                    self.aliases.clear();
+                    return None;
+                }
+                for name in &command_args {
+                    if self.aliases.remove(name).is_none() {
+                        io.error(&format!("unalias: {}: not found\n", name));
+                    }
+                }
+            }
+            SystemCall::Local(command_args) => {
+                let Some(frame) = self.frames.last_mut() else {
+                    io.error("local: can only be used in a function\n");
+                    return None;
+                };
+
+                for arg in &command_args {
+                    let (name, value) = match arg.split_once('=') {
+                        Some((name, value)) => (name, value.to_string()),
+                        None => (arg.as_str(), String::new()),
+                    };
+                    if !is_valid_name(name) {
+                        io.error(&format!("local: `{}': not a valid identifier\n", arg));
+                        continue;
+                    }
+
+                    // the first `local` of a name in a call remembers what it hides
+                    if !frame.iter().any(|(saved, _)| saved == name) {
+                        frame.push((name.to_string(), self.variables.get_variable(name).cloned()));
+                    }
+                    self.variables.set(name, value);
+                }
+            }
+            SystemCall::Source(path, args) => self.run_source(&path, args, io),
+            SystemCall::Shift(count) => {
+                if count > self.positional.len() {
+                    io.error(&format!("shift: {}: shift count out of range\n", count));
+                    return None;
+                }
+                self.positional.drain(..count);
+            }
+            SystemCall::Getopts(letters, name, args) => {
+                if !is_valid_name(&name) {
+                    io.error(&format!("getopts: `{}': not a valid identifier\n", name));
+                    return None;
+                }
+                let args = args.unwrap_or_else(|| self.positional.clone());
+                io.status = self.getopts(&letters, &name, &args, io);
+            }
+            SystemCall::Return(status) => {
+                if self.frames.is_empty() && self.sourcing == 0 {
+                    io.error("return: can only `return' from a function or sourced script\n");
+                    return None;
+                }
+                io.status = status.unwrap_or(self.last_status);
+                self.flow = Flow::Return;
+            }
+            SystemCall::Env => {
+                for (name, variable) in self.environment().iter() {
+                    io.write(&format!("{}={}\n", name, variable.value));
+                }
+            }
+            SystemCall::Set(command_args) => {
+                if command_args.is_empty() {
+                    for (name, variable) in self.all_variables().iter() {
+                        io.write(&format!("{}={}\n", name, quote(&variable.value)));
+                    }
+                    return None;
+                }
+
+                let mut options = self.options;
+                match options.apply(&command_args) {
+                    Ok(rest) => {
+                        self.options = options;
+                        if let Some(positional) = rest.positional {
+                            self.positional = positional;
+                        }
+                        if rest.list {
+                            for name in ShellOptions::NAMES {
+                                let on = options.get(name).unwrap_or_default();
+                                io.write(&format!("{}\t{}\n", name, if on { "on" } else { "off" }));
+                            }
+                        }
+                    }
+                    Err(err) => {
+                        io.error(&format!("set: {}\n", err));
+                        io.status = 2;
+                    }
+                }
+            }
+            SystemCall::Whoami => {
+                io.write(&format!("{}\n", self.user));
+            }
+            SystemCall::History => {
+                for (index, command) in self.history.iter().enumerate() {
+                    io.write(&format!("{}: {}\n", index + 1, command));
+                }
+            }
+            SystemCall::Sync => {
+                if let Some(vfs) = self.vfs.as_ref() {
+                    if let Err(err) = vfs.sync() {
+                        io.error(&format!("sync: {}\n", err));
+                    }
+                }
+            }
+            SystemCall::ExitStatus(status) => io.status = status,
+            SystemCall::Display(text) => io.write(&text),
+            SystemCall::DisplayError(text) => io.error(&text),
+            SystemCall::DisplayNewLine => io.write("\n"),
+            SystemCall::Break(count) | SystemCall::Continue(count) => {
+                if self.loop_depth == 0 {
+                    let name = if matches!(system_call, SystemCall::Break(_)) { "break" } else { "continue" };
+                    io.error(&format!("{}: only meaningful in a `for', `while', or `until' loop\n", name));
+                    return None;
+                }
+                let count = count.min(self.loop_depth);
+                self.flow = match system_call {
+                    SystemCall::Break(_) => Flow::Break(count),
+                    _ => Flow::Continue(count),
+                };
+            }
+            SystemCall::Exit(status) => {
+                let status = status.unwrap_or(self.last_status);
+                io.status = status;
+                // `exit` after `su` ends only that login
+                if let Some(login) = self.logins.pop() {
+                    if let Some(vfs) = self.vfs.as_mut() {
+                        vfs.user = login.user.clone();
+                        vfs.umask = login.umask;
+                        // the dir can be gone by now, then the shell stays where it is
+                        let _ = vfs.change_dir(vec![login.path]);
+                    }
+                    self.user = login.user;
+                    return None;
+                }
+                self.flow = Flow::Exit;
+                return Some(SystemCall::Exit(Some(status)));
+            }
+            SystemCall::Clear => return Some(system_call),
+        }
+
+        None
+    }
+}
+
+/// Arithmetic reads and assigns the shell variables.
+impl Scope for Session {
+    fn get(&self, name: &str) -> Option<String> {
+        self.lookup(name)
+    }
+
+    fn set(&mut self, name: &str, value: String) {
+        self.variables.set(name, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> Session {
+        Session::new(Some(VFS::new(SHELL_USER.to_string(), "./storage".to_string()).unwrap()))
+    }
+
+    fn run(session: &mut Session, line: &str) -> String {
+        session
+            .run_line(line)
+            .into_iter()
+            .map(|call| match call {
+                SystemCall::Display(text) => text,
+                SystemCall::DisplayNewLine => "\n".to_string(),
+                _ => String::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_pipelines() {
+        let mut session = session();
+
+        assert_eq!(run(&mut session, "ls /test"), "\nsecond_dir xd.pl \n");
+        assert_eq!(run(&mut session, "ls /test | cat"), "\nsecond_dir\nxd.pl\n");
+        assert_eq!(run(&mut session, "ls / | grep -c pl"), "\n7\n");
+        assert_eq!(run(&mut session, "ls / | grep ^h | grep -v history"), "\nhome.pl\n");
+        assert_eq!(run(&mut session, "cat /whoami.pl | grep -n who"), "\n1:whoami\n");
+        assert_eq!(run(&mut session, "echo one two | cat - | cat"), "\none two\n");
+        assert_eq!(run(&mut session, "whoami | grep -c root"), "\n1\n");
+    }
+
+    #[test]
+    fn test_list_operands() {
+        let mut session = session();
+
+        assert_eq!(
+            run(&mut session, "ls /*.pl"),
+            "\n/args.pl /errors.pl /history.pl /home.pl /lscd.pl /stage_5.pl /whoami.pl \n"
+        );
+        assert_eq!(
+            run(&mut session, "ls /whoami.pl /test /home.pl /test/second_dir"),
+            "\n/home.pl /whoami.pl \n\n/test:\nsecond_dir xd.pl \n\n/test/second_dir:\nexample_file \n"
+        );
+        assert_eq!(run(&mut session, "ls /whoami.pl /home.pl | cat"), "\n/home.pl\n/whoami.pl\n");
+        assert_eq!(run(&mut session, "ls /test /test/second_dir"), "\n/test:\nsecond_dir xd.pl \n\n/test/second_dir:\nexample_file \n");
+        assert_eq!(
+            run(&mut session, "ls /test missing /test/second_dir; echo $?"),
+            "\nls: missing: no such file or directory\n/test:\nsecond_dir xd.pl \n\n/test/second_dir:\nexample_file \n2\n"
+        );
+    }
+
+    #[test]
+    fn test_pipeline_errors() {
+        let mut session = session();
+
+        // stderr is never piped, it goes straight to the terminal
+        assert_eq!(
+            run(&mut session, "cat /missing | grep -c x"),
+            "\ncat: missing: no such file or directory\n0\n"
+        );
+        assert_eq!(run(&mut session, "nope | echo x"), "\nnope: command not found\nx\n");
+        assert_eq!(run(&mut session, "ls | | x"), "\nsyntax error at column 6: unexpected token `|'\n");
+        assert_eq!(run(&mut session, ""), "\n");
+        assert_eq!(run(&mut session, "history | grep -c ls"), "\n2\n");
+    }
+
+    #[test]
+    fn test_exit_statuses() {
+        let mut session = session();
+        let status = |session: &mut Session, line: &str| {
+            run(session, line);
+            session.last_status
+        };
+
+        assert_eq!(status(&mut session, "true"), 0);
+        assert_eq!(status(&mut session, "false"), 1);
+        assert_eq!(status(&mut session, "cd /missing"), 1);
+        assert_eq!(status(&mut session, "nope"), 127);
+        assert_eq!(status(&mut session, "stat"), 2);
+        assert_eq!(status(&mut session, "ls |"), 2);
+        assert_eq!(status(&mut session, "ls / | grep -q"), 2);
+        assert_eq!(status(&mut session, "ls / | grep xyz"), 1);
+        assert_eq!(status(&mut session, "ls / | grep pl"), 0);
+        assert_eq!(status(&mut session, "false | true"), 0);
+        assert_eq!(status(&mut session, "! cd /missing"), 0);
+        assert_eq!(status(&mut session, "echo x > /test"), 1);
+        assert_eq!(run(&mut session, "false; echo $?"), "\n1\n");
+    }
+
+    #[test]
+    fn test_lists() {
+        let mut session = session();
+
+        assert_eq!(
+            run(&mut session, "cd missing || echo fallback"),
+            "\ncd: missing: no such file or directory\nfallback\n"
+        );
+        assert_eq!(run(&mut session, "cd /test && pwd; cd / && pwd"), "\n/test\n/\n");
+        assert_eq!(run(&mut session, "false && echo no || echo yes"), "\nyes\n");
+        assert_eq!(run(&mut session, "true || echo no && echo yes"), "\nyes\n");
+        assert_eq!(run(&mut session, "! true || echo negated"), "\nnegated\n");
+        assert_eq!(run(&mut session, "echo a; exit; echo b"), "\na\n");
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let mut session = session();
+
+        assert_eq!(run(&mut session, "if cd /missing; then echo a; elif true; then echo b; fi"), "\ncd: missing: no such file or directory\nb\n");
+        assert_eq!(run(&mut session, "if false; then echo a; else echo c; fi; echo $?"), "\nc\n0\n");
+        assert_eq!(run(&mut session, "if false; then echo a; fi; echo $?"), "\n0\n");
+        assert_eq!(run(&mut session, "for f in /test/*; do echo $f; done"), "\n/test/second_dir\n/test/xd.pl\n");
+        assert_eq!(run(&mut session, "for x in a b c; do echo $x; done | grep -c ."), "\n3\n");
+
+        run(&mut session, "N=");
+        assert_eq!(run(&mut session, "while ! echo $N | grep -c xxx; do N=x$N; done"), "\n0\n0\n0\n1\n");
+        assert_eq!(run(&mut session, "until echo $N | grep x; do N=; done; echo $N"), "\nxxx\nxxx\n");
+
+        let case = "for f in home.pl test x; do case $f in *.pl) echo script;; test|x) echo $f; esac; done";
+        assert_eq!(run(&mut session, case), "\nscript\ntest\nx\n");
+        assert_eq!(run(&mut session, "case '*' in \\*) echo star;; *) echo any;; esac"), "\nstar\n");
+        assert_eq!(run(&mut session, "case a in b) echo b;; esac; echo $?"), "\n0\n");
+    }
+
+    #[test]
+    fn test_break_continue() {
+        let mut session = session();
+
+        let line = "for x in 1 2 3 4; do if echo $x | grep 2; then continue; fi; echo x$x; if echo $x | grep 3; then break; fi; done";
+        assert_eq!(run(&mut session, line), "\nx1\n2\nx3\n3\n");
+        assert_eq!(run(&mut session, "for x in a b; do for y in 1 2; do echo $x$y; continue 2; done; done"), "\na1\nb1\n");
+        assert_eq!(run(&mut session, "while true; do while true; do break 5; done; echo no; done; echo out"), "\nout\n");
+        assert_eq!(run(&mut session, "for x in a b; do exit; done; echo no"), "\n");
+
+        assert_eq!(
+            run(&mut session, "break; echo $?"),
+            "\nbreak: only meaningful in a `for', `while', or `until' loop\n1\n"
+        );
+        assert_eq!(
+            run(&mut session, "for x in a; do break 0; done"),
+            "\nbreak: 0: loop count out of range\n"
+        );
+    }
+
+    #[test]
+    fn test_continuation() {
+        let mut session = session();
+
+        for line in ["for x in a b", "do", "  if echo $x | grep b", "  then", "    echo 'found", "it'"] {
+            assert_eq!(run(&mut session, line), "\n");
+            assert!(session.is_continuing());
+        }
+        assert_eq!(run(&mut session, "  fi"), "\n");
+        assert_eq!(run(&mut session, "done"), "\nb\nfound\nit\n");
+        assert!(!session.is_continuing());
+        assert_eq!(session.history.last().unwrap().lines().count(), 8);
+
+        run(&mut session, "ls / |");
+        assert_eq!(run(&mut session, "grep -c pl"), "\n7\n");
+
+        run(&mut session, "while true");
+        assert!(session.end_of_input().iter().any(|call| matches!(
+            call,
+            SystemCall::Display(text) if text == "syntax error at column 11: unexpected end of file, expected `do'\n"
+        )));
+        assert!(!session.is_continuing());
+        assert_eq!(run(&mut session, "echo $?"), "\n2\n");
+    }
+
+    #[test]
+    fn test_set_options() {
+        let mut session = session();
+
+        assert_eq!(run(&mut session, "set -o | grep errexit"), "\nerrexit\toff\n");
+        run(&mut session, "set -eu");
+        assert_eq!(run(&mut session, "set -o"), "\nerrexit\ton\nnounset\ton\nxtrace\toff\n");
+        assert_eq!(run(&mut session, "set +o errexit +u; echo $?"), "\n0\n");
+        assert!(!session.options.errexit && !session.options.nounset);
+        assert_eq!(run(&mut session, "set -q; echo $?"), "\nset: -q: invalid option\n2\n");
+        assert_eq!(
+            run(&mut session, "set -o nothing; echo $?"),
+            "\nset: nothing: invalid option name\n2\n"
+        );
+
+        // errexit stops the line, but not for conditions, `!` or the left of && and ||
+        run(&mut session, "set -e");
+        assert_eq!(run(&mut session, "echo a; cat /missing; echo b"), "\na\ncat: missing: no such file or directory\n");
+        assert_eq!(run(&mut session, "false && echo no; echo yes"), "\nyes\n");
+        assert_eq!(run(&mut session, "! true; false || echo yes"), "\nyes\n");
+        assert_eq!(run(&mut session, "if false; then echo no; fi; while false; do :; done; echo yes"), "\nyes\n");
+        assert_eq!(run(&mut session, "f() { false; echo no; }; f; echo no"), "\n");
+        assert_eq!(run(&mut session, "x=$(false; echo no); echo no"), "\n");
+        run(&mut session, "set +e");
+
+        run(&mut session, "set -u");
+        assert_eq!(
+            run(&mut session, "echo $UNSET; echo $?"),
+            "\nUNSET: unbound variable\n1\n"
+        );
+        assert_eq!(run(&mut session, "echo $# $@"), "\n0\n");
+        run(&mut session, "set +u");
+
+        run(&mut session, "set -x");
+        assert_eq!(
+            run(&mut session, "N='a b'; echo $N | grep -c a"),
+            "\n+ N='a b'\n+ echo a b\n+ grep -c a\n1\n"
+        );
+    }
+
+    /// Runs `lines` the way the window runs a startapp script.
+    fn script(session: &mut Session, lines: &[&str]) -> String {
+        session.begin_script("home.pl", vec![]);
+        let mut calls = vec![];
+        for (index, line) in lines.iter().enumerate() {
+            session.set_script_line(index + 1);
+            if line.starts_with('#') {
+                continue;
+            }
+            calls.extend(session.run_line(line));
+            if session.is_script_stopped() {
+                break;
+            }
+        }
+        calls.extend(session.end_script());
+
+        calls
+            .into_iter()
+            .filter_map(|call| match call {
+                SystemCall::Display(text) => Some(text),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_script_diagnostics() {
+        let mut session = session();
+
+        assert_eq!(script(&mut session, &["# home", "cd /test", "ls"]), "second_dir xd.pl \nhome.pl: startapp succeeded\n");
+        assert_eq!(
+            script(&mut session, &["cd /missing", "# comment", "if true", "then", "  cat /x | cat", "fi", "echo done"]),
+            "home.pl:1: cd: missing: no such file or directory\n\
+             home.pl:5: cat: x: no such file or directory\n\
+             done\n\
+             home.pl: startapp failed: 2 errors\n"
+        );
+        assert_eq!(
+            script(&mut session, &["set -e", "echo one", "", "cat /x", "echo two"]),
+            "one\nhome.pl:4: cat: x: no such file or directory\nhome.pl: startapp stopped at line 4 by set -e\n"
+        );
+        run(&mut session, "set +e");
+
+        assert_eq!(
+            script(&mut session, &["echo (", "f() {", "  echo $(cat /y)", "}", "f", "x=$(f)", "while true"]),
+            "home.pl:1: syntax error at column 6: unexpected token `('\n\
+             home.pl:5: cat: y: no such file or directory\n\n\
+             home.pl:6: cat: y: no such file or directory\n\
+             home.pl:7: syntax error at column 11: unexpected end of file, expected `do'\n\
+             home.pl: startapp failed: 4 errors\n"
+        );
+
+        // the prompt is back to plain errors after the script
+        assert_eq!(run(&mut session, "cat /x"), "\ncat: x: no such file or directory\n");
+    }
+
+    #[test]
+    fn test_source() {
+        let mut session = session();
+        let script = "# sets things up\n\
+                      GREETING=hello\n\
+                      alias hi='echo $GREETING'\n\
+                      hi $# \"$@\"\n\
+                      for x in a b\n\
+                      do echo $x; done\n\
+                      cd /test\n\
+                      return 3\n\
+                      echo unreachable\n";
+        session.vfs.as_mut().unwrap().write_file("/setup.pl", script.as_bytes(), false).unwrap();
+
+        assert_eq!(run(&mut session, "source /setup.pl one two; echo $?"), "\nhello 2 one two\na\nb\n3\n");
+        assert_eq!(run(&mut session, "echo $GREETING $PWD; hi"), "\nhello /test\nhello\n");
+        assert_eq!(run(&mut session, ". ../setup.pl | grep -c o"), "\n1\n");
+        assert_eq!(run(&mut session, "cd /; . /whoami.pl"), "\nroot\n");
+
+        // the positional arguments come back, without any the script sees them
+        session.vfs.as_mut().unwrap().write_file("/args.sh", b"echo $# $1", false).unwrap();
+        run(&mut session, "f() { . /args.sh; . /args.sh x; echo $1; }");
+        assert_eq!(run(&mut session, "f a b"), "\n2 a\n1 x\na\n");
+
+        assert_eq!(
+            run(&mut session, "source; echo $?"),
+            "\nsource: filename argument required\nsource: usage: source filename [arguments]\n2\n"
+        );
+        assert_eq!(run(&mut session, ". /missing"), "\nsource: missing: no such file or directory\n");
+
+        session.vfs.as_mut().unwrap().write_file("/bad.sh", b"echo one\nif true\nthen echo )\nfi\necho two", false).unwrap();
+        assert_eq!(
+            run(&mut session, ". /bad.sh; echo $?"),
+            "\none\n/bad.sh:3: syntax error at column 11: unexpected token `)'\n2\n"
+        );
+        session.vfs.as_mut().unwrap().write_file("/open.sh", b"while true\ndo", false).unwrap();
+        assert_eq!(
+            run(&mut session, ". /open.sh"),
+            "\n/open.sh:2: syntax error at column 3: unexpected end of file\n"
+        );
+
+        // `exit` in a sourced script ends the shell with its status
+        session.vfs.as_mut().unwrap().write_file("/exit.sh", b"echo bye\nexit 3\necho unreachable", false).unwrap();
+        let calls = session.run_line(". /exit.sh; echo no");
+        assert!(calls.iter().any(|call| matches!(call, SystemCall::Exit(Some(3)))));
+        assert_eq!(run(&mut session, "echo $?"), "\n3\n");
+        let calls = session.run_line("false; exit");
+        assert!(calls.iter().any(|call| matches!(call, SystemCall::Exit(Some(1)))));
+        assert_eq!(run(&mut session, "exit x; echo $?"), "\nexit: x: numeric argument required\n2\n");
+
+        session.vfs.as_mut().unwrap().write_file("/loop.sh", b". /loop.sh", false).unwrap();
+        assert_eq!(run(&mut session, ". /loop.sh"), "\n/loop.sh: maximum nesting level exceeded (64)\n");
+    }
+
+    #[test]
+    fn test_positional() {
+        let mut session = session();
+
+        assert_eq!(run(&mut session, "echo $0 $#"), "\npolina-shell 0\n");
+        run(&mut session, "set -- 'a b' c ''");
+        assert_eq!(run(&mut session, "for x in \"$@\"; do echo \"<$x>\"; done"), "\n<a b>\n<c>\n<>\n");
+        run(&mut session, "set -- 'a b' c '' d e f g h i j k");
+        assert_eq!(run(&mut session, "echo $# $1 ${10} ${11}"), "\n11 a b j k\n");
+        assert_eq!(run(&mut session, "shift 9; echo \"$@\" \"[$*]\""), "\nj k [j k]\n");
+        assert_eq!(run(&mut session, "shift 3; echo $?"), "\nshift: 3: shift count out of range\n1\n");
+        assert_eq!(run(&mut session, "shift x; echo $?"), "\nshift: x: numeric argument required\n2\n");
+        run(&mut session, "shift 2; set -e -- x");
+        assert!(session.options.errexit);
+        run(&mut session, "set +e --");
+        assert_eq!(run(&mut session, "for x; do echo x; done; echo \"$@\"end"), "\nend\n");
+
+        // a script gets its own `$0` and arguments, the shell's are back after it
+        session.begin_script("args.pl", vec!["-v".to_string(), "-o".to_string(), "out.txt".to_string(), "in.txt".to_string()]);
+        assert_eq!(run(&mut session, "echo $0 $#"), "\nargs.pl 4\n");
+        run(&mut session, "f() { echo $0 $1; }");
+        assert_eq!(run(&mut session, "f x"), "\nargs.pl x\n");
+        assert_eq!(
+            run(&mut session, "while getopts vo:q opt; do echo $opt $OPTARG $OPTIND; done; shift $((OPTIND - 1)); echo $@"),
+            "\nv 2\no out.txt 4\nin.txt\n"
+        );
+        session.end_script();
+        assert_eq!(run(&mut session, "echo $0 $#"), "\npolina-shell 0\n");
+
+        // groups, attached arguments, `--` and mistakes
+        run(&mut session, "OPTIND=1");
+        assert_eq!(
+            run(&mut session, "while getopts ab:c opt -ca -bx -- -a; do echo $opt $OPTARG; done; echo $OPTIND"),
+            "\nc\na\nb x\n4\n"
+        );
+        run(&mut session, "OPTIND=1");
+        assert_eq!(
+            run(&mut session, "while getopts ab: opt -z -b; do echo $opt; done"),
+            "\ngetopts: illegal option -- z\n?\ngetopts: option requires an argument -- b\n?\n"
+        );
+        run(&mut session, "OPTIND=1");
+        assert_eq!(
+            run(&mut session, "while getopts :ab: opt -z -b; do echo $opt $OPTARG; done"),
+            "\n? z\n: b\n"
+        );
+        assert_eq!(run(&mut session, "getopts a; echo $?"), "\ngetopts: usage: getopts optstring name [arg ...]\n2\n");
+    }
+
+    #[test]
+    fn test_functions() {
+        let mut session = session();
+
+        run(&mut session, "greet() { echo hello $1, $# args; }");
+        assert_eq!(run(&mut session, "greet world x y | grep -c hello"), "\n1\n");
+        assert_eq!(run(&mut session, "greet 'big world'"), "\nhello big world, 1 args\n");
+
+        for line in ["check() {", "  case $1 in",  "    *.pl) return;;", "  esac", "  return 7", "}"] {
+            run(&mut session, line);
+        }
+        assert_eq!(run(&mut session, "check /missing; echo $?"), "\n7\n");
+        assert_eq!(run(&mut session, "check /home.pl && echo $?"), "\n0\n");
+
+        // `local` hides the outer value for the call only, dynamically scoped
+        run(&mut session, "X=outer; show() { echo $X; }; f() { local X=inner; show; X=changed; }");
+        assert_eq!(run(&mut session, "f; echo $X"), "\ninner\nouter\n");
+
+        run(&mut session, "count() { case $1 in xxx) return 3;; esac; echo $1; count x$1; }");
+        assert_eq!(run(&mut session, "count x; echo $?"), "\nx\nxx\n3\n");
+
+        run(&mut session, "forever() { forever; }");
+        assert_eq!(
+            run(&mut session, "forever; echo $?"),
+            "\nforever: maximum function nesting level exceeded (64)\n1\n"
+        );
+
+        assert_eq!(
+            run(&mut session, "return; local x"),
+            "\nreturn: can only `return' from a function or sourced script\nlocal: can only be used in a function\n"
+        );
+        run(&mut session, "unset -f greet");
+        assert_eq!(run(&mut session, "greet"), "\ngreet: command not found\n");
+    }
+
+    #[test]
+    fn test_aliases() {
+        let mut session = session();
+
+        run(&mut session, "alias ll='ls /test' c=cat");
+        assert_eq!(run(&mut session, "ll"), "\nsecond_dir xd.pl \n");
+        assert_eq!(run(&mut session, "echo ll; alias ll"), "\nll\nalias ll='ls /test'\n");
+        assert_eq!(run(&mut session, "alias"), "\nalias c='cat'\nalias ll='ls /test'\n");
+
+        // the alias counts from the next line, like in bash
+        assert_eq!(run(&mut session, "alias w=whoami; w"), "\nw: command not found\n");
+        assert_eq!(run(&mut session, "w"), "\nroot\n");
+
+        run(&mut session, "unalias ll");
+        assert_eq!(run(&mut session, "alias ll x/y=z"), "\nalias: ll: not found\nalias: `x/y': invalid alias name\n");
+        run(&mut session, "unalias -a");
+        assert_eq!(run(&mut session, "alias; unalias c"), "\nunalias: c: not found\n");
+    }
+
+    #[test]
+    fn test_command_substitution() {
+        let mut session = session();
+
+        assert_eq!(run(&mut session, "echo \"I am $(whoami)\" in `pwd`"), "\nI am root in /\n");
+        assert_eq!(run(&mut session, "for f in $(ls /test); do echo [$f]; done"), "\n[second_dir]\n[xd.pl]\n");
+        assert_eq!(run(&mut session, "DIR=$(cd /test; pwd); echo $DIR $(pwd)"), "\n/test /\n");
+        assert_eq!(run(&mut session, "echo \"$(echo a; echo; echo b)\" | grep -c ."), "\n2\n");
+        assert_eq!(run(&mut session, "echo $(echo $(echo nested))"), "\nnested\n");
+        assert_eq!(run(&mut session, "echo $(echo 'C:\\x')"), "\nC:\\x\n");
+
+        // the subshell's changes to the shell are undone, its exit only ends it
+        assert_eq!(run(&mut session, "X=1; echo $(X=2; exit; echo no) $X"), "\n1\n");
+        assert_eq!(run(&mut session, "X=$(cat /missing); echo [$X] $?"), "\ncat: missing: no such file or directory\n[] 1\n");
+        assert_eq!(run(&mut session, "ls $(echo /test | grep nothing)"), run(&mut session, "ls"));
+        assert_eq!(run(&mut session, "echo $(if true)"), "\nsyntax error at column 8: unexpected end of file, expected `then'\n\n");
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let mut session = session();
+
+        run(&mut session, "i=0; total=0");
+        let counter = "while true; do i=$((i + 1)); total=$((total + i * 2)); case $((i >= 4)) in 1) break;; esac; done";
+        assert_eq!(run(&mut session, &format!("{}; echo $i $total", counter)), "\n4 20\n");
+        assert_eq!(run(&mut session, "echo $((x = 7, x ** 2)) $x $((x++)) $x"), "\n49 7 7 8\n");
+        assert_eq!(run(&mut session, "echo \"$(( $(echo 6) * 7 ))\""), "\n42\n");
+        assert_eq!(run(&mut session, "echo $((10 / 0)) next; echo $?"), "\n10 / 0: division by 0\n1\n");
+        assert_eq!(run(&mut session, "N=$((1 +)); echo [$N]"), "\n1 +: syntax error: operand expected (error token is \"\")\n[]\n");
+    }
+
+    #[test]
+    fn test_variables() {
+        let mut session = session();
+
+        assert_eq!(run(&mut session, "DIR=/test NAME='my file'"), "\n");
+        assert_eq!(run(&mut session, "ls $DIR"), "\nsecond_dir xd.pl \n");
+        assert_eq!(run(&mut session, "echo \"$NAME\" $MISSING."), "\nmy file .\n");
+        assert_eq!(run(&mut session, "X='a\\b'; echo $X \"$X\"; unset X"), "\na\\b a\\b\n");
+        assert_eq!(run(&mut session, "set | grep NAME"), "\nNAME='my file'\n");
+        assert_eq!(run(&mut session, "env | grep -c ."), "\n3\n");
+
+        run(&mut session, "export DIR GREETING=hi");
+        assert_eq!(run(&mut session, "env | grep -v '^(HOME|PWD|USER)='"), "\nDIR=/test\nGREETING=hi\n");
+        assert_eq!(run(&mut session, "export"), "\nexport DIR=/test\nexport GREETING=hi\n");
+        run(&mut session, "export -n DIR");
+        assert_eq!(run(&mut session, "env | grep -v '^(HOME|PWD|USER)='"), "\nGREETING=hi\n");
+
+        // prefix assignments are exported for one command only
+        assert_eq!(
+            run(&mut session, "TMP=1 DIR=/ env | grep -v '^(HOME|PWD|USER)='"),
+            "\nDIR=/\nGREETING=hi\nTMP=1\n"
+        );
+        assert_eq!(run(&mut session, "echo $TMP $DIR"), "\n/test\n");
+
+        run(&mut session, "unset DIR GREETING");
+        assert_eq!(run(&mut session, "set"), "\nHOME=/\nNAME='my file'\nPWD=/\nUSER=root\n");
+        assert_eq!(run(&mut session, "unset 1x"), "\nunset: `1x': not a valid identifier\n");
+        assert_eq!(run(&mut session, "export a-b=1"), "\nexport: `a-b=1': not a valid identifier\n");
+    }
+
+    #[test]
+    fn test_state_variables() {
+        let mut session = session();
+        // set by hand, the shell's own value still wins
+        run(&mut session, "PWD=/nowhere");
+
+        assert_eq!(run(&mut session, "echo $PWD $USER $HOME"), "\n/ root /\n");
+        assert_eq!(run(&mut session, "cd -"), "\ncd: OLDPWD not set\n");
+        assert_eq!(run(&mut session, "echo $?"), "\n1\n");
+        run(&mut session, "cd /test/second_dir");
+        assert_eq!(run(&mut session, "echo $PWD $OLDPWD $?"), "\n/test/second_dir / 0\n");
+        assert_eq!(run(&mut session, "cd -"), "\n/\n");
+        assert_eq!(run(&mut session, "echo $OLDPWD"), "\n/test/second_dir\n");
+        run(&mut session, "cd $OLDPWD");
+        assert_eq!(run(&mut session, "pwd"), "\n/test/second_dir\n");
+
+        assert_eq!(run(&mut session, "echo $$"), format!("\n{}\n", process::id()));
+        let random: Vec<u32> = (0..5)
+            .map(|_| run(&mut session, "echo $RANDOM").trim().parse().unwrap())
+            .collect();
+        assert!(random.iter().all(|value| *value < 32768));
+        assert!(random.windows(2).any(|pair| pair[0] != pair[1]));
+
+        run(&mut session, "useradd -m ilya");
+        run(&mut session, "su - ilya");
+        assert_eq!(run(&mut session, "echo $USER $HOME $PWD"), "\nilya /home/ilya /home/ilya\n");
+        assert!(session.environment().get("USER") == Some("ilya"));
+    }
+
+    #[test]
+    fn test_accounts() {
+        let mut session = session();
+
+        // the home can't go away under the shell
+        run(&mut session, "useradd -m ilya; cd /home/ilya");
+        assert_eq!(run(&mut session, "userdel -r ilya"), "\nuserdel: /home/ilya: Device or resource busy\n");
+        assert_eq!(run(&mut session, "pwd; id ilya"), "\n/home/ilya\nuid=1000(ilya) gid=1000(ilya) groups=1000(ilya)\n");
+        run(&mut session, "cd /; userdel -r ilya");
+        assert_eq!(run(&mut session, "ls /home"), "\n");
+
+        // no way back to root without a password
+        run(&mut session, "useradd ilya; su ilya");
+        assert_eq!(run(&mut session, "su root"), "\nsu: Permission denied\n");
+        assert_eq!(run(&mut session, "su ilya; whoami"), "\nilya\n");
+
+        // `exit` leaves one `su` at a time
+        assert_eq!(run(&mut session, "exit; whoami; exit; whoami"), "\nilya\nroot\n");
+        run(&mut session, "useradd -m polina");
+        assert_eq!(run(&mut session, "su - polina; pwd; exit; whoami; pwd"), "\n/home/polina\nroot\n/\n");
+        assert_eq!(
+            run(&mut session, "su ilya; su nobody; exit; whoami"),
+            "\nsu: user nobody does not exist\nroot\n"
+        );
+    }
+
+    #[test]
+    fn test_move_copy() {
+        let mut session = session();
+
+        run(&mut session, "touch /a.txt; mv -f /a.txt /b.txt; cp -r /test /copy; mv -f /b.txt /copy");
+        assert_eq!(run(&mut session, "ls /copy"), "\nb.txt second_dir xd.pl \n");
+        assert_eq!(run(&mut session, "mv -f /copy/b.txt; echo $?"), run(&mut session, "mv; echo $?"));
+    }
+
+    fn read(session: &mut Session, path: &str) -> String {
+        let content = session.vfs.as_mut().unwrap().read_file(&path.to_string()).unwrap();
+        String::from_utf8_lossy(content).to_string()
+    }
+
+    #[test]
+    fn test_redirects() {
+        let mut session = session();
+
+        assert_eq!(run(&mut session, "ls / > /out.txt"), "\n");
+        assert_eq!(run(&mut session, "grep -c pl < /out.txt"), "\n7\n");
+        run(&mut session, "echo done >> /out.txt");
+        assert!(read(&mut session, "/out.txt").ends_with("whoami.pl\ndone\n"));
+        run(&mut session, "echo again > /out.txt");
+        assert_eq!(read(&mut session, "/out.txt"), "again\n");
+
+        assert_eq!(run(&mut session, "cat /missing 2> /err.txt"), "\n");
+        assert_eq!(read(&mut session, "/err.txt"), "cat: missing: no such file or directory\n");
+        run(&mut session, "cat /missing /whoami.pl > /all.txt 2>&1");
+        assert_eq!(read(&mut session, "/all.txt"), "cat: missing: no such file or directory\nwhoami\n");
+        run(&mut session, "cat /whoami.pl /missing &> /all.txt");
+        assert_eq!(read(&mut session, "/all.txt"), "whoami\ncat: missing: no such file or directory\n");
+        // order matters, stderr was copied before stdout went to the file
+        assert_eq!(
+            run(&mut session, "cat /missing 2>&1 > /all.txt"),
+            "\ncat: missing: no such file or directory\n"
+        );
+        assert_eq!(run(&mut session, "cat /missing 2>&1 | grep -c missing"), "\n1\n");
+        assert_eq!(run(&mut session, "> /empty.txt"), "\n");
+        assert_eq!(read(&mut session, "/empty.txt"), "");
+        run(&mut session, "cd /test; ln -s nonexist l; echo hi > l; cd /");
+        assert_eq!(read(&mut session, "/test/nonexist"), "hi\n");
+        // a `cd` inside doesn't move where the output goes
+        run(&mut session, "f() { cd /test; echo x; pwd; }; f > out; cd /; f >> out 2>&1; cd /");
+        assert_eq!(read(&mut session, "/out"), "x\n/test\nx\n/test\n");
+        assert_eq!(run(&mut session, "ls /test/out"), "\nls: out: no such file or directory\n");
+        run(&mut session, "history > /test/history.txt");
+        assert!(read(&mut session, "/test/history.txt").contains(": history > /test/history.txt\n"));
+    }
+
+    #[test]
+    fn test_redirect_errors() {
+        let mut session = session();
+
+        assert_eq!(run(&mut session, "echo x > /test"), "\n/test: is a directory\n");
+        assert_eq!(run(&mut session, "cat < /missing"), "\nmissing: no such file or directory\n");
+        assert_eq!(run(&mut session, "echo x 3> /out.txt"), "\n3: bad file descriptor\n");
+        assert_eq!(run(&mut session, "echo x > /*.pl"), "\n/args.pl /errors.pl /history.pl /home.pl /lscd.pl /stage_5.pl /whoami.pl: ambiguous redirect\n");
+
+        run(&mut session, "useradd ilya");
+        run(&mut session, "su ilya");
+        assert_eq!(run(&mut session, "echo x > /whoami.pl"), "\n/whoami.pl: Permission denied\n");
+        assert_eq!(read(&mut session, "/whoami.pl"), "whoami");
+    }
+}
@@ -1,5 +1,5 @@
 use std::collections::HashMap;
-use std::{env, vec};
+use std::vec;
 
 use iced::widget::text_editor::Content;
 
@@ -7,27 +7,137 @@ use iced::widget::text_editor::Content;
 pub enum Commands {
     Ls(Vec<String>, Option<HashMap<String, String>>),
     Cd(Vec<String>, Option<HashMap<String, String>>),
+    Cat(Vec<String>, Option<HashMap<String, String>>),
     Chown(Vec<String>),
-    Exit,
+    Chmod(Vec<String>),
+    Umask(Vec<String>),
+    UserAdd(Vec<String>),
+    UserDel(Vec<String>),
+    GroupAdd(Vec<String>),
+    GroupDel(Vec<String>),
+    Su(Vec<String>),
+    Id(Vec<String>),
+    Ln(Vec<String>),
+    ReadLink(Vec<String>),
+    Stat(Vec<String>),
+    Touch(Vec<String>),
+    Mkdir(Vec<String>),
+    Rmdir(Vec<String>),
+    Rm(Vec<String>),
+    Mv(Vec<String>),
+    Cp(Vec<String>),
+    Find(Vec<String>),
+    Tree(Vec<String>),
+    Shopt(Vec<String>),
+    Echo(Vec<String>),
+    Grep(Vec<String>),
+    Export(Vec<String>),
+    Unset(Vec<String>),
+    Env(Vec<String>),
+    Set(Vec<String>),
+    Break(Vec<String>),
+    Continue(Vec<String>),
+    Alias(Vec<String>),
+    Unalias(Vec<String>),
+    Local(Vec<String>),
+    Return(Vec<String>),
+    Source(Vec<String>),
+    Shift(Vec<String>),
+    Getopts(Vec<String>),
+    True,
+    False,
+    Exit(Vec<String>),
     Clear,
     Whoami,
+    Pwd,
     History,
+    Sync,
     NotFound(String),
     Null,
 }
 
+/// What a command asks the session to do. `Display` and `DisplayNewLine`
+/// only come out of the session, they are the terminal the last command of
+/// a pipeline writes into.
 pub enum SystemCall {
     Display(String),
+    DisplayError(String),
+    ExitStatus(i32),
     Clear,
-    Exit,
+    // the status to exit with, the last one without
+    Exit(Option<i32>),
     Whoami,
+    PrintWorkDir,
     History,
     ChangeDir(Vec<String>),
     ListDir(Vec<String>),
+    ReadFile(Vec<String>),
     ChangeOwner(Vec<String>),
+    ChangeMode(Vec<String>),
+    Umask(Vec<String>),
+    UserAdd(Vec<String>),
+    UserDel(Vec<String>),
+    GroupAdd(Vec<String>),
+    GroupDel(Vec<String>),
+    SwitchUser(Vec<String>),
+    Id(Vec<String>),
+    Link(Vec<String>),
+    ReadLink(Vec<String>),
+    Stat(Vec<String>),
+    Touch(Vec<String>),
+    MakeDir(Vec<String>),
+    RemoveDir(Vec<String>),
+    Remove(Vec<String>),
+    Move(Vec<String>),
+    Copy(Vec<String>),
+    Find(Vec<String>),
+    Tree(Vec<String>),
+    Shopt(Vec<String>),
+    Echo(Vec<String>),
+    Grep(Vec<String>),
+    Export(Vec<String>),
+    Unset(Vec<String>),
+    Env,
+    Set(Vec<String>),
+    // loop counts of `break` and `continue`
+    Break(u32),
+    Continue(u32),
+    Alias(Vec<String>),
+    Unalias(Vec<String>),
+    Local(Vec<String>),
+    // the status to return with, the last one without
+    Return(Option<i32>),
+    // the script and the positional arguments it gets
+    Source(String, Vec<String>),
+    // how many positional arguments to drop
+    Shift(usize),
+    // the option letters, the variable and the arguments, `$@` without any
+    Getopts(String, String, Option<Vec<String>>),
+    Sync,
     DisplayNewLine,
 }
 
+/// Usage errors, one message per line on stderr and status 2 like bash builtins.
+fn usage(lines: &[&str]) -> Vec<SystemCall> {
+    vec![
+        SystemCall::DisplayError(format!("{}\n", lines.join("\n"))),
+        SystemCall::ExitStatus(2),
+    ]
+}
+
+/// Loop count of `break` and `continue`, 1 without an argument.
+fn loop_count(name: &str, args: &[String]) -> Result<u32, Vec<SystemCall>> {
+    match args {
+        [] => Ok(1),
+        [count] => match count.parse::<u32>() {
+            Ok(0) => Err(usage(&[&format!("{}: {}: loop count out of range", name, count)])),
+            Ok(count) => Ok(count),
+            Err(_) => Err(usage(&[&format!("{}: {}: numeric argument required", name, count)])),
+        },
+        _ => Err(usage(&[&format!("{}: too many arguments", name)])),
+    }
+}
+
 impl Commands {
     fn format_command_args_to_env(
         &self,
@@ -36,14 +146,8 @@ impl Commands {
     ) -> Vec<String> {
         let mut result_vec = Vec::new();
 
+        // `$VAR` is expanded by the lexer already, only the extra names are left
         for arg in command {
-            if arg.starts_with('$') {
-                let key = arg.trim_start_matches('$');
-                let val = env::var(key.replace('$', "")).unwrap_or(arg.clone());
-                result_vec.push(val);
-                continue;
-            }
-
             if let Some(extra_vars) = extra {
                 if let Some(val) = extra_vars.get(arg) {
                     result_vec.push(val.clone());
@@ -61,66 +165,260 @@ impl Commands {
         match self {
             Commands::Ls(command, extra) => {
                 let replaced_args: Vec<String> =
-                    self.format_command_args_to_env(&command, extra.as_ref());
+                    self.format_command_args_to_env(command, extra.as_ref());
 
-                vec![
-                    SystemCall::DisplayNewLine,
-                    SystemCall::ListDir(replaced_args),
-                ]
+                vec![SystemCall::ListDir(replaced_args)]
             }
             Commands::Cd(command, extra) => {
                 let replaced_args: Vec<String> =
-                    self.format_command_args_to_env(&command, extra.as_ref());
-                vec![
-                    SystemCall::DisplayNewLine,
-                    SystemCall::ChangeDir(replaced_args.clone()),
-                ]
+                    self.format_command_args_to_env(command, extra.as_ref());
+                vec![SystemCall::ChangeDir(replaced_args.clone())]
+            }
+            Commands::Cat(command, extra) => {
+                let replaced_args: Vec<String> =
+                    self.format_command_args_to_env(command, extra.as_ref());
+
+                vec![SystemCall::ReadFile(replaced_args)]
+            }
+            Commands::UserAdd(commands) => {
+                if commands.is_empty() {
+                    return usage(&["useradd: need a user name", "example: useradd -m -G wheel ilya"]);
+                }
+
+                vec![SystemCall::UserAdd(commands.clone())]
+            }
+            Commands::UserDel(commands) => {
+                if commands.is_empty() {
+                    return usage(&["userdel: need a user name"]);
+                }
+
+                vec![SystemCall::UserDel(commands.clone())]
+            }
+            Commands::GroupAdd(commands) => {
+                if commands.is_empty() {
+                    return usage(&["groupadd: need a group name"]);
+                }
+
+                vec![SystemCall::GroupAdd(commands.clone())]
             }
-            Commands::Exit => {
-                vec![SystemCall::Exit]
+            Commands::GroupDel(commands) => {
+                if commands.is_empty() {
+                    return usage(&["groupdel: need a group name"]);
+                }
+
+                vec![SystemCall::GroupDel(commands.clone())]
+            }
+            Commands::Su(commands) => {
+                vec![SystemCall::SwitchUser(commands.clone())]
+            }
+            Commands::Id(commands) => {
+                vec![SystemCall::Id(commands.clone())]
+            }
+            Commands::Ln(commands) => {
+                if commands.iter().filter(|arg| !arg.starts_with('-')).count() != 2 {
+                    return usage(&["ln: need a target and a link name", "example: ln -s /test/xd.pl xd_link.pl"]);
+                }
+
+                vec![SystemCall::Link(commands.clone())]
+            }
+            Commands::ReadLink(commands) => {
+                if commands.iter().all(|arg| arg.starts_with('-')) {
+                    return usage(&["readlink: need a path"]);
+                }
+
+                vec![SystemCall::ReadLink(commands.clone())]
+            }
+            Commands::Stat(commands) => {
+                if commands.is_empty() {
+                    return usage(&["stat: need a path", "example: stat /test/xd.pl"]);
+                }
+
+                vec![SystemCall::Stat(commands.clone())]
+            }
+            Commands::Touch(commands) => {
+                if commands.iter().all(|arg| arg.starts_with('-')) {
+                    return usage(&["touch: need a path", "example: touch -m /test/xd.pl"]);
+                }
+
+                vec![SystemCall::Touch(commands.clone())]
+            }
+            Commands::Mkdir(commands) => {
+                if commands.iter().all(|arg| arg.starts_with('-')) {
+                    return usage(&["mkdir: need a path", "example: mkdir -p /test/a/b"]);
+                }
+
+                vec![SystemCall::MakeDir(commands.clone())]
+            }
+            Commands::Rmdir(commands) => {
+                if commands.is_empty() {
+                    return usage(&["rmdir: need a path"]);
+                }
+
+                vec![SystemCall::RemoveDir(commands.clone())]
             }
+            Commands::Rm(commands) => {
+                if commands.iter().all(|arg| arg.starts_with('-')) {
+                    return usage(&["rm: need a path", "example: rm -rf /test/a"]);
+                }
+
+                vec![SystemCall::Remove(commands.clone())]
+            }
+            Commands::Mv(commands) => {
+                if commands.iter().filter(|arg| !arg.starts_with('-')).count() < 2 {
+                    return usage(&["mv: need a source and a destination", "example: mv xd.pl /test/second_dir"]);
+                }
+
+                vec![SystemCall::Move(commands.clone())]
+            }
+            Commands::Cp(commands) => {
+                if commands.iter().filter(|arg| !arg.starts_with('-')).count() < 2 {
+                    return usage(&["cp: need a source and a destination", "example: cp -r /test /test_copy"]);
+                }
+
+                vec![SystemCall::Copy(commands.clone())]
+            }
+            Commands::Find(commands) => {
+                vec![SystemCall::Find(commands.clone())]
+            }
+            Commands::Tree(commands) => {
+                vec![SystemCall::Tree(commands.clone())]
+            }
+            Commands::Shopt(commands) => {
+                vec![SystemCall::Shopt(commands.clone())]
+            }
+            Commands::Echo(commands) => {
+                vec![SystemCall::Echo(commands.clone())]
+            }
+            Commands::Grep(commands) => {
+                if commands.iter().all(|arg| arg.starts_with('-')) {
+                    return usage(&["grep: need a pattern", "example: ls | grep pl"]);
+                }
+
+                vec![SystemCall::Grep(commands.clone())]
+            }
+            Commands::Export(commands) => {
+                vec![SystemCall::Export(commands.clone())]
+            }
+            Commands::Unset(commands) => {
+                vec![SystemCall::Unset(commands.clone())]
+            }
+            Commands::Env(commands) => {
+                if !commands.is_empty() {
+                    return usage(&["env: running commands is not supported", "example: env | grep HOME"]);
+                }
+
+                vec![SystemCall::Env]
+            }
+            Commands::Set(commands) => {
+                vec![SystemCall::Set(commands.clone())]
+            }
+            Commands::Break(commands) => match loop_count("break", commands) {
+                Ok(count) => vec![SystemCall::Break(count)],
+                Err(calls) => calls,
+            },
+            Commands::Continue(commands) => match loop_count("continue", commands) {
+                Ok(count) => vec![SystemCall::Continue(count)],
+                Err(calls) => calls,
+            },
+            Commands::Alias(commands) => {
+                vec![SystemCall::Alias(commands.clone())]
+            }
+            Commands::Unalias(commands) => {
+                if commands.is_empty() {
+                    return usage(&["unalias: usage: unalias [-a] name [name ...]"]);
+                }
+
+                vec![SystemCall::Unalias(commands.clone())]
+            }
+            Commands::Local(commands) => {
+                vec![SystemCall::Local(commands.clone())]
+            }
+            Commands::Return(commands) => match commands.as_slice() {
+                [] => vec![SystemCall::Return(None)],
+                // statuses are 0 to 255 like in bash
+                [status] => match status.parse::<i32>() {
+                    Ok(status) => vec![SystemCall::Return(Some(status & 0xff))],
+                    Err(_) => usage(&[&format!("return: {}: numeric argument required", status)]),
+                },
+                _ => usage(&["return: too many arguments"]),
+            },
+            Commands::Source(commands) => match commands.split_first() {
+                Some((path, args)) => vec![SystemCall::Source(path.clone(), args.to_vec())],
+                None => usage(&["source: filename argument required", "source: usage: source filename [arguments]"]),
+            },
+            Commands::Shift(commands) => match commands.as_slice() {
+                [] => vec![SystemCall::Shift(1)],
+                [count] => match count.parse::<usize>() {
+                    Ok(count) => vec![SystemCall::Shift(count)],
+                    Err(_) => usage(&[&format!("shift: {}: numeric argument required", count)]),
+                },
+                _ => usage(&["shift: too many arguments"]),
+            },
+            Commands::Getopts(commands) => match commands.as_slice() {
+                [letters, name] => vec![SystemCall::Getopts(letters.clone(), name.clone(), None)],
+                [letters, name, args @ ..] => {
+                    vec![SystemCall::Getopts(letters.clone(), name.clone(), Some(args.to_vec()))]
+                }
+                _ => usage(&["getopts: usage: getopts optstring name [arg ...]"]),
+            },
+            Commands::True => {
+                vec![SystemCall::ExitStatus(0)]
+            }
+            Commands::False => {
+                vec![SystemCall::ExitStatus(1)]
+            }
+            Commands::Exit(commands) => match commands.as_slice() {
+                [] => vec![SystemCall::Exit(None)],
+                [status] => match status.parse::<i32>() {
+                    Ok(status) => vec![SystemCall::Exit(Some(status & 0xff))],
+                    Err(_) => usage(&[&format!("exit: {}: numeric argument required", status)]),
+                },
+                _ => usage(&["exit: too many arguments"]),
+            },
             Commands::NotFound(command) => {
                 vec![
-                    SystemCall::DisplayNewLine,
-                    SystemCall::Display(format!("{}: command not found", command)),
-                    SystemCall::DisplayNewLine,
+                    SystemCall::DisplayError(format!("{}: command not found\n", command)),
+                    SystemCall::ExitStatus(127),
                 ]
             }
             Commands::Whoami => {
-                vec![
-                    SystemCall::DisplayNewLine,
-                    SystemCall::Whoami,
-                    SystemCall::DisplayNewLine,
-                ]
+                vec![SystemCall::Whoami]
+            }
+            Commands::Pwd => {
+                vec![SystemCall::PrintWorkDir]
             }
             Commands::History => {
-                vec![
-                    SystemCall::DisplayNewLine,
-                    SystemCall::History,
-                    SystemCall::DisplayNewLine,
-                ]
+                vec![SystemCall::History]
             }
             Commands::Clear => {
                 vec![SystemCall::Clear]
             }
+            Commands::Sync => {
+                vec![SystemCall::Sync]
+            }
             Commands::Null => {
-                vec![SystemCall::DisplayNewLine]
+                vec![]
             }
             Commands::Chown(commands) => {
                 if commands.len() != 2 {
-                    return vec![
-                        SystemCall::DisplayNewLine,
-                        SystemCall::Display(format!("chown: need 2 arguments")),
-                        SystemCall::DisplayNewLine,
-                        SystemCall::Display(format!("example: chown root home.pl")),
-                        SystemCall::DisplayNewLine,
-                    ];
+                    return usage(&["chown: need 2 arguments", "example: chown root home.pl"]);
                 }
 
-                vec![
-                    SystemCall::ChangeOwner(commands.clone()),
-                    SystemCall::DisplayNewLine
-                ]
+                vec![SystemCall::ChangeOwner(commands.clone())]
+            }
+            Commands::Chmod(commands) => {
+                if commands.len() < 2 {
+                    return usage(&["chmod: need at least 2 arguments", "example: chmod 755 home.pl, chmod u+x,go-w home.pl"]);
+                }
+
+                vec![SystemCall::ChangeMode(commands.clone())]
+            }
+            Commands::Umask(commands) => {
+                if commands.len() > 1 {
+                    return usage(&["umask: too many args"]);
+                }
+
+                vec![SystemCall::Umask(commands.clone())]
             }
         }
     }
@@ -128,29 +426,69 @@ impl Commands {
     pub fn get_last_command_frame(prefix: &str, data: &Content) -> String {
         let binding = data.text();
         let result: Vec<&str> = binding.split(prefix).collect();
-        return result.last().unwrap().to_string();
+        result.last().unwrap().to_string()
     }
 
-    pub fn parse_from_string(input: String) -> Commands {
-        let parts: Vec<&str> = input.split_whitespace().collect();
+    /// Builds a command from already expanded words, the first one is the name.
+    pub fn parse_from_words(parts: Vec<String>) -> Commands {
         let mut args: Vec<String> = vec![];
 
-        if parts.len() > 0 {
-            args = parts[1..].iter().map(|s| s.to_string()).collect();
+        if !parts.is_empty() {
+            args = parts[1..].to_vec();
         }
 
-        if parts.len() == 0 || parts[0].starts_with('#') {
+        if parts.is_empty() {
             Commands::Null
         } else {
-            match parts[0] {
+            match parts[0].as_str() {
                 "ls" => Commands::Ls(args, None),
                 "cd" => Commands::Cd(args, None),
-                "exit" => Commands::Exit,
+                "cat" => Commands::Cat(args, None),
+                "exit" => Commands::Exit(args),
                 "clear" => Commands::Clear,
                 "whoami" => Commands::Whoami,
+                "pwd" => Commands::Pwd,
                 "history" => Commands::History,
+                "sync" => Commands::Sync,
                 "chown" => Commands::Chown(args),
-                _ => Commands::NotFound(parts.get(0).unwrap_or(&"null").to_string()),
+                "chmod" => Commands::Chmod(args),
+                "umask" => Commands::Umask(args),
+                "useradd" => Commands::UserAdd(args),
+                "userdel" => Commands::UserDel(args),
+                "groupadd" => Commands::GroupAdd(args),
+                "groupdel" => Commands::GroupDel(args),
+                "su" => Commands::Su(args),
+                "id" => Commands::Id(args),
+                "ln" => Commands::Ln(args),
+                "readlink" => Commands::ReadLink(args),
+                "stat" => Commands::Stat(args),
+                "touch" => Commands::Touch(args),
+                "mkdir" => Commands::Mkdir(args),
+                "rmdir" => Commands::Rmdir(args),
+                "rm" => Commands::Rm(args),
+                "mv" => Commands::Mv(args),
+                "cp" => Commands::Cp(args),
+                "find" => Commands::Find(args),
+                "tree" => Commands::Tree(args),
+                "shopt" => Commands::Shopt(args),
+                "echo" => Commands::Echo(args),
+                "grep" => Commands::Grep(args),
+                "export" => Commands::Export(args),
+                "unset" => Commands::Unset(args),
+                "env" => Commands::Env(args),
+                "set" => Commands::Set(args),
+                "break" => Commands::Break(args),
+                "continue" => Commands::Continue(args),
+                "alias" => Commands::Alias(args),
+                "unalias" => Commands::Unalias(args),
+                "local" => Commands::Local(args),
+                "return" => Commands::Return(args),
+                "source" | "." => Commands::Source(args),
+                "shift" => Commands::Shift(args),
+                "getopts" => Commands::Getopts(args),
+                "true" => Commands::True,
+                "false" => Commands::False,
+                _ => Commands::NotFound(parts[0].clone()),
             }
         }
     }
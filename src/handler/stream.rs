@@ -0,0 +1,88 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Where a stream of a command ends up after its redirections.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    // where the pipeline sends that stream, the next command or the terminal
+    Stdout,
+    Stderr,
+    File(String),
+}
+
+/// Streams of one running command. Stdin is everything the previous command
+/// wrote, output chunks keep their order so stdout and stderr interleave on
+/// the terminal like they were written.
+#[derive(Debug)]
+pub struct CommandIo {
+    pub stdin: String,
+    pub output: Vec<(Stream, String)>,
+    pub stdout: Target,
+    pub stderr: Target,
+    // exit status, 0 is success
+    pub status: i32,
+    // stdout of the pipeline goes into the next command
+    piped: bool,
+}
+
+impl CommandIo {
+    pub fn new(stdin: String, piped: bool) -> Self {
+        CommandIo {
+            stdin,
+            output: vec![],
+            stdout: Target::Stdout,
+            stderr: Target::Stderr,
+            status: 0,
+            piped,
+        }
+    }
+
+    /// Whether stdout is read by a person, `ls` lays out its names by it.
+    pub fn is_terminal(&self) -> bool {
+        match self.stdout {
+            Target::Stdout => !self.piped,
+            Target::Stderr => true,
+            Target::File(_) => false,
+        }
+    }
+
+    pub fn write(&mut self, text: &str) {
+        self.push(Stream::Stdout, text);
+    }
+
+    /// Writes to stderr, the command has failed with status 1 unless it
+    /// set a status of its own.
+    pub fn error(&mut self, text: &str) {
+        if self.status == 0 {
+            self.status = 1;
+        }
+        self.push(Stream::Stderr, text);
+    }
+
+    /// Writes to `stream` as is, the status stays. Output of the commands
+    /// inside a compound command goes through here.
+    pub fn push(&mut self, stream: Stream, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        match self.output.last_mut() {
+            Some((last, chunk)) if *last == stream => chunk.push_str(text),
+            _ => self.output.push((stream, text.to_string())),
+        }
+    }
+
+    /// The output chunks with redirections applied, in the order they were written.
+    pub fn routed(self) -> Vec<(Target, String)> {
+        let CommandIo { output, stdout, stderr, .. } = self;
+        output
+            .into_iter()
+            .map(|(stream, text)| match stream {
+                Stream::Stdout => (stdout.clone(), text),
+                Stream::Stderr => (stderr.clone(), text),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variable {
+    pub value: String,
+    pub exported: bool,
+}
+
+/// Shell variables of a session, kept sorted for `set` and `env`. Nothing
+/// comes from the host, only exported ones are passed on to child scripts.
+#[derive(Debug, Default, Clone)]
+pub struct Variables {
+    table: BTreeMap<String, Variable>,
+}
+
+impl Variables {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.table.get(name).map(|variable| variable.value.as_str())
+    }
+
+    pub fn get_variable(&self, name: &str) -> Option<&Variable> {
+        self.table.get(name)
+    }
+
+    /// Sets the value, an exported variable stays exported.
+    pub fn set(&mut self, name: &str, value: String) {
+        match self.table.get_mut(name) {
+            Some(variable) => variable.value = value,
+            None => {
+                self.table.insert(name.to_string(), Variable { value, exported: false });
+            }
+        }
+    }
+
+    /// Sets the export flag, a missing variable is created empty.
+    pub fn export(&mut self, name: &str, exported: bool) {
+        self.table
+            .entry(name.to_string())
+            .or_insert_with(|| Variable {
+                value: String::new(),
+                exported,
+            })
+            .exported = exported;
+    }
+
+    /// Puts back a variable as it was saved with [`Variables::get_variable`].
+    pub fn restore(&mut self, name: &str, variable: Option<Variable>) {
+        match variable {
+            Some(variable) => {
+                self.table.insert(name.to_string(), variable);
+            }
+            None => self.unset(name),
+        }
+    }
+
+    pub fn unset(&mut self, name: &str) {
+        self.table.remove(name);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Variable)> {
+        self.table.iter()
+    }
+
+    /// The environment a child script starts with: exported variables only.
+    pub fn exported(&self) -> Variables {
+        Variables {
+            table: self
+                .table
+                .iter()
+                .filter(|(_, variable)| variable.exported)
+                .map(|(name, variable)| (name.clone(), variable.clone()))
+                .collect(),
+        }
+    }
+}
+
+/// Quotes a value so it can be read back by the shell, plain ones stay as is.
+pub fn quote(value: &str) -> String {
+    let plain = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./:,+@%".contains(c));
+    if plain {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variables() {
+        let mut variables = Variables::default();
+        variables.set("A", "1".to_string());
+        variables.export("B", true);
+        variables.set("B", "2".to_string());
+
+        assert_eq!(variables.get("A"), Some("1"));
+        assert!(variables.get_variable("B").unwrap().exported);
+        let exported: Vec<String> = variables.exported().iter().map(|(name, _)| name.clone()).collect();
+        assert_eq!(exported, vec!["B"]);
+
+        let saved = variables.get_variable("A").cloned();
+        variables.set("A", "changed".to_string());
+        variables.restore("A", saved);
+        assert_eq!(variables.get("A"), Some("1"));
+        variables.restore("A", None);
+        assert_eq!(variables.get("A"), None);
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("/home/ilya"), "/home/ilya");
+        assert_eq!(quote("a b"), "'a b'");
+        assert_eq!(quote("it's"), "'it'\\''s'");
+        assert_eq!(quote(""), "''");
+    }
+}
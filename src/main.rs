@@ -7,5 +7,7 @@ use window::polina_vfs::MainWindow;
 pub fn main() -> iced::Result {
     iced::application("Polina VFS", MainWindow::update, MainWindow::view)
         .window_size(iced::Size::new(600.0, 800.0))
+        .subscription(MainWindow::subscription)
+        .exit_on_close_request(false)
         .run()
 }
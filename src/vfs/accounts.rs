@@ -0,0 +1,490 @@
+use std::fmt;
+use std::io::{Error, ErrorKind};
+
+use crate::vfs::storage::VFS;
+use crate::vfs::tree::{VFSNode, VFSNodeKind, VFSTree};
+use crate::window::polina_vfs::SHELL_USER;
+
+pub const PASSWD_PATH: &str = "/etc/passwd";
+pub const GROUP_PATH: &str = "/etc/group";
+
+pub const FIRST_USER_ID: u32 = 1000;
+pub const DEFAULT_SHELL: &str = "/bin/polina";
+
+/// One `/etc/passwd` line: `name:x:uid:gid:gecos:home:shell`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PasswdEntry {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub gecos: String,
+    pub home: String,
+    pub shell: String,
+}
+
+/// One `/etc/group` line: `name:x:gid:member,member`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GroupEntry {
+    pub name: String,
+    pub gid: u32,
+    pub members: Vec<String>,
+}
+
+impl PasswdEntry {
+    pub fn parse(line: &str) -> Option<Self> {
+        let fields: Vec<&str> = line.split(':').collect();
+        if fields.len() != 7 {
+            return None;
+        }
+
+        Some(PasswdEntry {
+            name: fields[0].to_string(),
+            uid: fields[2].parse().ok()?,
+            gid: fields[3].parse().ok()?,
+            gecos: fields[4].to_string(),
+            home: fields[5].to_string(),
+            shell: fields[6].to_string(),
+        })
+    }
+}
+
+impl fmt::Display for PasswdEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:x:{}:{}:{}:{}:{}",
+            self.name, self.uid, self.gid, self.gecos, self.home, self.shell
+        )
+    }
+}
+
+impl GroupEntry {
+    pub fn parse(line: &str) -> Option<Self> {
+        let fields: Vec<&str> = line.split(':').collect();
+        if fields.len() != 4 {
+            return None;
+        }
+
+        Some(GroupEntry {
+            name: fields[0].to_string(),
+            gid: fields[2].parse().ok()?,
+            members: fields[3]
+                .split(',')
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+                .collect(),
+        })
+    }
+}
+
+impl fmt::Display for GroupEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:x:{}:{}", self.name, self.gid, self.members.join(","))
+    }
+}
+
+/// Parses a whole db file, broken lines and comments are skipped.
+pub fn parse_db<T>(data: &str, parse: fn(&str) -> Option<T>) -> Vec<T> {
+    data.lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter_map(parse)
+        .collect()
+}
+
+pub fn format_db<T: fmt::Display>(entries: &[T]) -> String {
+    entries.iter().map(|entry| format!("{}\n", entry)).collect()
+}
+
+/// One above the highest id in use, at least [`FIRST_USER_ID`]. Like
+/// `useradd`, ids freed below it are not handed out again.
+pub fn next_id(used: impl Iterator<Item = u32>) -> u32 {
+    used.filter(|id| *id >= FIRST_USER_ID)
+        .max()
+        .map(|id| id + 1)
+        .unwrap_or(FIRST_USER_ID)
+}
+
+fn valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_lowercase() || c == '_')
+        && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+        && name.len() <= 32
+}
+
+impl VFS {
+    /// Creates `/etc/passwd` and `/etc/group` with the root account if the
+    /// storage doesn't ship its own.
+    pub(super) fn ensure_accounts(&mut self) {
+        if self.tree.lookup("/etc").is_none() {
+            self.tree.add_child(
+                VFSTree::ROOT,
+                "etc".to_string(),
+                VFSNode::new(
+                    SHELL_USER.to_string(),
+                    SHELL_USER.to_string(),
+                    0o755,
+                    VFSNodeKind::Dir { children: vec![] },
+                ),
+            );
+        }
+
+        if self.tree.lookup(PASSWD_PATH).is_none() {
+            let root = PasswdEntry {
+                name: SHELL_USER.to_string(),
+                uid: 0,
+                gid: 0,
+                gecos: SHELL_USER.to_string(),
+                home: format!("/{}", SHELL_USER),
+                shell: DEFAULT_SHELL.to_string(),
+            };
+            self.write_system_file(PASSWD_PATH, format_db(&[root]));
+        }
+
+        if self.tree.lookup(GROUP_PATH).is_none() {
+            let root = GroupEntry {
+                name: SHELL_USER.to_string(),
+                gid: 0,
+                members: vec![],
+            };
+            self.write_system_file(GROUP_PATH, format_db(&[root]));
+        }
+    }
+
+    fn read_system_file(&self, path: &str) -> String {
+        match self.tree.lookup(path).map(|id| &self.tree.get(id).kind) {
+            Some(VFSNodeKind::File { content }) => String::from_utf8_lossy(content).to_string(),
+            _ => String::new(),
+        }
+    }
+
+    /// Writes an account db bypassing permissions, the caller checks who may change it.
+    fn write_system_file(&mut self, path: &str, data: String) {
+        match self.tree.lookup(path) {
+            Some(id) => {
+                let node = self.tree.get_mut(id);
+                if let VFSNodeKind::File { content } = &mut node.kind {
+                    *content = data.into_bytes();
+                }
+                node.modified();
+            }
+            None => {
+                let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+                if let Some(parent) = self.tree.lookup(parent) {
+                    self.tree.add_child(
+                        parent,
+                        name.to_string(),
+                        VFSNode::new(
+                            SHELL_USER.to_string(),
+                            SHELL_USER.to_string(),
+                            0o644,
+                            VFSNodeKind::File { content: data.into_bytes() },
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    pub fn users(&self) -> Vec<PasswdEntry> {
+        parse_db(&self.read_system_file(PASSWD_PATH), PasswdEntry::parse)
+    }
+
+    pub fn groups(&self) -> Vec<GroupEntry> {
+        parse_db(&self.read_system_file(GROUP_PATH), GroupEntry::parse)
+    }
+
+    pub fn find_user(&self, name: &str) -> Option<PasswdEntry> {
+        self.users().into_iter().find(|user| user.name == name)
+    }
+
+    pub fn find_group(&self, name: &str) -> Option<GroupEntry> {
+        self.groups().into_iter().find(|group| group.name == name)
+    }
+
+    /// Primary group first, then every group that lists the user as a member.
+    pub fn groups_of(&self, name: &str) -> Vec<GroupEntry> {
+        let groups = self.groups();
+        let primary = self
+            .find_user(name)
+            .and_then(|user| groups.iter().find(|group| group.gid == user.gid).cloned());
+
+        let mut result: Vec<GroupEntry> = primary.into_iter().collect();
+        for group in groups {
+            if group.members.iter().any(|member| member == name)
+                && !result.iter().any(|known| known.gid == group.gid)
+            {
+                result.push(group);
+            }
+        }
+
+        result
+    }
+
+    fn require_root(&self) -> Result<(), Error> {
+        if self.user == SHELL_USER {
+            Ok(())
+        } else {
+            Err(Error::new(ErrorKind::PermissionDenied, "Permission denied"))
+        }
+    }
+
+    pub fn add_group(&mut self, name: &str) -> Result<GroupEntry, Error> {
+        self.require_root()?;
+        if !valid_name(name) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("invalid group name '{}'", name)));
+        }
+
+        let mut groups = self.groups();
+        if groups.iter().any(|group| group.name == name) {
+            return Err(Error::new(ErrorKind::AlreadyExists, format!("group '{}' already exists", name)));
+        }
+
+        let group = GroupEntry {
+            name: name.to_string(),
+            gid: next_id(groups.iter().map(|group| group.gid)),
+            members: vec![],
+        };
+        groups.push(group.clone());
+        self.write_system_file(GROUP_PATH, format_db(&groups));
+
+        Ok(group)
+    }
+
+    pub fn remove_group(&mut self, name: &str) -> Result<(), Error> {
+        self.require_root()?;
+        let mut groups = self.groups();
+        let group = groups
+            .iter()
+            .find(|group| group.name == name)
+            .cloned()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("group '{}' does not exist", name)))?;
+
+        if let Some(user) = self.users().iter().find(|user| user.gid == group.gid) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("cannot remove the primary group of user '{}'", user.name),
+            ));
+        }
+
+        groups.retain(|known| known.name != name);
+        self.write_system_file(GROUP_PATH, format_db(&groups));
+
+        Ok(())
+    }
+
+    /// `useradd`: a personal group is created unless `group` is given,
+    /// `create_home` also makes `/home/<name>` owned by the new user.
+    pub fn add_user(
+        &mut self,
+        name: &str,
+        group: Option<&str>,
+        extra_groups: &[String],
+        create_home: bool,
+    ) -> Result<PasswdEntry, Error> {
+        self.require_root()?;
+        if !valid_name(name) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("invalid user name '{}'", name)));
+        }
+
+        let mut users = self.users();
+        if users.iter().any(|user| user.name == name) {
+            return Err(Error::new(ErrorKind::AlreadyExists, format!("user '{}' already exists", name)));
+        }
+        for extra in extra_groups {
+            if self.find_group(extra).is_none() {
+                return Err(Error::new(ErrorKind::NotFound, format!("group '{}' does not exist", extra)));
+            }
+        }
+
+        let primary = match group {
+            Some(group) => self
+                .find_group(group)
+                .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("group '{}' does not exist", group)))?,
+            None => self.add_group(name)?,
+        };
+
+        let user = PasswdEntry {
+            name: name.to_string(),
+            uid: next_id(users.iter().map(|user| user.uid)),
+            gid: primary.gid,
+            gecos: String::new(),
+            home: format!("/home/{}", name),
+            shell: DEFAULT_SHELL.to_string(),
+        };
+        users.push(user.clone());
+        self.write_system_file(PASSWD_PATH, format_db(&users));
+
+        if !extra_groups.is_empty() {
+            let mut groups = self.groups();
+            for known in groups.iter_mut() {
+                if extra_groups.contains(&known.name) && !known.members.iter().any(|m| m == name) {
+                    known.members.push(name.to_string());
+                }
+            }
+            self.write_system_file(GROUP_PATH, format_db(&groups));
+        }
+
+        if create_home {
+            let mut parent = VFSTree::ROOT;
+            for part in user.home.split('/').filter(|s| !s.is_empty()) {
+                parent = match self.tree.child(parent, part) {
+                    Some(id) => id,
+                    None => self.tree.add_child(
+                        parent,
+                        part.to_string(),
+                        VFSNode::new(
+                            SHELL_USER.to_string(),
+                            SHELL_USER.to_string(),
+                            0o755,
+                            VFSNodeKind::Dir { children: vec![] },
+                        ),
+                    ),
+                };
+            }
+
+            let home = self.tree.get_mut(parent);
+            home.owner = user.name.clone();
+            home.group = primary.name.clone();
+            home.mode = 0o755;
+            home.changed();
+        }
+
+        Ok(user)
+    }
+
+    pub fn remove_user(&mut self, name: &str, remove_home: bool) -> Result<(), Error> {
+        self.require_root()?;
+        if name == SHELL_USER {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("cannot remove '{}'", name)));
+        }
+
+        let mut users = self.users();
+        let user = self
+            .find_user(name)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("user '{}' does not exist", name)))?;
+        // the shell must not be left standing in a removed dir
+        let home = self.tree.lookup(&user.home).filter(|home| *home != VFSTree::ROOT);
+        if let (true, Some(home)) = (remove_home, home) {
+            self.check_not_busy(home, &user.home)?;
+        }
+        users.retain(|known| known.name != name);
+        self.write_system_file(PASSWD_PATH, format_db(&users));
+
+        let mut groups = self.groups();
+        for group in groups.iter_mut() {
+            group.members.retain(|member| member != name);
+        }
+        // drop the personal group if nobody else relies on it
+        groups.retain(|group| {
+            !(group.name == name && group.gid == user.gid && !users.iter().any(|u| u.gid == group.gid))
+        });
+        self.write_system_file(GROUP_PATH, format_db(&groups));
+
+        if let (true, Some(home)) = (remove_home, home) {
+            self.tree.remove(home);
+        }
+
+        Ok(())
+    }
+
+    /// `su`: no passwords in the VFS, so only root can enter other
+    /// accounts, anyone else just their own.
+    pub fn switch_user(&mut self, name: &str) -> Result<(), Error> {
+        if self.find_user(name).is_none() {
+            return Err(Error::new(ErrorKind::NotFound, format!("user {} does not exist", name)));
+        }
+        if name != self.user {
+            self.require_root()?;
+        }
+        self.user = name.to_string();
+
+        Ok(())
+    }
+
+    /// `id` line, e.g. `uid=1000(ilya) gid=1000(ilya) groups=1000(ilya),10(wheel)`.
+    pub fn id_string(&self, name: &str) -> Result<String, Error> {
+        let user = self
+            .find_user(name)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("'{}': no such user", name)))?;
+        let groups = self.groups_of(name);
+
+        let primary = groups
+            .iter()
+            .find(|group| group.gid == user.gid)
+            .map(|group| format!("{}({})", group.gid, group.name))
+            .unwrap_or_else(|| user.gid.to_string());
+        let all: Vec<String> = groups
+            .iter()
+            .map(|group| format!("{}({})", group.gid, group.name))
+            .collect();
+
+        Ok(format!(
+            "uid={}({}) gid={} groups={}",
+            user.uid,
+            user.name,
+            primary,
+            all.join(",")
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_passwd_roundtrip() {
+        let line = "ilya:x:1000:1000:Ilya:/home/ilya:/bin/polina";
+        let entry = PasswdEntry::parse(line).unwrap();
+        assert_eq!(entry.uid, 1000);
+        assert_eq!(entry.home, "/home/ilya");
+        assert_eq!(entry.to_string(), line);
+        assert!(PasswdEntry::parse("broken:x:1").is_none());
+    }
+
+    #[test]
+    fn test_group_roundtrip() {
+        let entry = GroupEntry::parse("wheel:x:10:root,ilya").unwrap();
+        assert_eq!(entry.members, vec!["root", "ilya"]);
+        assert_eq!(entry.to_string(), "wheel:x:10:root,ilya");
+        assert!(GroupEntry::parse("empty:x:11:").unwrap().members.is_empty());
+    }
+
+    #[test]
+    fn test_next_id() {
+        assert_eq!(next_id([0, 10].into_iter()), 1000);
+        // the gap at 1001 and 1002 stays
+        assert_eq!(next_id([0, 1000, 1003].into_iter()), 1004);
+        assert_eq!(next_id([1003, 0, 1000].into_iter()), 1004);
+    }
+
+    #[test]
+    fn test_accounts() {
+        let mut vfs = VFS::new(SHELL_USER.to_string(), "./storage".to_string()).unwrap();
+        assert_eq!(vfs.id_string("root").unwrap(), "uid=0(root) gid=0(root) groups=0(root)");
+
+        vfs.add_group("wheel").unwrap();
+        vfs.add_user("ilya", None, &["wheel".to_string()], true).unwrap();
+        assert!(vfs.add_user("ilya", None, &[], false).is_err());
+        assert!(vfs.add_user("Bad Name", None, &[], false).is_err());
+        assert_eq!(
+            vfs.id_string("ilya").unwrap(),
+            "uid=1000(ilya) gid=1001(ilya) groups=1001(ilya),1000(wheel)"
+        );
+        assert_eq!(vfs.tree.get(vfs.tree.lookup("/home/ilya").unwrap()).owner, "ilya");
+
+        vfs.switch_user("ilya").unwrap();
+        assert_eq!(vfs.home_dir(), "/home/ilya");
+        assert!(vfs.add_group("staff").is_err());
+        assert!(vfs.switch_user("nobody").is_err());
+        vfs.switch_user("ilya").unwrap();
+        assert_eq!(vfs.switch_user("root").unwrap_err().kind(), ErrorKind::PermissionDenied);
+
+        vfs.user = SHELL_USER.to_string();
+        vfs.remove_user("ilya", true).unwrap();
+        assert!(vfs.find_user("ilya").is_none());
+        assert!(vfs.find_group("ilya").is_none());
+        assert!(vfs.find_group("wheel").unwrap().members.is_empty());
+        assert!(vfs.tree.lookup("/home/ilya").is_none());
+    }
+}
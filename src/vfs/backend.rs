@@ -0,0 +1,696 @@
+use serde::{Deserialize, Serialize};
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use crate::vfs::permissions::{parse_octal, DEFAULT_DIR_MODE, DEFAULT_FILE_MODE, DEFAULT_UMASK};
+use crate::vfs::tree::{NodeId, VFSNode, VFSNodeKind, VFSTree};
+use crate::window::polina_vfs::SHELL_USER;
+
+// sidecar file inside the storage dir, keeps what the host fs can't (owners, groups)
+pub const META_FILE_NAME: &str = ".polina_meta";
+
+/// Where the VFS tree is loaded from and saved to.
+pub trait VfsBackend {
+    fn load(&self) -> Result<VFSTree, Error>;
+    fn save(&self, tree: &VFSTree) -> Result<(), Error>;
+
+    /// What the last load had to leave out, one line each.
+    fn warnings(&self) -> Vec<String> {
+        vec![]
+    }
+}
+
+/// Picks a backend for `--storage`: a dir is walked as is, a file is a JSON image.
+pub fn open_backend(storage_path: &str) -> Box<dyn VfsBackend> {
+    let path = PathBuf::from(storage_path);
+    if path.is_file() {
+        Box::new(JsonImageBackend { path })
+    } else {
+        Box::new(DirBackend {
+            path,
+            loaded: RefCell::default(),
+            skipped: RefCell::default(),
+        })
+    }
+}
+
+/// Timestamps of a host file, ctime can't be set back on the host so all
+/// three are also kept in the sidecar.
+fn set_host_times(node: &mut VFSNode, metadata: &fs::Metadata) {
+    node.atime = metadata.atime().max(0) as u64;
+    node.mtime = metadata.mtime().max(0) as u64;
+    node.ctime = metadata.ctime().max(0) as u64;
+}
+
+/// Host directory backend, owners are stored in the [`META_FILE_NAME`] sidecar.
+/// Host symlinks become VFS symlinks, host hard links share one VFS node.
+pub struct DirBackend {
+    path: PathBuf,
+    // host entries behind the tree as of the last load or save, only these
+    // are removed on save when the VFS doesn't have them anymore
+    loaded: RefCell<HashSet<PathBuf>>,
+    skipped: RefCell<Vec<String>>,
+}
+
+/// What a load learns about the host dir besides the tree.
+#[derive(Default)]
+struct HostScan {
+    // the first name of every host file with more than one link
+    inodes: HashMap<(u64, u64), NodeId>,
+    loaded: HashSet<PathBuf>,
+    skipped: Vec<String>,
+}
+
+impl DirBackend {
+    fn init_dir_reader(
+        sys_path: &Path,
+        tree: &mut VFSTree,
+        dir: NodeId,
+        scan: &mut HostScan,
+    ) -> std::io::Result<()> {
+        let owner = tree.get(dir).owner.clone();
+        let group = tree.get(dir).group.clone();
+
+        for entry in fs::read_dir(sys_path)? {
+            let entry = entry?;
+            let entry_path = entry.path();
+            // a name the VFS can't hold would come back as `""` and be saved
+            // over the dir itself
+            let Ok(entry_name) = entry.file_name().into_string() else {
+                scan.skipped.push(format!("{}: name is not UTF-8", entry_path.display()));
+                continue;
+            };
+            if entry_name == META_FILE_NAME {
+                continue;
+            }
+            // symlink_metadata, the links themselves are imported, not their targets
+            let metadata = fs::symlink_metadata(&entry_path)?;
+            let mode = (metadata.permissions().mode() & 0o777) as u16;
+
+            let child = if metadata.file_type().is_symlink() {
+                let target = fs::read_link(&entry_path)?.to_string_lossy().to_string();
+                tree.add_child(
+                    dir,
+                    entry_name,
+                    VFSNode::new(owner.clone(), group.clone(), 0o777, VFSNodeKind::Symlink { target }),
+                )
+            } else if metadata.is_dir() {
+                let child = tree.add_child(
+                    dir,
+                    entry_name,
+                    VFSNode::new(owner.clone(), group.clone(), mode, VFSNodeKind::Dir { children: vec![] }),
+                );
+                Self::init_dir_reader(&entry_path, tree, child, scan)?;
+                child
+            } else if !metadata.is_file() {
+                // fifos, sockets and devices have no content to import, a
+                // fifo would even block the read forever
+                scan.skipped.push(format!("{}: not a regular file", entry_path.display()));
+                continue;
+            } else if let Some(&first) = scan.inodes.get(&(metadata.dev(), metadata.ino())) {
+                tree.add_link(dir, entry_name, first)
+            } else {
+                let content = match fs::read(&entry_path) {
+                    Ok(content) => content,
+                    Err(err) => {
+                        scan.skipped.push(format!("{}: {}", entry_path.display(), err));
+                        continue;
+                    }
+                };
+                let child = tree.add_child(
+                    dir,
+                    entry_name,
+                    VFSNode::new(owner.clone(), group.clone(), mode, VFSNodeKind::File { content }),
+                );
+                if metadata.nlink() > 1 {
+                    scan.inodes.insert((metadata.dev(), metadata.ino()), child);
+                }
+                child
+            };
+            // after the children, adding them bumps the dir times
+            set_host_times(tree.get_mut(child), &metadata);
+            scan.loaded.insert(entry_path);
+        }
+
+        Ok(())
+    }
+
+    fn load_meta(&self, tree: &mut VFSTree) -> Result<(), Error> {
+        let meta_path = self.path.join(META_FILE_NAME);
+        if !meta_path.exists() {
+            return Ok(());
+        }
+
+        // path \t owner [\t group \t mode [\t atime \t mtime \t ctime]]
+        for line in fs::read_to_string(meta_path)?.lines() {
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() < 2 {
+                continue;
+            }
+
+            // meta can be stale, skip nodes that are gone from storage
+            if let Some(id) = tree.lookup(fields[0]) {
+                let node = tree.get_mut(id);
+                node.owner = fields[1].to_string();
+                if let Some(group) = fields.get(2) {
+                    node.group = group.to_string();
+                }
+                if let Some(mode) = fields.get(3).and_then(|mode| parse_octal(mode)) {
+                    node.mode = mode;
+                }
+                let times: Vec<u64> = fields.iter().skip(4).filter_map(|time| time.parse().ok()).collect();
+                if let [atime, mtime, ctime] = times[..] {
+                    node.atime = atime;
+                    node.mtime = mtime;
+                    node.ctime = ctime;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn sync_dir_writer(
+        sys_path: &Path,
+        tree: &VFSTree,
+        id: NodeId,
+        meta: &mut Vec<String>,
+        written: &mut HashMap<usize, PathBuf>,
+        loaded: &HashSet<PathBuf>,
+        saved: &mut HashSet<PathBuf>,
+    ) -> std::io::Result<()> {
+        saved.insert(sys_path.to_path_buf());
+        let node = tree.get(id);
+        meta.push(format!(
+            "{}\t{}\t{}\t{:o}\t{}\t{}\t{}",
+            tree.path_of(id),
+            node.owner,
+            node.group,
+            node.mode,
+            node.atime,
+            node.mtime,
+            node.ctime
+        ));
+
+        match &node.kind {
+            VFSNodeKind::Dir { children } => {
+                fs::create_dir_all(sys_path)?;
+
+                let names: HashMap<&str, NodeId> = children
+                    .iter()
+                    .map(|&child| (tree.name(child), child))
+                    .collect();
+
+                for entry in fs::read_dir(sys_path)? {
+                    let entry = entry?;
+                    let entry_name = entry.file_name().into_string().unwrap_or_default();
+                    if id == VFSTree::ROOT && entry_name == META_FILE_NAME {
+                        continue;
+                    }
+
+                    let file_type = entry.file_type()?;
+                    let stale = match names.get(entry_name.as_str()) {
+                        Some(&child) => match &tree.get(child).kind {
+                            VFSNodeKind::Dir { .. } => !file_type.is_dir(),
+                            VFSNodeKind::File { .. } => !file_type.is_file(),
+                            VFSNodeKind::Symlink { target } => {
+                                !file_type.is_symlink()
+                                    || fs::read_link(entry.path())?.to_string_lossy() != *target
+                            }
+                        },
+                        // what the load skipped or someone else put there
+                        // is not the VFS's to remove
+                        None => loaded.contains(&entry.path()),
+                    };
+                    if stale {
+                        if file_type.is_dir() {
+                            fs::remove_dir_all(entry.path())?;
+                        } else {
+                            fs::remove_file(entry.path())?;
+                        }
+                    }
+                }
+
+                for (name, child) in names {
+                    Self::sync_dir_writer(&sys_path.join(name), tree, child, meta, written, loaded, saved)?;
+                }
+            }
+            VFSNodeKind::File { content } => {
+                match written.get(&tree.node_index(id)) {
+                    Some(first) => {
+                        let same = match (fs::metadata(first), fs::symlink_metadata(sys_path)) {
+                            (Ok(first), Ok(current)) => first.ino() == current.ino(),
+                            _ => false,
+                        };
+                        if !same {
+                            if sys_path.exists() {
+                                fs::remove_file(sys_path)?;
+                            }
+                            fs::hard_link(first, sys_path)?;
+                        }
+                    }
+                    None => {
+                        // a host hard link the VFS doesn't have anymore would
+                        // carry the content over to the other name, more
+                        // names of this node are linked back to it above
+                        let shared = fs::symlink_metadata(sys_path).is_ok_and(|meta| meta.is_file() && meta.nlink() > 1);
+                        if shared {
+                            fs::remove_file(sys_path)?;
+                        }
+                        fs::write(sys_path, content)?;
+                        written.insert(tree.node_index(id), sys_path.to_path_buf());
+                    }
+                }
+            }
+            VFSNodeKind::Symlink { target } => {
+                if fs::symlink_metadata(sys_path).is_err() {
+                    std::os::unix::fs::symlink(target, sys_path)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl VfsBackend for DirBackend {
+    fn load(&self) -> Result<VFSTree, Error> {
+        let mut tree = VFSTree::new(SHELL_USER.to_string(), SHELL_USER.to_string(), 0o755);
+        let mut scan = HostScan::default();
+        Self::init_dir_reader(&self.path, &mut tree, VFSTree::ROOT, &mut scan)?;
+        set_host_times(tree.get_mut(VFSTree::ROOT), &fs::metadata(&self.path)?);
+        self.load_meta(&mut tree)?;
+        *self.loaded.borrow_mut() = scan.loaded;
+        *self.skipped.borrow_mut() = scan.skipped;
+
+        Ok(tree)
+    }
+
+    fn save(&self, tree: &VFSTree) -> Result<(), Error> {
+        let mut meta: Vec<String> = vec![];
+        let mut saved = HashSet::new();
+        Self::sync_dir_writer(
+            &self.path,
+            tree,
+            VFSTree::ROOT,
+            &mut meta,
+            &mut HashMap::new(),
+            &self.loaded.borrow(),
+            &mut saved,
+        )?;
+        *self.loaded.borrow_mut() = saved;
+
+        let mut meta_data = meta.join("\n");
+        meta_data.push('\n');
+        fs::write(self.path.join(META_FILE_NAME), meta_data)
+    }
+
+    fn warnings(&self) -> Vec<String> {
+        self.skipped.borrow().clone()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum ImageContent {
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+// seconds since the epoch, missing ones stay at the load time
+#[derive(Serialize, Deserialize, Default, Clone, Copy)]
+struct ImageTimes {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    atime: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mtime: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ctime: Option<u64>,
+}
+
+impl ImageTimes {
+    fn of(node: &VFSNode) -> Self {
+        ImageTimes {
+            atime: Some(node.atime),
+            mtime: Some(node.mtime),
+            ctime: Some(node.ctime),
+        }
+    }
+
+    fn apply(self, node: &mut VFSNode) {
+        node.atime = self.atime.unwrap_or(node.atime);
+        node.mtime = self.mtime.unwrap_or(node.mtime);
+        node.ctime = self.ctime.unwrap_or(node.ctime);
+    }
+}
+
+/// What can only be attached once the whole image is in the tree: hard links
+/// need their target, and attaching anything bumps the times.
+#[derive(Default)]
+struct PendingImage {
+    links: Vec<(NodeId, String, String)>,
+    times: Vec<(NodeId, ImageTimes)>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ImageNode {
+    File {
+        name: String,
+        #[serde(default = "default_owner")]
+        owner: String,
+        #[serde(default = "default_owner")]
+        group: String,
+        // octal string, e.g. "644"
+        #[serde(default)]
+        mode: Option<String>,
+        #[serde(default = "empty_content")]
+        content: ImageContent,
+        #[serde(flatten)]
+        times: ImageTimes,
+    },
+    Dir {
+        name: String,
+        #[serde(default = "default_owner")]
+        owner: String,
+        #[serde(default = "default_owner")]
+        group: String,
+        #[serde(default)]
+        mode: Option<String>,
+        #[serde(default)]
+        children: Vec<ImageNode>,
+        #[serde(flatten)]
+        times: ImageTimes,
+    },
+    Symlink {
+        name: String,
+        #[serde(default = "default_owner")]
+        owner: String,
+        #[serde(default = "default_owner")]
+        group: String,
+        target: String,
+        #[serde(flatten)]
+        times: ImageTimes,
+    },
+    // another name for the file at the absolute path `target`
+    Hardlink {
+        name: String,
+        target: String,
+    },
+}
+
+fn default_owner() -> String {
+    SHELL_USER.to_string()
+}
+
+fn empty_content() -> ImageContent {
+    ImageContent::Text(String::new())
+}
+
+fn image_mode(mode: Option<String>, default: u16) -> Result<u16, Error> {
+    match mode {
+        Some(mode) => parse_octal(&mode).ok_or_else(|| {
+            Error::new(ErrorKind::InvalidData, format!("bad image: invalid mode {}", mode))
+        }),
+        None => Ok(default & !DEFAULT_UMASK),
+    }
+}
+
+impl ImageNode {
+    fn insert_into(self, tree: &mut VFSTree, parent: NodeId, pending: &mut PendingImage) -> Result<(), Error> {
+        match self {
+            ImageNode::File { name, owner, group, mode, content, times } => {
+                let content = match content {
+                    ImageContent::Text(text) => text.into_bytes(),
+                    ImageContent::Bytes(bytes) => bytes,
+                };
+                let mode = image_mode(mode, DEFAULT_FILE_MODE)?;
+                let file = tree.add_child(parent, name, VFSNode::new(owner, group, mode, VFSNodeKind::File { content }));
+                pending.times.push((file, times));
+            }
+            ImageNode::Dir { name, owner, group, mode, children, times } => {
+                let mode = image_mode(mode, DEFAULT_DIR_MODE)?;
+                let dir = tree.add_child(
+                    parent,
+                    name,
+                    VFSNode::new(owner, group, mode, VFSNodeKind::Dir { children: vec![] }),
+                );
+                for child in children {
+                    child.insert_into(tree, dir, pending)?;
+                }
+                pending.times.push((dir, times));
+            }
+            ImageNode::Symlink { name, owner, group, target, times } => {
+                let link = tree.add_child(parent, name, VFSNode::new(owner, group, 0o777, VFSNodeKind::Symlink { target }));
+                pending.times.push((link, times));
+            }
+            ImageNode::Hardlink { name, target } => {
+                pending.links.push((parent, name, target));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn from_tree(tree: &VFSTree, id: NodeId, seen: &mut HashMap<usize, String>) -> Self {
+        let node = tree.get(id);
+        let name = tree.name(id).to_string();
+
+        if let Some(first) = seen.get(&tree.node_index(id)) {
+            return ImageNode::Hardlink {
+                name,
+                target: first.clone(),
+            };
+        }
+        if node.links > 1 {
+            seen.insert(tree.node_index(id), tree.path_of(id));
+        }
+
+        match &node.kind {
+            VFSNodeKind::File { content } => ImageNode::File {
+                name,
+                owner: node.owner.clone(),
+                group: node.group.clone(),
+                mode: Some(format!("{:o}", node.mode)),
+                content: match String::from_utf8(content.clone()) {
+                    Ok(text) => ImageContent::Text(text),
+                    Err(err) => ImageContent::Bytes(err.into_bytes()),
+                },
+                times: ImageTimes::of(node),
+            },
+            VFSNodeKind::Dir { children } => ImageNode::Dir {
+                name,
+                owner: node.owner.clone(),
+                group: node.group.clone(),
+                mode: Some(format!("{:o}", node.mode)),
+                children: children
+                    .iter()
+                    .map(|&child| ImageNode::from_tree(tree, child, seen))
+                    .collect(),
+                times: ImageTimes::of(node),
+            },
+            VFSNodeKind::Symlink { target } => ImageNode::Symlink {
+                name,
+                owner: node.owner.clone(),
+                group: node.group.clone(),
+                target: target.clone(),
+                times: ImageTimes::of(node),
+            },
+        }
+    }
+}
+
+/// Single JSON file with the whole tree, e.g.
+/// `{"type": "dir", "name": "/", "children": [{"type": "file", "name": "a.pl", "content": "ls"}]}`
+pub struct JsonImageBackend {
+    path: PathBuf,
+}
+
+impl VfsBackend for JsonImageBackend {
+    fn load(&self) -> Result<VFSTree, Error> {
+        let data = fs::read_to_string(&self.path)?;
+        let image: ImageNode = serde_json::from_str(&data)
+            .map_err(|err| Error::new(ErrorKind::InvalidData, format!("bad image: {}", err)))?;
+
+        match image {
+            ImageNode::Dir { children, owner, group, mode, times, .. } => {
+                let mut tree = VFSTree::new(owner, group, image_mode(mode, DEFAULT_DIR_MODE)?);
+                let mut pending = PendingImage::default();
+                for child in children {
+                    child.insert_into(&mut tree, VFSTree::ROOT, &mut pending)?;
+                }
+                pending.times.push((VFSTree::ROOT, times));
+
+                for (parent, name, target) in pending.links {
+                    match tree.lookup(&target) {
+                        Some(id) if !tree.get(id).is_dir() => {
+                            tree.add_link(parent, name, id);
+                        }
+                        _ => {
+                            return Err(Error::new(
+                                ErrorKind::InvalidData,
+                                format!("bad image: hard link to missing file {}", target),
+                            ))
+                        }
+                    }
+                }
+                for (id, times) in pending.times {
+                    times.apply(tree.get_mut(id));
+                }
+                Ok(tree)
+            }
+            _ => Err(Error::new(
+                ErrorKind::InvalidData,
+                "bad image: root must be a dir",
+            )),
+        }
+    }
+
+    fn save(&self, tree: &VFSTree) -> Result<(), Error> {
+        let image = ImageNode::from_tree(tree, VFSTree::ROOT, &mut HashMap::new());
+        let data = serde_json::to_string_pretty(&image)
+            .map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+        fs::write(&self.path, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::ffi::OsStrExt;
+
+    #[test]
+    fn test_json_image_roundtrip() {
+        let image = std::env::temp_dir().join(format!("polina_image_{}.json", std::process::id()));
+        fs::write(
+            &image,
+            r#"{"type": "dir", "name": "/", "children": [
+                {"type": "file", "name": "home.pl", "owner": "ilya", "mode": "600", "content": "cd", "mtime": 1000},
+                {"type": "dir", "name": "test", "children": [
+                    {"type": "hardlink", "name": "home_link.pl", "target": "/home.pl"},
+                    {"type": "symlink", "name": "up", "target": ".."}
+                ]}
+            ]}"#,
+        )
+        .unwrap();
+
+        let backend = open_backend(image.to_str().unwrap());
+        let tree = backend.load().unwrap();
+        backend.save(&tree).unwrap();
+
+        let tree = backend.load().unwrap();
+        assert_eq!(tree.children(VFSTree::ROOT).len(), 2);
+
+        let home = tree.lookup("/home.pl").unwrap();
+        assert_eq!(tree.get(home).owner, "ilya");
+        assert_eq!(tree.get(home).mode, 0o600);
+        assert_eq!(tree.get(home).mtime, 1000);
+        assert_eq!(tree.get(tree.lookup("/test").unwrap()).mode, 0o755);
+        match &tree.get(home).kind {
+            VFSNodeKind::File { content } => assert_eq!(content, b"cd"),
+            _ => panic!("expected file"),
+        }
+        assert!(tree.get(tree.lookup("/test").unwrap()).is_dir());
+        assert_eq!(tree.node_index(tree.lookup("/test/home_link.pl").unwrap()), tree.node_index(home));
+        assert_eq!(tree.get(tree.lookup("/test/up").unwrap()).file_type(), 'l');
+
+        fs::remove_file(image).unwrap();
+    }
+
+    #[test]
+    fn test_dir_keeps_unloaded() {
+        let storage = std::env::temp_dir().join(format!("polina_unloaded_{}", std::process::id()));
+        fs::create_dir_all(&storage).unwrap();
+        fs::write(storage.join("a.pl"), "ls").unwrap();
+        fs::write(storage.join("locked.pl"), "cd").unwrap();
+        fs::set_permissions(storage.join("locked.pl"), fs::Permissions::from_mode(0o000)).unwrap();
+
+        let backend = open_backend(storage.to_str().unwrap());
+        let mut tree = backend.load().unwrap();
+        // root reads it anyway, anyone else can't and the load skips it
+        if fs::read(storage.join("locked.pl")).is_err() {
+            assert!(tree.lookup("/locked.pl").is_none());
+        }
+        fs::write(storage.join("later.pl"), "pwd").unwrap();
+        tree.remove(tree.lookup("/a.pl").unwrap());
+        backend.save(&tree).unwrap();
+
+        // only what the VFS loaded and then removed goes away on the host
+        assert!(!storage.join("a.pl").exists());
+        assert!(fs::symlink_metadata(storage.join("locked.pl")).is_ok());
+        assert_eq!(fs::read_to_string(storage.join("later.pl")).unwrap(), "pwd");
+
+        fs::remove_dir_all(storage).unwrap();
+    }
+
+    #[test]
+    fn test_dir_links_roundtrip() {
+        let storage = std::env::temp_dir().join(format!("polina_links_{}", std::process::id()));
+        fs::create_dir_all(&storage).unwrap();
+        fs::write(storage.join("a.pl"), "ls").unwrap();
+        // a socket has no content, it is left out of the VFS and left alone on save
+        let _socket = std::os::unix::net::UnixListener::bind(storage.join("sock")).unwrap();
+        // so is a name that is not UTF-8
+        let bad_name = storage.join(std::ffi::OsStr::from_bytes(b"bad\xff.pl"));
+        fs::write(&bad_name, "pwd").unwrap();
+        fs::hard_link(storage.join("a.pl"), storage.join("b.pl")).unwrap();
+        std::os::unix::fs::symlink("a.pl", storage.join("c.pl")).unwrap();
+
+        let backend = open_backend(storage.to_str().unwrap());
+        let mut tree = backend.load().unwrap();
+        let a = tree.lookup("/a.pl").unwrap();
+        assert_eq!(tree.node_index(a), tree.node_index(tree.lookup("/b.pl").unwrap()));
+        match &tree.get(tree.lookup("/c.pl").unwrap()).kind {
+            VFSNodeKind::Symlink { target } => assert_eq!(target, "a.pl"),
+            _ => panic!("expected symlink"),
+        }
+
+        tree.get_mut(a).kind = VFSNodeKind::File { content: b"cd".to_vec() };
+        tree.add_child(
+            VFSTree::ROOT,
+            "d.pl".to_string(),
+            VFSNode::new("root".to_string(), "root".to_string(), 0o777, VFSNodeKind::Symlink { target: "b.pl".to_string() }),
+        );
+        backend.save(&tree).unwrap();
+
+        assert_eq!(fs::read_to_string(storage.join("b.pl")).unwrap(), "cd");
+        assert_eq!(fs::read_link(storage.join("d.pl")).unwrap(), PathBuf::from("b.pl"));
+        assert!(tree.lookup("/sock").is_none());
+        let mut warnings = backend.warnings();
+        warnings.sort();
+        assert_eq!(
+            warnings,
+            vec![
+                format!("{}: name is not UTF-8", bad_name.display()),
+                format!("{}: not a regular file", storage.join("sock").display()),
+            ]
+        );
+        assert_eq!(fs::read_to_string(&bad_name).unwrap(), "pwd");
+        assert!(fs::symlink_metadata(storage.join("sock")).is_ok());
+
+        // `rm b.pl; touch b.pl` splits the names, the host link has to go
+        tree.remove(tree.lookup("/b.pl").unwrap());
+        tree.add_child(
+            VFSTree::ROOT,
+            "b.pl".to_string(),
+            VFSNode::new("root".to_string(), "root".to_string(), 0o644, VFSNodeKind::File { content: b"new".to_vec() }),
+        );
+        backend.save(&tree).unwrap();
+
+        assert_eq!(fs::read_to_string(storage.join("a.pl")).unwrap(), "cd");
+        assert_eq!(fs::read_to_string(storage.join("b.pl")).unwrap(), "new");
+        assert_eq!(fs::metadata(storage.join("a.pl")).unwrap().nlink(), 1);
+        let tree = backend.load().unwrap();
+        assert_ne!(
+            tree.node_index(tree.lookup("/a.pl").unwrap()),
+            tree.node_index(tree.lookup("/b.pl").unwrap())
+        );
+
+        fs::remove_dir_all(storage).unwrap();
+    }
+}
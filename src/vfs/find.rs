@@ -0,0 +1,308 @@
+use regex::Regex;
+
+use std::io::{Error, ErrorKind};
+
+use crate::vfs::pattern::glob_match;
+use crate::vfs::permissions::{EXEC, READ};
+use crate::vfs::storage::VFS;
+use crate::vfs::time::now;
+use crate::vfs::tree::{NodeId, VFSNode};
+
+/// `+N` more than, `-N` less than, `N` exactly, like in find.
+#[derive(Debug, Clone, Copy)]
+enum Compare {
+    More(u64),
+    Less(u64),
+    Exactly(u64),
+}
+
+impl Compare {
+    fn parse(spec: &str) -> Option<(Self, &str)> {
+        let (make, rest): (fn(u64) -> Compare, &str) = match spec.chars().next()? {
+            '+' => (Compare::More, &spec[1..]),
+            '-' => (Compare::Less, &spec[1..]),
+            _ => (Compare::Exactly, spec),
+        };
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        let value = rest[..digits].parse().ok()?;
+
+        Some((make(value), &rest[digits..]))
+    }
+
+    fn matches(self, value: u64) -> bool {
+        match self {
+            Compare::More(limit) => value > limit,
+            Compare::Less(limit) => value < limit,
+            Compare::Exactly(limit) => value == limit,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Predicate {
+    Name(String),
+    Regex(Regex),
+    // `ls -l` type letter
+    Type(char),
+    User(String),
+    // compared in units of the given size in bytes, rounded up
+    Size(Compare, u64),
+    // age of the mtime in units of the given seconds, rounded down
+    Mtime(Compare, u64),
+    Not(Box<Predicate>),
+}
+
+impl Predicate {
+    fn matches(&self, path: &str, name: &str, node: &VFSNode) -> bool {
+        match self {
+            Predicate::Name(pattern) => glob_match(pattern, name),
+            Predicate::Regex(regex) => regex.is_match(path),
+            Predicate::Type(file_type) => node.file_type() == *file_type,
+            Predicate::User(user) => node.owner == *user,
+            Predicate::Size(compare, unit) => compare.matches(node.size().div_ceil(*unit)),
+            Predicate::Mtime(compare, unit) => compare.matches(now().saturating_sub(node.mtime) / unit),
+            Predicate::Not(predicate) => !predicate.matches(path, name, node),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Action {
+    Print,
+    Delete,
+}
+
+/// Parsed `find` command line: start paths, then tests and an action, all
+/// tests must match.
+#[derive(Debug)]
+pub struct FindQuery {
+    paths: Vec<String>,
+    predicates: Vec<Predicate>,
+    min_depth: usize,
+    max_depth: Option<usize>,
+    action: Action,
+}
+
+impl FindQuery {
+    pub fn parse(args: &[String]) -> Result<Self, Error> {
+        let invalid = |message: String| Error::new(ErrorKind::InvalidInput, message);
+
+        let start = args
+            .iter()
+            .position(|arg| arg.starts_with('-') || arg == "!")
+            .unwrap_or(args.len());
+        let mut query = FindQuery {
+            paths: args[..start].to_vec(),
+            predicates: vec![],
+            min_depth: 0,
+            max_depth: None,
+            action: Action::Print,
+        };
+        if query.paths.is_empty() {
+            query.paths.push(".".to_string());
+        }
+
+        let mut negate = false;
+        let mut rest = args[start..].iter();
+        while let Some(arg) = rest.next() {
+            let mut value = || {
+                rest.next()
+                    .cloned()
+                    .ok_or_else(|| invalid(format!("missing argument to `{}'", arg)))
+            };
+
+            let predicate = match arg.as_str() {
+                "!" | "-not" => {
+                    negate = !negate;
+                    continue;
+                }
+                "-print" => {
+                    query.action = Action::Print;
+                    continue;
+                }
+                "-delete" => {
+                    query.action = Action::Delete;
+                    continue;
+                }
+                "-maxdepth" | "-mindepth" => {
+                    let spec = value()?;
+                    let depth = spec
+                        .parse()
+                        .map_err(|_| invalid(format!("invalid depth: {}", spec)))?;
+                    if arg == "-maxdepth" {
+                        query.max_depth = Some(depth);
+                    } else {
+                        query.min_depth = depth;
+                    }
+                    continue;
+                }
+                "-name" => Predicate::Name(value()?),
+                "-regex" => {
+                    let spec = value()?;
+                    // like find the regex has to match the whole path
+                    Predicate::Regex(
+                        Regex::new(&format!("^(?:{})$", spec))
+                            .map_err(|err| invalid(format!("invalid regex {}: {}", spec, err)))?,
+                    )
+                }
+                "-type" => match value()?.as_str() {
+                    "f" => Predicate::Type('-'),
+                    "d" => Predicate::Type('d'),
+                    "l" => Predicate::Type('l'),
+                    other => return Err(invalid(format!("unknown type: {}", other))),
+                },
+                "-user" => Predicate::User(value()?),
+                "-size" => {
+                    let spec = value()?;
+                    let (compare, unit) = Compare::parse(&spec)
+                        .ok_or_else(|| invalid(format!("invalid size: {}", spec)))?;
+                    let unit = match unit {
+                        "" | "b" => 512,
+                        "c" => 1,
+                        "k" => 1024,
+                        "M" => 1024 * 1024,
+                        "G" => 1024 * 1024 * 1024,
+                        _ => return Err(invalid(format!("invalid size: {}", spec))),
+                    };
+                    Predicate::Size(compare, unit)
+                }
+                "-mtime" | "-mmin" => {
+                    let spec = value()?;
+                    let compare = match Compare::parse(&spec) {
+                        Some((compare, "")) => compare,
+                        _ => return Err(invalid(format!("invalid time: {}", spec))),
+                    };
+                    Predicate::Mtime(compare, if arg == "-mtime" { 86400 } else { 60 })
+                }
+                _ => return Err(invalid(format!("unknown predicate `{}'", arg))),
+            };
+
+            query.predicates.push(if negate {
+                Predicate::Not(Box::new(predicate))
+            } else {
+                predicate
+            });
+            negate = false;
+        }
+
+        Ok(query)
+    }
+}
+
+impl VFS {
+    /// Runs a `find` query, returns the output lines. Dirs that can't be read
+    /// are reported in place and skipped like find does.
+    pub fn find(&mut self, query: &FindQuery) -> Result<String, Error> {
+        let mut found: Vec<(NodeId, String)> = vec![];
+        let mut output = String::new();
+
+        for path in &query.paths {
+            let start = self.resolve(path, false)?;
+            self.walk(query, start, path.clone(), 0, &mut found, &mut output);
+        }
+
+        match query.action {
+            Action::Print => {
+                for (_, path) in found {
+                    output.push_str(&path);
+                    output.push('\n');
+                }
+            }
+            // children come after their dir, going backwards empties dirs first
+            Action::Delete => {
+                for (id, path) in found.into_iter().rev() {
+                    let full_path = self.tree.path_of(id);
+                    let result = if self.tree.get(id).is_dir() {
+                        self.remove_dir(&full_path)
+                    } else {
+                        self.remove_node(&full_path, false, false)
+                    };
+                    if let Err(err) = result {
+                        output.push_str(&format!("find: cannot delete {}: {}\n", path, err));
+                    }
+                }
+            }
+        }
+
+        Ok(output)
+    }
+
+    fn walk(
+        &self,
+        query: &FindQuery,
+        id: NodeId,
+        path: String,
+        depth: usize,
+        found: &mut Vec<(NodeId, String)>,
+        output: &mut String,
+    ) {
+        let node = self.tree.get(id);
+        // the start point is named by the last part of the path it was given as
+        let name = match (depth, path.trim_end_matches('/')) {
+            (0, "") => "/",
+            (0, trimmed) => trimmed.rsplit('/').next().unwrap_or(trimmed),
+            _ => self.tree.name(id),
+        };
+
+        if depth >= query.min_depth
+            && query
+                .predicates
+                .iter()
+                .all(|predicate| predicate.matches(&path, name, node))
+        {
+            found.push((id, path.clone()));
+        }
+
+        if !node.is_dir() || query.max_depth.is_some_and(|max| depth >= max) {
+            return;
+        }
+        if let Err(err) = self.check_access(id, READ | EXEC) {
+            output.push_str(&format!("find: {}\n", err));
+            return;
+        }
+
+        for &child in self.tree.children(id) {
+            let child_path = format!("{}/{}", path.trim_end_matches('/'), self.tree.name(child));
+            self.walk(query, child, child_path, depth + 1, found, output);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::window::polina_vfs::SHELL_USER;
+
+    fn find(vfs: &mut VFS, args: &str) -> Vec<String> {
+        let args: Vec<String> = args.split_whitespace().map(str::to_string).collect();
+        let query = FindQuery::parse(&args).unwrap();
+        let mut lines: Vec<String> = vfs.find(&query).unwrap().lines().map(str::to_string).collect();
+        lines.sort();
+        lines
+    }
+
+    #[test]
+    fn test_find() {
+        let mut vfs = VFS::new(SHELL_USER.to_string(), "./storage".to_string()).unwrap();
+
+        assert_eq!(find(&mut vfs, "/test -name *.pl"), vec!["/test/xd.pl"]);
+        assert_eq!(find(&mut vfs, "test -type d"), vec!["test", "test/second_dir"]);
+        assert_eq!(find(&mut vfs, "/ -maxdepth 1 -name home.pl"), vec!["/home.pl"]);
+        assert_eq!(find(&mut vfs, "/test -name test"), vec!["/test"]);
+        assert_eq!(find(&mut vfs, "test/ -maxdepth 0 -name test"), vec!["test/"]);
+        assert_eq!(find(&mut vfs, "/ -maxdepth 0 -name /"), vec!["/"]);
+        assert!(find(&mut vfs, "/ -mindepth 2 -maxdepth 2 -name *.pl").contains(&"/test/xd.pl".to_string()));
+        assert_eq!(find(&mut vfs, "/test -regex .*/second_.*"), vec!["/test/second_dir", "/test/second_dir/example_file"]);
+        assert_eq!(find(&mut vfs, "/test ! -type d"), vec!["/test/second_dir/example_file", "/test/xd.pl"]);
+        assert_eq!(find(&mut vfs, "/test -type f -size -1"), vec!["/test/second_dir/example_file", "/test/xd.pl"]);
+        assert_eq!(find(&mut vfs, "/test -type f -size +0c"), Vec::<String>::new());
+        assert_eq!(find(&mut vfs, "/test -type f -mmin -5 -user nobody"), Vec::<String>::new());
+        assert!(FindQuery::parse(&["-type".to_string(), "x".to_string()]).is_err());
+        assert!(FindQuery::parse(&["-name".to_string()]).is_err());
+
+        find(&mut vfs, "/test -name *_file -delete");
+        assert!(vfs.canonical_path("/test/second_dir/example_file").is_err());
+        find(&mut vfs, "/test -delete");
+        assert!(vfs.canonical_path("/test").is_err());
+    }
+}
@@ -0,0 +1,210 @@
+use std::io::{Error, ErrorKind};
+
+use crate::vfs::pattern::{glob_match, unescape_glob};
+use crate::vfs::permissions::{EXEC, READ};
+use crate::vfs::storage::VFS;
+use crate::vfs::tree::NodeId;
+
+/// What a pattern without matches turns into, set with `shopt -s/-u`. Like
+/// bash the pattern is kept as is by default, `failglob` wins over `nullglob`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GlobOptions {
+    pub nullglob: bool,
+    pub failglob: bool,
+}
+
+impl GlobOptions {
+    pub const NAMES: [&'static str; 2] = ["nullglob", "failglob"];
+
+    pub fn get(&self, name: &str) -> Option<bool> {
+        match name {
+            "nullglob" => Some(self.nullglob),
+            "failglob" => Some(self.failglob),
+            _ => None,
+        }
+    }
+
+    pub fn set(&mut self, name: &str, value: bool) -> Result<(), Error> {
+        match name {
+            "nullglob" => self.nullglob = value,
+            "failglob" => self.failglob = value,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{}: invalid shell option name", name),
+                ))
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether the word has an unescaped `*`, `?` or `[`.
+pub fn has_glob(word: &str) -> bool {
+    let mut chars = word.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+fn join(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else if prefix.ends_with('/') {
+        format!("{}{}", prefix, name)
+    } else {
+        format!("{}/{}", prefix, name)
+    }
+}
+
+impl VFS {
+    /// Pathname expansion of every word, each pattern is replaced by its
+    /// sorted matches. Words come glob-escaped from the lexer, whatever is not
+    /// expanded is unescaped.
+    pub fn expand_globs(&self, words: Vec<String>, options: GlobOptions) -> Result<Vec<String>, Error> {
+        let mut result = vec![];
+
+        for word in words {
+            if !has_glob(&word) {
+                result.push(unescape_glob(&word));
+                continue;
+            }
+
+            let matches = self.expand_glob(&word);
+            if !matches.is_empty() {
+                result.extend(matches);
+            } else if options.failglob {
+                return Err(Error::new(ErrorKind::NotFound, format!("no match: {}", unescape_glob(&word))));
+            } else if !options.nullglob {
+                result.push(unescape_glob(&word));
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Matches of one pattern against the tree, `**` stands for any number of
+    /// dirs. Names starting with a dot need a pattern starting with a dot.
+    fn expand_glob(&self, pattern: &str) -> Vec<String> {
+        let parts: Vec<&str> = pattern.split('/').filter(|part| !part.is_empty()).collect();
+        let mut candidates = vec![if pattern.starts_with('/') { "/".to_string() } else { String::new() }];
+
+        for (index, part) in parts.iter().enumerate() {
+            let last = index + 1 == parts.len();
+            let mut next = vec![];
+
+            for candidate in candidates {
+                if *part == "**" {
+                    if !last {
+                        next.push(candidate.clone());
+                    }
+                    if let Some(dir) = self.glob_dir(&candidate) {
+                        self.collect_descendants(dir, &candidate, !last, &mut next);
+                    }
+                } else if has_glob(part) {
+                    let Some(dir) = self.glob_dir(&candidate) else {
+                        continue;
+                    };
+                    for &child in self.tree.children(dir) {
+                        let name = self.tree.name(child);
+                        if (!name.starts_with('.') || part.starts_with('.')) && glob_match(part, name) {
+                            next.push(join(&candidate, name));
+                        }
+                    }
+                } else {
+                    next.push(join(&candidate, &unescape_glob(part)));
+                }
+            }
+
+            candidates = next;
+        }
+
+        let dirs_only = pattern.ends_with('/');
+        let mut matches: Vec<String> = candidates
+            .into_iter()
+            .filter(|path| !path.is_empty())
+            .filter(|path| match self.resolve(path, dirs_only) {
+                Ok(id) => !dirs_only || self.tree.get(id).is_dir(),
+                Err(_) => false,
+            })
+            .map(|path| if dirs_only { join(&path, "") } else { path })
+            .collect();
+        matches.sort();
+        matches.dedup();
+
+        matches
+    }
+
+    /// The dir behind a partial match if its entries can be listed.
+    fn glob_dir(&self, path: &str) -> Option<NodeId> {
+        let id = self.resolve(if path.is_empty() { "." } else { path }, true).ok()?;
+        (self.tree.get(id).is_dir() && self.check_access(id, READ | EXEC).is_ok()).then_some(id)
+    }
+
+    /// Everything below `dir` for `**`, only dirs when more parts follow.
+    /// Symlinks are not descended into, so link loops can't recurse forever.
+    fn collect_descendants(&self, dir: NodeId, prefix: &str, dirs_only: bool, output: &mut Vec<String>) {
+        if self.check_access(dir, READ | EXEC).is_err() {
+            return;
+        }
+
+        for &child in self.tree.children(dir) {
+            let name = self.tree.name(child);
+            if name.starts_with('.') {
+                continue;
+            }
+
+            let path = join(prefix, name);
+            let is_dir = self.tree.get(child).is_dir();
+            if is_dir || !dirs_only {
+                output.push(path.clone());
+            }
+            if is_dir {
+                self.collect_descendants(child, &path, dirs_only, output);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::window::polina_vfs::SHELL_USER;
+
+    fn words(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_expand_globs() {
+        let mut vfs = VFS::new(SHELL_USER.to_string(), "./storage".to_string()).unwrap();
+        let options = GlobOptions::default();
+
+        let expanded = vfs.expand_globs(words("ls h*.pl"), options).unwrap();
+        assert_eq!(expanded, words("ls history.pl home.pl"));
+        assert_eq!(vfs.expand_glob("/test/*"), vec!["/test/second_dir", "/test/xd.pl"]);
+        assert_eq!(vfs.expand_glob("test/*/"), vec!["test/second_dir/"]);
+        assert_eq!(vfs.expand_glob("[ew]*.pl"), vec!["errors.pl", "whoami.pl"]);
+        assert_eq!(vfs.expand_glob("test/**"), vec!["test/second_dir", "test/second_dir/example_file", "test/xd.pl"]);
+        assert_eq!(vfs.expand_glob("**/example_?ile"), vec!["test/second_dir/example_file"]);
+        assert!(vfs.expand_glob("/etc/*").contains(&"/etc/passwd".to_string()));
+
+        vfs.change_dir(vec!["test".to_string()]).unwrap();
+        assert_eq!(vfs.expand_glob("../w*"), vec!["../whoami.pl"]);
+
+        assert_eq!(vfs.expand_globs(words("cat *.xyz"), options).unwrap(), words("cat *.xyz"));
+        let options = GlobOptions { nullglob: true, failglob: false };
+        assert_eq!(vfs.expand_globs(words("cat *.xyz"), options).unwrap(), words("cat"));
+        let options = GlobOptions { nullglob: true, failglob: true };
+        assert!(vfs.expand_globs(words("cat *.xyz"), options).is_err());
+        assert!(!has_glob("\\*.pl"));
+        assert_eq!(vfs.expand_globs(words("ls \\*.pl"), options).unwrap(), words("ls *.pl"));
+    }
+}
@@ -1 +1,10 @@
-pub mod storage;
\ No newline at end of file
+pub mod accounts;
+pub mod backend;
+pub mod find;
+pub mod glob;
+pub mod pattern;
+pub mod permissions;
+pub mod storage;
+pub mod time;
+pub mod tree;
+pub mod tree_view;
\ No newline at end of file
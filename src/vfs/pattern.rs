@@ -0,0 +1,137 @@
+/// Chars that mean something to glob expansion, escaped when they come from quotes.
+const GLOB_CHARS: [char; 4] = ['*', '?', '[', '\\'];
+
+/// Escapes glob chars so the text only ever matches itself.
+pub fn escape_glob(text: &str) -> String {
+    let mut result = String::new();
+    for c in text.chars() {
+        if GLOB_CHARS.contains(&c) {
+            result.push('\\');
+        }
+        result.push(c);
+    }
+    result
+}
+
+/// Undoes [`escape_glob`], for words that end up not being globbed.
+pub fn unescape_glob(pattern: &str) -> String {
+    let mut result = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => result.extend(chars.next()),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// Shell glob over a single name: `*`, `?`, `[abc]`, `[a-z]`, `[!a]` and `\` escapes.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // the last `*` seen and where in the text it stopped eating, a failed
+    // match goes back there and lets it take one more char
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if pattern.get(p) == Some(&'*') {
+            p += 1;
+            star = Some((p, t));
+            continue;
+        }
+        if let Some(len) = match_one(&pattern[p..], text[t]) {
+            p += len;
+            t += 1;
+            continue;
+        }
+        match star {
+            Some((after, eaten)) => {
+                p = after;
+                t = eaten + 1;
+                star = Some((after, t));
+            }
+            None => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Matches `c` against the single-char item at the start of `pattern`,
+/// returns how many pattern chars the item took.
+fn match_one(pattern: &[char], c: char) -> Option<usize> {
+    match pattern.first() {
+        None => None,
+        Some('?') => Some(1),
+        Some('[') => match match_class(pattern, Some(&c)) {
+            Some((matched, len)) => matched.then_some(len),
+            // no closing `]`, the bracket is taken literally
+            None => (c == '[').then_some(1),
+        },
+        Some('\\') if pattern.len() > 1 => (pattern[1] == c).then_some(2),
+        Some(&first) => (first == c).then_some(1),
+    }
+}
+
+/// Matches `c` against the class at the start of `pattern`, returns whether it
+/// matched and how many pattern chars the class took.
+fn match_class(pattern: &[char], c: Option<&char>) -> Option<(bool, usize)> {
+    let mut pos = 1;
+    let negate = matches!(pattern.get(pos), Some('!') | Some('^'));
+    if negate {
+        pos += 1;
+    }
+
+    let mut matched = false;
+    let start = pos;
+    while let Some(&current) = pattern.get(pos) {
+        // `]` right after the opening bracket is a member, not the end
+        if current == ']' && pos > start {
+            return Some((matched != negate, pos + 1));
+        }
+        if pattern.get(pos + 1) == Some(&'-') && pattern.get(pos + 2).is_some_and(|end| *end != ']') {
+            let end = pattern[pos + 2];
+            matched |= c.is_some_and(|c| current <= *c && *c <= end);
+            pos += 3;
+        } else {
+            matched |= c == Some(&current);
+            pos += 1;
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.pl", "home.pl"));
+        assert!(!glob_match("*.pl", "home.pl.bak"));
+        assert!(glob_match("h?me.*", "home.pl"));
+        assert!(glob_match("[a-h]ome.pl", "home.pl"));
+        assert!(!glob_match("[!h]ome.pl", "home.pl"));
+        assert!(glob_match("[]x]", "]"));
+        assert!(glob_match("\\*", "*"));
+        assert!(!glob_match("\\*", "a"));
+        assert!(glob_match("[abc", "[abc"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*a*b*", "xaybz"));
+        assert!(!glob_match("*a*b", "xaybz"));
+        assert!(glob_match("\\**", "*x"));
+        // stars don't make it exponential
+        assert!(!glob_match(&"*a".repeat(30), &"a".repeat(60).replace("aa", "ab")));
+    }
+
+    #[test]
+    fn test_escape_glob() {
+        assert_eq!(escape_glob("a*b\\"), "a\\*b\\\\");
+        assert_eq!(unescape_glob(&escape_glob("[x]?*\\")), "[x]?*\\");
+        assert!(glob_match(&escape_glob("*.pl"), "*.pl"));
+        assert!(!glob_match(&escape_glob("*.pl"), "a.pl"));
+    }
+}
@@ -0,0 +1,105 @@
+use std::io::{Error, ErrorKind};
+
+pub const READ: u16 = 0o4;
+pub const WRITE: u16 = 0o2;
+pub const EXEC: u16 = 0o1;
+
+pub const DEFAULT_DIR_MODE: u16 = 0o777;
+pub const DEFAULT_FILE_MODE: u16 = 0o666;
+pub const DEFAULT_UMASK: u16 = 0o022;
+
+/// `ls -l` style mode, e.g. `drwxr-xr-x`.
+pub fn mode_string(mode: u16, file_type: char) -> String {
+    let mut result = String::from(file_type);
+
+    for shift in [6, 3, 0] {
+        let bits = (mode >> shift) & 0o7;
+        result.push(if bits & READ != 0 { 'r' } else { '-' });
+        result.push(if bits & WRITE != 0 { 'w' } else { '-' });
+        result.push(if bits & EXEC != 0 { 'x' } else { '-' });
+    }
+
+    result
+}
+
+pub fn parse_octal(spec: &str) -> Option<u16> {
+    if spec.is_empty() || spec.len() > 4 {
+        return None;
+    }
+    u16::from_str_radix(spec, 8).ok().filter(|mode| *mode <= 0o777)
+}
+
+/// Applies a `chmod` mode to `current`: octal (`755`) or symbolic (`u+x,go-w`, `a=r`).
+pub fn parse_mode(spec: &str, current: u16) -> Result<u16, Error> {
+    if spec.chars().all(|c| c.is_ascii_digit()) {
+        return parse_octal(spec)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("invalid mode: {}", spec)));
+    }
+
+    let invalid = || Error::new(ErrorKind::InvalidInput, format!("invalid mode: {}", spec));
+    let mut mode = current;
+
+    for clause in spec.split(',') {
+        let op_pos = clause.find(['+', '-', '=']).ok_or_else(invalid)?;
+        let (who, rest) = clause.split_at(op_pos);
+        let (op, perms) = rest.split_at(1);
+
+        let mut who_mask = 0;
+        for c in who.chars() {
+            who_mask |= match c {
+                'u' => 0o700,
+                'g' => 0o070,
+                'o' => 0o007,
+                'a' => 0o777,
+                _ => return Err(invalid()),
+            };
+        }
+        if who_mask == 0 {
+            who_mask = 0o777;
+        }
+
+        let mut bits = 0;
+        for c in perms.chars() {
+            bits |= match c {
+                'r' => 0o444,
+                'w' => 0o222,
+                'x' => 0o111,
+                _ => return Err(invalid()),
+            };
+        }
+        bits &= who_mask;
+
+        match op {
+            "+" => mode |= bits,
+            "-" => mode &= !bits,
+            _ => mode = (mode & !who_mask) | bits,
+        }
+    }
+
+    Ok(mode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mode_string() {
+        assert_eq!(mode_string(0o755, 'd'), "drwxr-xr-x");
+        assert_eq!(mode_string(0o640, '-'), "-rw-r-----");
+        assert_eq!(mode_string(0o777, 'l'), "lrwxrwxrwx");
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(parse_mode("755", 0).unwrap(), 0o755);
+        assert_eq!(parse_mode("0644", 0).unwrap(), 0o644);
+        assert_eq!(parse_mode("u+x", 0o644).unwrap(), 0o744);
+        assert_eq!(parse_mode("go-r", 0o644).unwrap(), 0o600);
+        assert_eq!(parse_mode("+x", 0o644).unwrap(), 0o755);
+        assert_eq!(parse_mode("u=rw,g=r,o=", 0o777).unwrap(), 0o640);
+        assert!(parse_mode("888", 0).is_err());
+        assert!(parse_mode("z+x", 0).is_err());
+        assert!(parse_mode("u+q", 0).is_err());
+    }
+}
@@ -1,39 +1,52 @@
 use clap::Parser;
 
-use std::fs::{self, File};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs::File;
 use std::io::Error;
 use std::io::{self, BufRead, ErrorKind};
 use std::path::Path;
 
-use crate::window::polina_vfs::{SHELL_USER};
+use crate::vfs::backend::{open_backend, VfsBackend};
+use crate::vfs::permissions::{
+    mode_string, parse_mode, parse_octal, DEFAULT_DIR_MODE, DEFAULT_FILE_MODE, DEFAULT_UMASK, EXEC, READ,
+    WRITE,
+};
+use crate::vfs::time::{format_time, now};
+use crate::vfs::tree::{NodeId, VFSNode, VFSNodeKind, VFSTree};
+use crate::window::polina_vfs::SHELL_USER;
+
+// same limit as linux, loops like `a -> b -> a` give up instead of spinning
+const MAX_SYMLINK_EXPANSIONS: usize = 40;
+
+// loaded when no `--storage` is given, changes to it are only kept by `sync`
+pub const DEFAULT_STORAGE: &str = "./storage";
 
 // paths
 #[derive(Parser, Debug, Clone)]
 pub struct VFSArgs {
-    #[arg(long, default_value = "./storage")]
+    #[arg(long)]
     pub storage: Option<String>,
 
     #[arg(long)]
     pub startapp: Option<String>,
-}
 
-#[derive(Clone, Debug)]
-pub enum VFSNode {
-    File {
-        name: String,
-        owner: String
-    },
-    Dir {
-        name: String,
-        children: Vec<VFSNode>,
-        owner: String
-    },
+    // `$1` and on of the startapp script
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    pub script_args: Vec<String>,
 }
+
+#[allow(clippy::upper_case_acronyms)]
 pub struct VFS {
-    root: VFSNode,
+    pub(super) tree: VFSTree,
     pub user: String,
-    sys_path_name: String,
-    current_path: String
+    pub umask: u16,
+    backend: Box<dyn VfsBackend>,
+    pub(super) cwd: NodeId,
+    // groups of a user as of a tree generation, access checks run for every
+    // part of a path and `/etc/group` is only parsed again once something
+    // in the tree was changed, reads don't count
+    groups_cache: RefCell<Option<(u64, String, Vec<String>)>>,
 }
 
 impl VFSArgs {
@@ -42,7 +55,7 @@ impl VFSArgs {
             if Path::new(path).exists() {
                 if let Ok(file) = File::open(path) {
                     let reader = io::BufReader::new(file);
-                    return reader.lines().filter_map(|line| line.ok()).collect();
+                    return reader.lines().map_while(Result::ok).collect();
                 }
             }
         }
@@ -52,203 +65,734 @@ impl VFSArgs {
 
 impl VFS {
     pub fn new(user: String, storage_path: String) -> Result<Self, Error> {
-        let mut root = VFSNode::Dir {
-            name: "/".to_string(),
-            children: vec![],
-            owner: SHELL_USER.to_string()
-        };
-        VFS::init_dir_reader(storage_path.clone(), &mut root).unwrap();
+        VFS::with_backend(user, open_backend(&storage_path))
+    }
 
-        Ok(VFS {
-            root: root,
+    pub fn with_backend(user: String, backend: Box<dyn VfsBackend>) -> Result<Self, Error> {
+        let mut vfs = VFS {
+            tree: backend.load()?,
             user,
-            sys_path_name: storage_path.clone(),
-            current_path: "/".to_string()
-        })
-    }
-
-    fn init_dir_reader(sys_path: String, node: &mut VFSNode) -> std::io::Result<()> {
-        match node {
-            VFSNode::Dir { owner, name: _, children } => {
-                for entry in fs::read_dir(sys_path)? {
-                    let entry = entry?;
-                    let entry_path = entry.path();
-                    let entry_name = entry.file_name().into_string().unwrap_or_default();
-
-                    let mut child_node = if entry_path.is_dir() {
-                        VFSNode::Dir {
-                            name: entry_name.clone(),
-                            children: vec![],
-                            owner: owner.to_string()
-                        }
-                    } else {
-                        VFSNode::File {
-                            name: entry_name.clone(),
-                            owner: owner.to_string()
-                        }
-                    };
-
-                    if entry_path.is_dir() {
-                        Self::init_dir_reader(
-                            entry_path.to_str().unwrap().to_string(),
-                            &mut child_node,
-                        )?;
-                    }
+            umask: DEFAULT_UMASK,
+            backend,
+            cwd: VFSTree::ROOT,
+            groups_cache: RefCell::new(None),
+        };
+        vfs.ensure_accounts();
 
-                    children.push(child_node);
-                }
-            }
+        Ok(vfs)
+    }
 
-            VFSNode::File { name: _, owner: _ } => {}
-        }
+    fn permission_denied(path: &str) -> Error {
+        Error::new(ErrorKind::PermissionDenied, format!("{}: Permission denied", path))
+    }
 
-        Ok(())
+    fn is_root(&self) -> bool {
+        self.user == SHELL_USER
     }
 
-    pub fn get_path_from_node(&self, node: &VFSNode) -> Result<String, Error> {
-        fn dfs(current: &VFSNode, target: &VFSNode, path: &mut Vec<String>) -> bool {
-            if std::ptr::eq(current, target) {
-                return true;
+    /// Names of the groups the current user belongs to, from `/etc/group`.
+    pub fn user_groups(&self) -> Vec<String> {
+        let generation = self.tree.generation();
+        if let Some((cached, user, groups)) = self.groups_cache.borrow().as_ref() {
+            if *cached == generation && *user == self.user {
+                return groups.clone();
             }
+        }
 
-            match current {
-                VFSNode::Dir { children, .. } => {
-                    for child in children {
-                        match child {
-                            VFSNode::Dir { name, owner: _, ..} | VFSNode::File { name, owner: _ } => {
-                                path.push(name.clone());
-                            }
-                        }
+        let groups: Vec<String> = self
+            .groups_of(&self.user)
+            .into_iter()
+            .map(|group| group.name)
+            .collect();
+        *self.groups_cache.borrow_mut() = Some((generation, self.user.clone(), groups.clone()));
+        groups
+    }
 
-                        if dfs(child, target, path) {
-                            return true;
-                        }
+    /// Checks `access` (`READ`/`WRITE`/`EXEC` bits) against the owner, group or
+    /// other triplet of the node, root is never restricted.
+    pub(super) fn check_access(&self, id: NodeId, access: u16) -> Result<(), Error> {
+        if self.is_root() {
+            return Ok(());
+        }
 
-                        path.pop();
-                    }
-                }
-                _ => {}
-            }
+        let node = self.tree.get(id);
+        let bits = if node.owner == self.user {
+            node.mode >> 6
+        } else if self.user_groups().contains(&node.group) {
+            node.mode >> 3
+        } else {
+            node.mode
+        } & 0o7;
 
-            false
+        if bits & access == access {
+            Ok(())
+        } else {
+            Err(VFS::permission_denied(&self.tree.path_of(id)))
         }
+    }
 
-        let mut path = Vec::new();
+    /// Entries the backend left out while loading, shown before the first prompt.
+    pub fn load_warnings(&self) -> Vec<String> {
+        self.backend.warnings()
+    }
+
+    /// Writes the in-memory tree back through the backend it was loaded from.
+    pub fn sync(&self) -> Result<(), Error> {
+        self.backend.save(&self.tree)
+    }
+
+    pub fn current_path(&self) -> String {
+        self.tree.path_of(self.cwd)
+    }
+
+    /// Home dir of the current user from `/etc/passwd`, falls back to the
+    /// root when the account or the dir is missing.
+    pub fn home_dir(&self) -> String {
+        let home = match self.find_user(&self.user) {
+            Some(user) => user.home,
+            None => return "/".to_string(),
+        };
 
-        if std::ptr::eq(&self.root, node) {
-            return Ok("/".to_string());
+        match self.tree.lookup(&home) {
+            Some(id) if self.tree.get(id).is_dir() => home,
+            _ => "/".to_string(),
         }
+    }
 
-        if dfs(&self.root, node, &mut path) {
-            Ok(format!("/{}", path.join("/")))
+    fn absolute_path(&self, path: &str) -> String {
+        if path == "~" || path.starts_with("~/") {
+            format!("{}/{}", self.home_dir(), &path[1..])
+        } else if path.starts_with('/') {
+            path.to_string()
         } else {
-            Err(Error::new(ErrorKind::NotFound, "node not found"))
+            format!("{}/{}", self.current_path(), path)
         }
     }
 
-    fn get_node_from_path(&mut self, path: &String) -> Result<&mut VFSNode, Error> {
-        let mut current_obj: &mut VFSNode = &mut self.root;
+    /// Turns any user-given path into the canonical absolute path of an
+    /// existing node: `~`, `.`, `..` and every symlink on the way resolved.
+    pub fn canonical_path(&self, path: &str) -> Result<String, Error> {
+        Ok(self.tree.path_of(self.resolve_path(path)?))
+    }
 
-        let full_path = if path.starts_with("/") {
-            path.clone()
-        } else if self.current_path == "/" {
-            format!("/{}", path)
-        } else {
-            format!("{}/{}", self.current_path, path)
-        };
+    fn resolve_path(&self, path: &str) -> Result<NodeId, Error> {
+        self.resolve(path, true)
+    }
 
-        let parts: Vec<&str> = full_path.split("/").filter(|s| !s.is_empty()).collect();
+    /// Walks the path from the root expanding symlinks on the way, the last
+    /// one only if `follow_last` is set. `..` is taken physically, after the
+    /// link was expanded, like the kernel does.
+    pub(super) fn resolve(&self, path: &str, follow_last: bool) -> Result<NodeId, Error> {
+        let mut parts: VecDeque<String> = self
+            .absolute_path(path)
+            .split('/')
+            .map(str::to_string)
+            .collect();
+        let mut current = VFSTree::ROOT;
+        let mut expansions = 0;
 
-        if parts.len() == 0 {
-            return Ok(current_obj);
-        }
+        while let Some(obj) = parts.pop_front() {
+            match obj.as_str() {
+                "" | "." => continue,
+                ".." => {
+                    current = self.tree.parent(current).unwrap_or(VFSTree::ROOT);
+                    continue;
+                }
+                _ => {}
+            }
 
-        for obj in parts {
-            match current_obj {
-                VFSNode::Dir { children, .. } => {
-                    if let Some(child) = children.iter_mut().find(|c| match c {
-                        VFSNode::Dir { name, .. } => name == obj,
-                        VFSNode::File { name, owner: _ } => name == obj,
-                    }) {
-                        current_obj = child;
-                    } else {
+            if !self.tree.get(current).is_dir() {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!("{} is a file, not a directory", self.tree.name(current)),
+                ));
+            }
+
+            self.check_access(current, EXEC)?;
+            let child = self.tree.child(current, &obj).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::NotFound,
+                    format!("{}: no such file or directory", obj),
+                )
+            })?;
+
+            match &self.tree.get(child).kind {
+                VFSNodeKind::Symlink { target }
+                    if follow_last || parts.iter().any(|part| !part.is_empty()) =>
+                {
+                    expansions += 1;
+                    if expansions > MAX_SYMLINK_EXPANSIONS {
                         return Err(Error::new(
-                            ErrorKind::NotFound,
-                            format!("dir not found: {}", obj),
+                            ErrorKind::InvalidInput,
+                            format!("{}: Too many levels of symbolic links", path),
                         ));
                     }
+                    if target.starts_with('/') {
+                        current = VFSTree::ROOT;
+                    }
+                    for part in target.split('/').rev() {
+                        parts.push_front(part.to_string());
+                    }
                 }
-                VFSNode::File { .. } => {
-                    return Err(Error::new(
-                        ErrorKind::NotFound,
-                        format!("{} is a file, not a directory", obj),
-                    ));
-                }
+                _ => current = child,
+            }
+        }
+
+        Ok(current)
+    }
+
+    /// Splits a path about to be created into its existing parent dir and the
+    /// new name, the parent must be writable.
+    fn resolve_parent(&self, path: &str) -> Result<(NodeId, String), Error> {
+        let trimmed = path.trim_end_matches('/');
+        let (parent_path, name) = match trimmed.rsplit_once('/') {
+            Some(("", name)) => ("/", name),
+            Some((parent, name)) => (parent, name),
+            None => (".", trimmed),
+        };
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("{}: invalid name", path)));
+        }
+
+        let parent = self.resolve_path(parent_path)?;
+        if !self.tree.get(parent).is_dir() {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("{}: not a dir", parent_path)));
+        }
+        self.check_access(parent, WRITE | EXEC)?;
+
+        Ok((parent, name.to_string()))
+    }
+
+    /// Where `source` lands for `ln`, `mv` and `cp`: inside `destination` if
+    /// it is a dir, otherwise under the name `destination` itself.
+    fn resolve_destination(&self, source: &str, destination: &str) -> Result<(NodeId, String), Error> {
+        match self.resolve_path(destination) {
+            Ok(id) if self.tree.get(id).is_dir() => {
+                self.check_access(id, WRITE | EXEC)?;
+                let name = source.trim_end_matches('/').rsplit('/').next().unwrap_or_default();
+                Ok((id, name.to_string()))
             }
+            _ => self.resolve_parent(destination),
+        }
+    }
+
+    /// Nodes the shell still stands in can't go away, the cwd would dangle.
+    pub(super) fn check_not_busy(&self, id: NodeId, path: &str) -> Result<(), Error> {
+        if self.tree.is_within(self.cwd, id) {
+            return Err(Error::new(
+                ErrorKind::ResourceBusy,
+                format!("{}: Device or resource busy", path),
+            ));
         }
+        Ok(())
+    }
 
-        Ok(current_obj)
+    /// Group new nodes get, the user's primary one.
+    fn primary_group(&self) -> String {
+        self.user_groups().into_iter().next().unwrap_or_else(|| self.user.clone())
     }
 
     pub fn change_dir(&mut self, args: Vec<String>) -> Result<&VFSNode, Error> {
-        /*
-            TODO: FIX THIS!
-            It's ignoring borrow checker, bad practice
-        */
         let path = if args.is_empty() {
-            "/".to_string()
+            self.home_dir()
         } else if args.len() > 1 {
             return Err(Error::new(ErrorKind::InvalidInput, "too many args"));
         } else {
             args[0].clone()
         };
 
-        let node_ptr: *const VFSNode = {
-            let node_ref = self.get_node_from_path(&path)?;
-            node_ref as *const VFSNode
+        let id = self.resolve_path(&path)?;
+        if !self.tree.get(id).is_dir() {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("{}: not a dir", self.tree.name(id))));
+        }
+        self.check_access(id, EXEC)?;
+
+        self.cwd = id;
+
+        Ok(self.tree.get(id))
+    }
+
+    /// Entries of a dir as `(name, node)`, names are kept apart since hard
+    /// links share one node. Anything else is listed as itself like `ls` does,
+    /// a symlink to a file shows the link.
+    pub fn list_dir(&mut self, path: &str) -> Result<Vec<(String, &VFSNode)>, Error> {
+        let id = match self.resolve_path(path) {
+            Ok(id) if self.tree.get(id).is_dir() => id,
+            _ => {
+                let id = self.resolve(path, false)?;
+                return Ok(vec![(path.to_string(), self.tree.get(id))]);
+            }
         };
+        self.check_access(id, READ)?;
+        self.tree.touch_atime(id);
 
-        let new_path = {
-            let node_ref = unsafe { &*node_ptr };
-            self.get_path_from_node(node_ref)?
+        Ok(self
+            .tree
+            .children(id)
+            .iter()
+            .map(|&child| (self.tree.name(child).to_string(), self.tree.get(child)))
+            .collect())
+    }
+
+    pub fn read_file(&mut self, path: &String) -> Result<&Vec<u8>, Error> {
+        let id = self.resolve_path(path)?;
+        if !self.tree.get(id).is_dir() {
+            self.check_access(id, READ)?;
+            self.tree.touch_atime(id);
+        }
+        match &self.tree.get(id).kind {
+            VFSNodeKind::File { content } => Ok(content),
+            _ => Err(Error::new(ErrorKind::InvalidInput, format!("{}: is a directory", path))),
+        }
+    }
+
+    /// Writes into a file for a redirection, `append` keeps the old content.
+    /// A missing file is created with the umask applied.
+    pub fn write_file(&mut self, path: &str, data: &[u8], append: bool) -> Result<(), Error> {
+        let id = match self.resolve_path(path) {
+            Ok(id) => {
+                if self.tree.get(id).is_dir() {
+                    return Err(Error::new(ErrorKind::InvalidInput, format!("{}: is a directory", path)));
+                }
+                self.check_access(id, WRITE)?;
+                id
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => self.create_file(path, err)?,
+            Err(err) => return Err(err),
         };
 
-        self.current_path = new_path;
+        // opening for append writes nothing, the file stays untouched
+        if append && data.is_empty() {
+            return Ok(());
+        }
+        let node = self.tree.get_mut(id);
+        if let VFSNodeKind::File { content } = &mut node.kind {
+            if !append {
+                content.clear();
+            }
+            content.extend_from_slice(data);
+        }
+        node.modified();
 
-        Ok(unsafe { &*node_ptr })
+        Ok(())
     }
 
-    pub fn list_dir(&mut self, args: Vec<String>) -> Result<&Vec<VFSNode>, Error> {
-        let path = if args.is_empty() {
-            self.current_path.clone()
-        } else if args.len() > 1 {
-            return Err(Error::new(ErrorKind::InvalidInput, "too many args"));
+    /// Creates an empty file at `path` that failed to resolve with `err`. A
+    /// dangling symlink in its place gets the file created where it points,
+    /// like the shell does for `echo hi > link`.
+    fn create_file(&mut self, path: &str, err: Error) -> Result<NodeId, Error> {
+        let mut path = path.to_string();
+
+        for expansions in 0..=MAX_SYMLINK_EXPANSIONS {
+            let (parent, name) = match self.resolve_parent(&path) {
+                Ok(found) => found,
+                // where a link points can be missing too, that is the
+                // link's own error
+                Err(_) if expansions > 0 => return Err(err),
+                Err(parent_err) => return Err(parent_err),
+            };
+            let Some(existing) = self.tree.child(parent, &name) else {
+                let node = VFSNode::new(
+                    self.user.clone(),
+                    self.primary_group(),
+                    DEFAULT_FILE_MODE & !self.umask,
+                    VFSNodeKind::File { content: vec![] },
+                );
+                return Ok(self.tree.add_child(parent, name, node));
+            };
+
+            path = match &self.tree.get(existing).kind {
+                VFSNodeKind::Symlink { target } if target.starts_with('/') => target.clone(),
+                VFSNodeKind::Symlink { target } => format!("{}/{}", self.tree.path_of(parent), target),
+                _ => return Err(err),
+            };
+        }
+
+        Err(err)
+    }
+
+    /// `ln [-s]`: a symlink stores `target` as is, a hard link shares the node
+    /// of an existing file. If `link_path` is a dir the link goes inside it.
+    pub fn create_link(&mut self, target: &str, link_path: &str, symbolic: bool, force: bool) -> Result<(), Error> {
+        let (parent, name) = self.resolve_destination(target, link_path)?;
+
+        // hard links point at the node itself, a symlink target may not exist yet
+        let target_id = if symbolic {
+            None
         } else {
-            args[0].clone()
+            let id = self.resolve(target, false)?;
+            if self.tree.get(id).is_dir() {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{}: hard link not allowed for directory", target),
+                ));
+            }
+            Some(id)
         };
 
-        let node = self.get_node_from_path(&path)?;
-        match node {
-            VFSNode::Dir { name: _, owner: _, children } => {
-                Ok(children)
+        if let Some(existing) = self.tree.child(parent, &name) {
+            if !force {
+                return Err(Error::new(ErrorKind::AlreadyExists, format!("{}: File exists", link_path)));
             }
-            VFSNode::File { name, owner: _ } => {
-                return Err(Error::new(ErrorKind::InvalidInput, format!("{}: not a dir", name)));
+            if self.tree.get(existing).is_dir() {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{}: cannot overwrite directory", link_path),
+                ));
             }
+            if target_id.is_some_and(|id| self.tree.node_index(id) == self.tree.node_index(existing)) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{} and {} are the same file", target, link_path),
+                ));
+            }
+            self.tree.remove(existing);
+        }
+
+        match target_id {
+            Some(id) => {
+                self.tree.add_link(parent, name, id);
+            }
+            None => {
+                self.tree.add_child(
+                    parent,
+                    name,
+                    VFSNode::new(
+                        self.user.clone(),
+                        self.primary_group(),
+                        0o777,
+                        VFSNodeKind::Symlink { target: target.to_string() },
+                    ),
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `readlink`: the stored target of a symlink, or with `canonicalize` the
+    /// absolute path with every link on the way resolved (`readlink -f`).
+    pub fn read_link(&self, path: &str, canonicalize: bool) -> Result<String, Error> {
+        if canonicalize {
+            return self.canonical_path(path);
+        }
+
+        match &self.tree.get(self.resolve(path, false)?).kind {
+            VFSNodeKind::Symlink { target } => Ok(target.clone()),
+            _ => Err(Error::new(ErrorKind::InvalidInput, format!("{}: not a symbolic link", path))),
         }
     }
 
+    /// `chown` with an `owner[:group]` or `:group` spec, both must exist in
+    /// the account db.
     pub fn set_node_owner(&mut self, node_path: String, new_owner: String) -> Result<(), Error> {
-        let node_ref = self.get_node_from_path(&node_path)?;
+        let id = self.resolve_path(&node_path)?;
+        // like on a real system only root can give files away
+        if !self.is_root() {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("{}: Operation not permitted", node_path),
+            ));
+        }
+
+        let (owner, group) = match new_owner.split_once(':') {
+            Some((owner, group)) => (owner, Some(group)),
+            None => (new_owner.as_str(), None),
+        };
+        // `:group` leaves the owner as it is
+        let owner = Some(owner).filter(|owner| !owner.is_empty() || group.is_none());
+        if let Some(owner) = owner {
+            if self.find_user(owner).is_none() {
+                return Err(Error::new(ErrorKind::InvalidInput, format!("invalid user: '{}'", owner)));
+            }
+        }
+        if let Some(group) = group {
+            if self.find_group(group).is_none() {
+                return Err(Error::new(ErrorKind::InvalidInput, format!("invalid group: '{}'", group)));
+            }
+        }
+
+        let node = self.tree.get_mut(id);
+        if let Some(owner) = owner {
+            node.owner = owner.to_string();
+        }
+        if let Some(group) = group {
+            node.group = group.to_string();
+        }
+        node.changed();
+
+        Ok(())
+    }
+
+    pub fn set_node_mode(&mut self, node_path: String, mode_spec: &str) -> Result<(), Error> {
+        let id = self.resolve_path(&node_path)?;
+        if !self.is_root() && self.tree.get(id).owner != self.user {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("{}: Operation not permitted", node_path),
+            ));
+        }
+
+        let node = self.tree.get_mut(id);
+        node.mode = parse_mode(mode_spec, node.mode)?;
+        node.changed();
+
+        Ok(())
+    }
+
+    pub fn is_dir(&self, path: &str) -> bool {
+        self.resolve_path(path).is_ok_and(|id| self.tree.get(id).is_dir())
+    }
+
+    /// `mkdir [-p]`, with `parents` missing dirs on the way are created and
+    /// an existing dir is not an error.
+    pub fn make_dir(&mut self, path: &str, parents: bool) -> Result<(), Error> {
+        if parents {
+            let parts: Vec<&str> = path.split('/').collect();
+            for end in 1..=parts.len() {
+                let prefix = parts[..end].join("/");
+                if !prefix.is_empty() && self.resolve_path(&prefix).is_err() {
+                    self.make_dir(&prefix, false)?;
+                }
+            }
+            if self.is_dir(path) {
+                return Ok(());
+            }
+        }
 
-        match node_ref {
-            VFSNode::Dir { owner, .. } => {
-                *owner = new_owner;
+        if self.resolve(path, false).is_ok() {
+            return Err(Error::new(ErrorKind::AlreadyExists, format!("{}: File exists", path)));
+        }
+        let (parent, name) = self.resolve_parent(path)?;
+        let node = VFSNode::new(
+            self.user.clone(),
+            self.primary_group(),
+            DEFAULT_DIR_MODE & !self.umask,
+            VFSNodeKind::Dir { children: vec![] },
+        );
+        self.tree.add_child(parent, name, node);
+
+        Ok(())
+    }
+
+    /// `rmdir`, only empty dirs.
+    pub fn remove_dir(&mut self, path: &str) -> Result<(), Error> {
+        let id = self.resolve(path, false)?;
+        if !self.tree.get(id).is_dir() {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("{}: not a dir", path)));
+        }
+        if !self.tree.children(id).is_empty() {
+            return Err(Error::new(ErrorKind::DirectoryNotEmpty, format!("{}: Directory not empty", path)));
+        }
+        self.remove_entry(id, path)
+    }
+
+    /// `rm [-r] [-f]`, symlinks are removed themselves, not their targets.
+    pub fn remove_node(&mut self, path: &str, recursive: bool, force: bool) -> Result<(), Error> {
+        let id = match self.resolve(path, false) {
+            Ok(id) => id,
+            Err(err) if force && err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        if self.tree.get(id).is_dir() {
+            if !recursive {
+                return Err(Error::new(ErrorKind::InvalidInput, format!("{}: Is a directory", path)));
             }
-            VFSNode::File { owner, .. } => {
-                *owner = new_owner;
+            self.check_subtree_access(id, READ | WRITE | EXEC)?;
+        }
+        self.remove_entry(id, path)
+    }
+
+    fn remove_entry(&mut self, id: NodeId, path: &str) -> Result<(), Error> {
+        let parent = self.tree.parent(id).ok_or_else(|| {
+            Error::new(ErrorKind::PermissionDenied, format!("{}: cannot remove the root", path))
+        })?;
+        self.check_access(parent, WRITE | EXEC)?;
+        self.check_not_busy(id, path)?;
+
+        self.tree.remove(id);
+        Ok(())
+    }
+
+    /// `access` on every dir of a subtree, a recursive `rm` or `cp` must be
+    /// able to get through all of them before touching anything.
+    fn check_subtree_access(&self, id: NodeId, access: u16) -> Result<(), Error> {
+        if self.tree.get(id).is_dir() {
+            self.check_access(id, access)?;
+            for &child in self.tree.children(id) {
+                self.check_subtree_access(child, access)?;
             }
+        }
+        Ok(())
+    }
+
+    /// Clears the spot for a `mv`/`cp` onto an existing name, like coreutils
+    /// only a non-dir may be replaced and only by a non-dir.
+    fn replace_existing(&mut self, source: NodeId, parent: NodeId, name: &str, destination: &str) -> Result<(), Error> {
+        let Some(existing) = self.tree.child(parent, name) else {
+            return Ok(());
         };
+        if self.tree.node_index(existing) == self.tree.node_index(source) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} and {} are the same file", self.tree.path_of(source), destination),
+            ));
+        }
+        if self.tree.get(existing).is_dir() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{}: cannot overwrite directory", destination),
+            ));
+        }
+        if self.tree.get(source).is_dir() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{}: cannot overwrite non-directory with directory", destination),
+            ));
+        }
+
+        self.tree.remove(existing);
+        Ok(())
+    }
+
+    /// `mv`: renames in place or moves into another dir, the node itself is
+    /// kept so hard links and inode numbers survive.
+    pub fn move_node(&mut self, source: &str, destination: &str) -> Result<(), Error> {
+        let id = self.resolve(source, false)?;
+        let old_parent = self.tree.parent(id).ok_or_else(|| {
+            Error::new(ErrorKind::InvalidInput, format!("{}: cannot move the root", source))
+        })?;
+        self.check_access(old_parent, WRITE | EXEC)?;
+
+        let (parent, name) = self.resolve_destination(source, destination)?;
+        if self.tree.is_within(parent, id) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("cannot move '{}' to a subdirectory of itself", source),
+            ));
+        }
+        if self.tree.child(parent, &name) == Some(id) {
+            return Ok(());
+        }
+        self.replace_existing(id, parent, &name, destination)?;
+
+        self.tree.move_entry(id, parent, name);
+        Ok(())
+    }
+
+    /// `cp [-r]`: copies get new nodes owned by the current user, the mode is
+    /// kept minus the umask. Symlinks inside a copied dir stay links.
+    pub fn copy_node(&mut self, source: &str, destination: &str, recursive: bool) -> Result<(), Error> {
+        let id = self.resolve_path(source)?;
+        if self.tree.get(id).is_dir() {
+            if !recursive {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("-r not specified; omitting directory '{}'", source),
+                ));
+            }
+            self.check_subtree_access(id, READ | EXEC)?;
+        }
+
+        let (parent, name) = self.resolve_destination(source, destination)?;
+        if self.tree.is_within(parent, id) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("cannot copy '{}' into itself", source),
+            ));
+        }
+        self.replace_existing(id, parent, &name, destination)?;
+
+        self.copy_subtree(id, parent, name)
+    }
+
+    fn copy_subtree(&mut self, source: NodeId, parent: NodeId, name: String) -> Result<(), Error> {
+        self.check_access(source, READ)?;
+
+        let node = self.tree.get(source);
+        let kind = match &node.kind {
+            VFSNodeKind::Dir { .. } => VFSNodeKind::Dir { children: vec![] },
+            kind => kind.clone(),
+        };
+        let copy = VFSNode::new(self.user.clone(), self.primary_group(), node.mode & !self.umask, kind);
+        let copy = self.tree.add_child(parent, name, copy);
+
+        for child in self.tree.children(source).to_vec() {
+            let name = self.tree.name(child).to_string();
+            self.copy_subtree(child, copy, name)?;
+        }
+
+        Ok(())
+    }
+
+    /// `stat` output of a node, symlinks are described, not followed.
+    pub fn stat(&self, path: &str) -> Result<String, Error> {
+        let id = self.resolve(path, false)?;
+        let node = self.tree.get(id);
+
+        let (file_type, name) = match &node.kind {
+            VFSNodeKind::File { .. } => ("regular file", path.to_string()),
+            VFSNodeKind::Dir { .. } => ("directory", path.to_string()),
+            VFSNodeKind::Symlink { target } => ("symbolic link", format!("{} -> {}", path, target)),
+        };
+        let uid = self.find_user(&node.owner).map_or("?".to_string(), |user| user.uid.to_string());
+        let gid = self.find_group(&node.group).map_or("?".to_string(), |group| group.gid.to_string());
+
+        Ok(format!(
+            "  File: {}\n  Size: {}\t{}\n Inode: {}\tLinks: {}\nAccess: ({:04o}/{})  Uid: ({}/{})  Gid: ({}/{})\nAccess: {}\nModify: {}\nChange: {}\n",
+            name,
+            node.size(),
+            file_type,
+            self.tree.inode(id),
+            node.links,
+            node.mode,
+            mode_string(node.mode, node.file_type()),
+            uid,
+            node.owner,
+            gid,
+            node.group,
+            format_time(node.atime),
+            format_time(node.mtime),
+            format_time(node.ctime),
+        ))
+    }
+
+    /// `touch`: sets the access and/or modification time to now, a missing
+    /// file is created empty unless `no_create` is set.
+    pub fn touch(&mut self, path: &str, access: bool, modify: bool, no_create: bool) -> Result<(), Error> {
+        let id = match self.resolve_path(path) {
+            Ok(id) => id,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                if no_create {
+                    return Ok(());
+                }
+                self.create_file(path, err)?;
+                return Ok(());
+            }
+            Err(err) => return Err(err),
+        };
+
+        // the owner may set any time, others need write access like on linux
+        if self.tree.get(id).owner != self.user {
+            self.check_access(id, WRITE)?;
+        }
+
+        let time = now();
+        let node = self.tree.get_mut(id);
+        if access {
+            node.atime = time;
+        }
+        if modify {
+            node.mtime = time;
+        }
+        node.ctime = time;
+
+        Ok(())
+    }
+
+    pub fn set_umask(&mut self, spec: &str) -> Result<(), Error> {
+        self.umask = parse_octal(spec)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("invalid mask: {}", spec)))?;
 
         Ok(())
     }
@@ -258,19 +802,291 @@ impl VFS {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::vfs::backend::META_FILE_NAME;
+    use std::fs;
 
     #[test]
     fn test_init() {
         let vfs = VFS::new("".to_string(), "./storage".to_string()).unwrap();
-        println!("{:?} {}", vfs.root, vfs.current_path);
+        println!("{:?} {}", vfs.tree, vfs.current_path());
+    }
+
+    #[test]
+    fn test_script_args() {
+        let args = VFSArgs::try_parse_from(["polina-shell", "--startapp", "home.pl", "-v", "two words"]).unwrap();
+        assert_eq!(args.startapp.as_deref(), Some("home.pl"));
+        assert_eq!(args.script_args, vec!["-v", "two words"]);
+
+        let args = VFSArgs::try_parse_from(["polina-shell", "--startapp", "home.pl", "--", "--storage"]).unwrap();
+        assert_eq!(args.storage, None);
+        assert_eq!(args.script_args, vec!["--storage"]);
     }
 
     #[test]
     fn test_found_dir() {
-        let mut vfs = VFS::new("".to_string(),"./storage".to_string()).unwrap();
-        let _ = vfs.get_node_from_path(&"/".to_string());
-        let _ = vfs.get_node_from_path(&"xd/double/r".to_string());
-        let _ = vfs.get_node_from_path(&"/xddddd".to_string());
-        let _ = vfs.get_node_from_path(&"test/second_dir".to_string());
+        let vfs = VFS::new("".to_string(),"./storage".to_string()).unwrap();
+        let _ = vfs.resolve_path("/");
+        let _ = vfs.resolve_path("xd/double/r");
+        let _ = vfs.resolve_path("/xddddd");
+        let _ = vfs.resolve_path("test/second_dir");
+    }
+
+    #[test]
+    fn test_canonical_path() {
+        let mut vfs = VFS::new("".to_string(), "./storage".to_string()).unwrap();
+        assert_eq!(vfs.canonical_path("/").unwrap(), "/");
+        assert_eq!(vfs.canonical_path("//test///second_dir/").unwrap(), "/test/second_dir");
+        assert_eq!(vfs.canonical_path("../../test").unwrap(), "/test");
+        assert_eq!(vfs.canonical_path("~").unwrap(), "/");
+        assert_eq!(vfs.canonical_path("~/test/./second_dir/..").unwrap(), "/test");
+
+        vfs.change_dir(vec!["./test/second_dir".to_string()]).unwrap();
+        assert_eq!(vfs.current_path(), "/test/second_dir");
+        assert_eq!(vfs.canonical_path("..").unwrap(), "/test");
+        assert_eq!(vfs.canonical_path("../second_dir/example_file").unwrap(), "/test/second_dir/example_file");
+
+        vfs.change_dir(vec!["..".to_string()]).unwrap();
+        assert_eq!(vfs.current_path(), "/test");
+        assert!(vfs.change_dir(vec!["xd.pl".to_string()]).is_err());
+        vfs.change_dir(vec![]).unwrap();
+        assert_eq!(vfs.current_path(), "/");
+    }
+
+    #[test]
+    fn test_change_dir_keeps_node() {
+        let mut vfs = VFS::new("".to_string(), "./storage".to_string()).unwrap();
+        let second_dir = vfs.resolve_path("/test/second_dir").unwrap();
+
+        assert!(vfs.change_dir(vec!["test/second_dir".to_string()]).unwrap().is_dir());
+        assert_eq!(vfs.cwd, second_dir);
+        assert!(vfs.change_dir(vec!["missing".to_string()]).is_err());
+        assert_eq!(vfs.cwd, second_dir);
+    }
+
+    #[test]
+    fn test_read_file() {
+        let mut vfs = VFS::new("".to_string(), "./storage".to_string()).unwrap();
+        let content = vfs.read_file(&"/whoami.pl".to_string()).unwrap();
+        assert_eq!(content, &b"whoami".to_vec());
+
+        let err = vfs.read_file(&"/test".to_string()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        let err = vfs.read_file(&"/missing.pl".to_string()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_sync() {
+        let storage = std::env::temp_dir().join(format!("polina_sync_{}", std::process::id()));
+        fs::create_dir_all(storage.join("test")).unwrap();
+        fs::write(storage.join("home.pl"), "cd").unwrap();
+        let storage_path = storage.to_str().unwrap().to_string();
+
+        let mut vfs = VFS::new(SHELL_USER.to_string(), storage_path.clone()).unwrap();
+        vfs.add_user("ilya", None, &[], false).unwrap();
+        vfs.set_node_owner("/test".to_string(), "ilya".to_string()).unwrap();
+        vfs.set_node_mode("/test".to_string(), "750").unwrap();
+        vfs.sync().unwrap();
+
+        let mut vfs = VFS::new("".to_string(), storage_path).unwrap();
+        let test = vfs.resolve_path("/test").unwrap();
+        assert_eq!(vfs.tree.get(test).owner, "ilya");
+        assert_eq!(vfs.tree.get(test).mode, 0o750);
+        assert!(vfs.find_user("ilya").is_some());
+        assert_eq!(vfs.read_file(&"/home.pl".to_string()).unwrap(), &b"cd".to_vec());
+        assert!(vfs.list_dir(".").unwrap().iter().all(|(name, _)| name != META_FILE_NAME));
+
+        fs::remove_dir_all(storage).unwrap();
+    }
+
+    #[test]
+    fn test_permissions() {
+        let mut vfs = VFS::new(SHELL_USER.to_string(), "./storage".to_string()).unwrap();
+        vfs.set_node_mode("/test".to_string(), "700").unwrap();
+        vfs.set_node_mode("/whoami.pl".to_string(), "u=rw,go=").unwrap();
+        vfs.add_user("ilya", None, &[], false).unwrap();
+        vfs.set_node_owner("/home.pl".to_string(), "ilya".to_string()).unwrap();
+        assert!(vfs.set_node_owner("/home.pl".to_string(), "nobody".to_string()).is_err());
+        vfs.set_node_owner("/whoami.pl".to_string(), ":ilya".to_string()).unwrap();
+        let whoami = vfs.tree.get(vfs.tree.lookup("/whoami.pl").unwrap());
+        assert_eq!((whoami.owner.as_str(), whoami.group.as_str()), ("root", "ilya"));
+        assert!(vfs.set_node_owner("/whoami.pl".to_string(), ":nobody".to_string()).is_err());
+
+        vfs.switch_user("ilya").unwrap();
+        let err = vfs.change_dir(vec!["/test".to_string()]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        assert_eq!(err.to_string(), "/test: Permission denied");
+        assert!(vfs.list_dir("/test/second_dir").is_err());
+        assert!(vfs.read_file(&"/whoami.pl".to_string()).is_err());
+        assert!(vfs.set_node_owner("/home.pl".to_string(), "ilya".to_string()).is_err());
+        assert!(vfs.set_node_mode("/whoami.pl".to_string(), "777").is_err());
+
+        vfs.set_node_mode("/home.pl".to_string(), "000").unwrap();
+        assert!(vfs.read_file(&"/home.pl".to_string()).is_err());
+        vfs.set_node_mode("/home.pl".to_string(), "u+r").unwrap();
+        assert!(vfs.read_file(&"/home.pl".to_string()).is_ok());
+
+        // group bits, a member added to `/etc/group` counts right away
+        vfs.user = SHELL_USER.to_string();
+        vfs.set_node_mode("/test".to_string(), "750").unwrap();
+        let test = vfs.tree.lookup("/test").unwrap();
+        vfs.tree.get_mut(test).group = "staff".to_string();
+        vfs.user = "ilya".to_string();
+        assert!(vfs.change_dir(vec!["/test".to_string()]).is_err());
+        vfs.user = SHELL_USER.to_string();
+        vfs.write_file("/etc/group", b"staff:x:50:ilya\n", true).unwrap();
+        vfs.user = "ilya".to_string();
+        assert!(vfs.change_dir(vec!["/test".to_string()]).is_ok());
+    }
+
+    #[test]
+    fn test_links() {
+        let mut vfs = VFS::new(SHELL_USER.to_string(), "./storage".to_string()).unwrap();
+        vfs.create_link("test/second_dir", "/sd", true, false).unwrap();
+        vfs.create_link("/whoami.pl", "/test", false, false).unwrap();
+        vfs.create_link("loop_b", "/loop_a", true, false).unwrap();
+        vfs.create_link("loop_a", "/loop_b", true, false).unwrap();
+
+        assert_eq!(vfs.read_link("/sd", false).unwrap(), "test/second_dir");
+        assert_eq!(vfs.read_link("/sd", true).unwrap(), "/test/second_dir");
+        assert_eq!(vfs.read_link("/sd/..", true).unwrap(), "/test");
+        assert!(vfs.read_link("/whoami.pl", false).is_err());
+        assert_eq!(vfs.read_file(&"/test/whoami.pl".to_string()).unwrap(), &b"whoami".to_vec());
+
+        vfs.change_dir(vec!["/sd".to_string()]).unwrap();
+        assert_eq!(vfs.current_path(), "/test/second_dir");
+
+        let err = vfs.read_file(&"/loop_a".to_string()).unwrap_err();
+        assert!(err.to_string().contains("Too many levels of symbolic links"));
+        assert_eq!(vfs.read_link("/loop_a", false).unwrap(), "loop_b");
+
+        assert!(vfs.create_link("/test", "/test_link", false, false).is_err());
+        vfs.create_link("/home.pl", "/sd", true, false).unwrap();
+        assert_eq!(vfs.read_link("/test/second_dir/home.pl", false).unwrap(), "/home.pl");
+        assert_eq!(
+            vfs.create_link("/home.pl", "/loop_a", false, false).unwrap_err().kind(),
+            ErrorKind::AlreadyExists
+        );
+        vfs.create_link("/home.pl", "/loop_a", false, true).unwrap();
+        let home = vfs.read_file(&"/home.pl".to_string()).unwrap().clone();
+        assert_eq!(vfs.read_file(&"/loop_a".to_string()).unwrap(), &home);
+    }
+
+    #[test]
+    fn test_stat_touch() {
+        let mut vfs = VFS::new(SHELL_USER.to_string(), "./storage".to_string()).unwrap();
+        let whoami = vfs.resolve_path("/whoami.pl").unwrap();
+        vfs.tree.get_mut(whoami).atime = 0;
+        vfs.tree.get_mut(whoami).mtime = 0;
+
+        let stat = vfs.stat("/whoami.pl").unwrap();
+        assert!(stat.contains("Size: 6\tregular file"));
+        assert!(stat.contains("Uid: (0/root)"));
+        assert!(stat.contains("Modify: 1970-01-01 00:00:00"));
+
+        // a read sets atime without counting as a change, cached groups stay
+        let generation = vfs.tree.generation();
+        vfs.read_file(&"/whoami.pl".to_string()).unwrap();
+        vfs.list_dir("/test").unwrap();
+        assert!(vfs.tree.get(whoami).atime > 0);
+        assert_eq!(vfs.tree.generation(), generation);
+        vfs.touch("/whoami.pl", false, true, false).unwrap();
+        assert!(vfs.tree.get(whoami).mtime > 0);
+
+        vfs.umask = 0o027;
+        vfs.touch("/test/new.pl", true, true, false).unwrap();
+        let new = vfs.resolve_path("/test/new.pl").unwrap();
+        assert_eq!(vfs.tree.get(new).mode, 0o640);
+        assert_eq!(vfs.tree.get(new).size(), 0);
+        vfs.touch("/test/other.pl", true, true, true).unwrap();
+        assert!(vfs.resolve_path("/test/other.pl").is_err());
+
+        vfs.create_link("whoami.pl", "/w", true, false).unwrap();
+        assert!(vfs.stat("/w").unwrap().contains("/w -> whoami.pl"));
+    }
+
+    #[test]
+    fn test_write_file() {
+        let mut vfs = VFS::new(SHELL_USER.to_string(), "./storage".to_string()).unwrap();
+        vfs.write_file("/test/out.txt", b"one\n", false).unwrap();
+        vfs.write_file("/test/out.txt", b"two\n", true).unwrap();
+        assert_eq!(vfs.read_file(&"/test/out.txt".to_string()).unwrap(), b"one\ntwo\n");
+        vfs.write_file("/test/out.txt", b"", false).unwrap();
+        assert!(vfs.read_file(&"/test/out.txt".to_string()).unwrap().is_empty());
+        assert!(vfs.write_file("/test", b"x", false).is_err());
+        assert!(vfs.write_file("/missing/out.txt", b"x", false).is_err());
+
+        // a dangling link gets its target created, through a chain of links too
+        vfs.create_link("nonexist", "/test/l", true, false).unwrap();
+        vfs.create_link("/test/l", "/m", true, false).unwrap();
+        vfs.write_file("/m", b"hi\n", false).unwrap();
+        assert_eq!(vfs.read_file(&"/test/nonexist".to_string()).unwrap(), b"hi\n");
+        assert_eq!(vfs.read_file(&"/test/l".to_string()).unwrap(), b"hi\n");
+        vfs.create_link("/missing/x", "/n", true, false).unwrap();
+        assert_eq!(
+            vfs.write_file("/n", b"x", false).unwrap_err().to_string(),
+            "missing: no such file or directory"
+        );
+
+        vfs.add_user("ilya", None, &[], false).unwrap();
+        vfs.switch_user("ilya").unwrap();
+        let err = vfs.write_file("/whoami.pl", b"x", true).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        assert!(vfs.write_file("/test/ilya.txt", b"x", false).is_err());
+    }
+
+    #[test]
+    fn test_mkdir_rm() {
+        let mut vfs = VFS::new(SHELL_USER.to_string(), "./storage".to_string()).unwrap();
+        vfs.make_dir("/a/b/c", true).unwrap();
+        vfs.make_dir("/a/b", true).unwrap();
+        assert!(vfs.is_dir("/a/b/c"));
+        assert_eq!(vfs.make_dir("/a", false).unwrap_err().kind(), ErrorKind::AlreadyExists);
+        assert!(vfs.make_dir("/missing/x", false).is_err());
+
+        assert_eq!(vfs.remove_dir("/a").unwrap_err().kind(), ErrorKind::DirectoryNotEmpty);
+        vfs.remove_dir("/a/b/c").unwrap();
+        assert!(vfs.remove_node("/a", false, false).is_err());
+
+        vfs.change_dir(vec!["/a/b".to_string()]).unwrap();
+        assert_eq!(vfs.remove_node("/a", true, false).unwrap_err().kind(), ErrorKind::ResourceBusy);
+        vfs.change_dir(vec!["/".to_string()]).unwrap();
+        vfs.remove_node("/a", true, false).unwrap();
+        assert!(vfs.resolve_path("/a").is_err());
+
+        assert!(vfs.remove_node("/missing.pl", false, false).is_err());
+        vfs.remove_node("/missing.pl", false, true).unwrap();
+        assert!(vfs.remove_node("/", true, false).is_err());
+
+        vfs.add_user("ilya", None, &[], false).unwrap();
+        vfs.switch_user("ilya").unwrap();
+        assert_eq!(vfs.remove_node("/home.pl", false, false).unwrap_err().kind(), ErrorKind::PermissionDenied);
+        assert!(vfs.make_dir("/ilya_dir", false).is_err());
+    }
+
+    #[test]
+    fn test_mv_cp() {
+        let mut vfs = VFS::new(SHELL_USER.to_string(), "./storage".to_string()).unwrap();
+        let xd = vfs.resolve_path("/test/xd.pl").unwrap();
+        vfs.move_node("/test/xd.pl", "/renamed.pl").unwrap();
+        assert_eq!(vfs.resolve_path("/renamed.pl").unwrap(), xd);
+        vfs.move_node("/renamed.pl", "/test/second_dir").unwrap();
+        assert_eq!(vfs.current_path(), "/");
+        assert_eq!(vfs.resolve_path("/test/second_dir/renamed.pl").unwrap(), xd);
+        assert!(vfs.move_node("/test", "/test/second_dir").is_err());
+        assert!(vfs.move_node("/test", "/home.pl").is_err());
+
+        assert!(vfs.copy_node("/test", "/copy", false).is_err());
+        vfs.umask = 0o077;
+        vfs.copy_node("/test", "/copy", true).unwrap();
+        let copy = vfs.resolve_path("/copy/second_dir/renamed.pl").unwrap();
+        assert_ne!(vfs.tree.node_index(copy), vfs.tree.node_index(xd));
+        assert_eq!(vfs.tree.get(copy).mode & 0o077, 0);
+        assert!(vfs.copy_node("/test", "/test/second_dir", true).is_err());
+
+        vfs.copy_node("/whoami.pl", "/home.pl", false).unwrap();
+        assert_eq!(vfs.read_file(&"/home.pl".to_string()).unwrap(), &b"whoami".to_vec());
+        assert!(vfs.copy_node("/home.pl", "/home.pl", false).is_err());
     }
 }
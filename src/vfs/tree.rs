@@ -0,0 +1,352 @@
+use crate::vfs::time::now;
+
+pub type NodeId = usize;
+
+// what `ls -l` and `stat` show for a dir
+const DIR_SIZE: u64 = 4096;
+
+#[derive(Clone, Debug)]
+pub enum VFSNodeKind {
+    File {
+        content: Vec<u8>
+    },
+    Dir {
+        children: Vec<NodeId>
+    },
+    Symlink {
+        target: String
+    },
+}
+
+/// Data, access bits and timestamps of a file, shared by all hard links to it.
+#[derive(Clone, Debug)]
+pub struct VFSNode {
+    pub owner: String,
+    pub group: String,
+    pub mode: u16,
+    pub links: usize,
+    // seconds since the epoch: last read, last content change, last change of anything
+    pub atime: u64,
+    pub mtime: u64,
+    pub ctime: u64,
+    pub kind: VFSNodeKind,
+}
+
+impl VFSNode {
+    pub fn new(owner: String, group: String, mode: u16, kind: VFSNodeKind) -> Self {
+        let time = now();
+        VFSNode {
+            owner,
+            group,
+            mode,
+            links: 0,
+            atime: time,
+            mtime: time,
+            ctime: time,
+            kind,
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        match &self.kind {
+            VFSNodeKind::File { content } => content.len() as u64,
+            VFSNodeKind::Dir { .. } => DIR_SIZE,
+            VFSNodeKind::Symlink { target } => target.len() as u64,
+        }
+    }
+
+    /// Content changed, bumps mtime and ctime.
+    pub fn modified(&mut self) {
+        self.mtime = now();
+        self.ctime = self.mtime;
+    }
+
+    /// Only the metadata changed (owner, mode, links), bumps ctime.
+    pub fn changed(&mut self) {
+        self.ctime = now();
+    }
+
+    pub fn is_dir(&self) -> bool {
+        matches!(self.kind, VFSNodeKind::Dir { .. })
+    }
+
+    /// Type letter of `ls -l`.
+    pub fn file_type(&self) -> char {
+        match self.kind {
+            VFSNodeKind::File { .. } => '-',
+            VFSNodeKind::Dir { .. } => 'd',
+            VFSNodeKind::Symlink { .. } => 'l',
+        }
+    }
+}
+
+/// A name in a dir pointing to a node, hard links are several entries with one node.
+#[derive(Clone, Debug)]
+struct Entry {
+    name: String,
+    parent: Option<NodeId>,
+    node: usize,
+}
+
+/// Arena of entries and nodes, a `NodeId` stays valid for the whole life of its entry.
+#[derive(Clone, Debug)]
+pub struct VFSTree {
+    entries: Vec<Option<Entry>>,
+    nodes: Vec<Option<VFSNode>>,
+    // bumped on every change, see `generation`
+    generation: u64,
+}
+
+impl VFSTree {
+    pub const ROOT: NodeId = 0;
+
+    pub fn new(owner: String, group: String, mode: u16) -> Self {
+        let mut root = VFSNode::new(owner, group, mode, VFSNodeKind::Dir { children: vec![] });
+        root.links = 1;
+
+        VFSTree {
+            entries: vec![Some(Entry {
+                name: "/".to_string(),
+                parent: None,
+                node: 0,
+            })],
+            nodes: vec![Some(root)],
+            generation: 0,
+        }
+    }
+
+    fn entry(&self, id: NodeId) -> &Entry {
+        self.entries[id].as_ref().expect("stale node id")
+    }
+
+    pub fn get(&self, id: NodeId) -> &VFSNode {
+        self.nodes[self.entry(id).node].as_ref().expect("stale node")
+    }
+
+    pub fn get_mut(&mut self, id: NodeId) -> &mut VFSNode {
+        self.generation += 1;
+        let node = self.entry(id).node;
+        self.nodes[node].as_mut().expect("stale node")
+    }
+
+    /// Tells whether the tree changed since it was last looked at, every change
+    /// goes through [`VFSTree::get_mut`] and bumps it. Reads only set atime
+    /// with [`VFSTree::touch_atime`], which leaves it alone.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Marks the node as read, not a change to the tree.
+    pub fn touch_atime(&mut self, id: NodeId) {
+        let node = self.entry(id).node;
+        if let Some(node) = self.nodes[node].as_mut() {
+            node.atime = now();
+        }
+    }
+
+    pub fn name(&self, id: NodeId) -> &str {
+        &self.entry(id).name
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.entry(id).parent
+    }
+
+    /// Index of the node behind an entry, equal for hard links to the same file.
+    pub fn node_index(&self, id: NodeId) -> usize {
+        self.entry(id).node
+    }
+
+    /// Inode number shown by `stat`, the root is 1.
+    pub fn inode(&self, id: NodeId) -> u64 {
+        self.node_index(id) as u64 + 1
+    }
+
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        match &self.get(id).kind {
+            VFSNodeKind::Dir { children } => children,
+            _ => &[],
+        }
+    }
+
+    pub fn child(&self, id: NodeId, name: &str) -> Option<NodeId> {
+        self.children(id)
+            .iter()
+            .copied()
+            .find(|&child| self.name(child) == name)
+    }
+
+    fn attach(&mut self, parent: NodeId, name: String, node: usize) -> NodeId {
+        let id = self.entries.len();
+        self.entries.push(Some(Entry {
+            name,
+            parent: Some(parent),
+            node,
+        }));
+        if let Some(node) = self.nodes[node].as_mut() {
+            node.links += 1;
+            node.changed();
+        }
+
+        let parent = self.get_mut(parent);
+        if let VFSNodeKind::Dir { children } = &mut parent.kind {
+            children.push(id);
+        }
+        parent.modified();
+
+        id
+    }
+
+    /// Adds a new node under `parent` and returns its id, `parent` must be a dir.
+    pub fn add_child(&mut self, parent: NodeId, name: String, node: VFSNode) -> NodeId {
+        self.nodes.push(Some(node));
+        self.attach(parent, name, self.nodes.len() - 1)
+    }
+
+    /// Adds another name for the node behind `target` (a hard link).
+    pub fn add_link(&mut self, parent: NodeId, name: String, target: NodeId) -> NodeId {
+        let node = self.entry(target).node;
+        self.attach(parent, name, node)
+    }
+
+    /// Moves an entry under `parent` with a new name, the node keeps its links.
+    pub fn move_entry(&mut self, id: NodeId, parent: NodeId, name: String) {
+        if let Some(old_parent) = self.parent(id) {
+            let old_parent = self.get_mut(old_parent);
+            if let VFSNodeKind::Dir { children } = &mut old_parent.kind {
+                children.retain(|&child| child != id);
+            }
+            old_parent.modified();
+        }
+
+        let entry = self.entries[id].as_mut().expect("stale node id");
+        entry.name = name;
+        entry.parent = Some(parent);
+        self.get_mut(id).changed();
+
+        let parent = self.get_mut(parent);
+        if let VFSNodeKind::Dir { children } = &mut parent.kind {
+            children.push(id);
+        }
+        parent.modified();
+    }
+
+    /// Whether `id` is `ancestor` itself or lies somewhere below it.
+    pub fn is_within(&self, id: NodeId, ancestor: NodeId) -> bool {
+        let mut current = Some(id);
+        while let Some(node) = current {
+            if node == ancestor {
+                return true;
+            }
+            current = self.parent(node);
+        }
+        false
+    }
+
+    /// Detaches the entry from its parent and frees it with the whole subtree,
+    /// a node goes away together with its last link.
+    pub fn remove(&mut self, id: NodeId) {
+        if let Some(parent) = self.parent(id) {
+            let parent = self.get_mut(parent);
+            if let VFSNodeKind::Dir { children } = &mut parent.kind {
+                children.retain(|&child| child != id);
+            }
+            parent.modified();
+        }
+
+        let mut stack = vec![id];
+        while let Some(current) = stack.pop() {
+            stack.extend_from_slice(self.children(current));
+
+            let node = self.entry(current).node;
+            self.entries[current] = None;
+            if let Some(data) = self.nodes[node].as_mut() {
+                data.links -= 1;
+                data.changed();
+                if data.links == 0 {
+                    self.nodes[node] = None;
+                }
+            }
+        }
+    }
+
+    /// Walks a canonical absolute path down from the root, links are not followed.
+    pub fn lookup(&self, path: &str) -> Option<NodeId> {
+        path.split('/')
+            .filter(|s| !s.is_empty())
+            .try_fold(Self::ROOT, |id, part| self.child(id, part))
+    }
+
+    /// Rebuilds the absolute path by walking parent links up to the root.
+    pub fn path_of(&self, id: NodeId) -> String {
+        let mut parts: Vec<&str> = vec![];
+        let mut current = id;
+
+        while let Some(parent) = self.parent(current) {
+            parts.push(self.name(current));
+            current = parent;
+        }
+        parts.reverse();
+
+        format!("/{}", parts.join("/"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(kind: VFSNodeKind) -> VFSNode {
+        VFSNode::new("root".to_string(), "root".to_string(), 0o755, kind)
+    }
+
+    #[test]
+    fn test_path_of() {
+        let mut tree = VFSTree::new("root".to_string(), "root".to_string(), 0o755);
+        let test = tree.add_child(VFSTree::ROOT, "test".to_string(), node(VFSNodeKind::Dir { children: vec![] }));
+        let file = tree.add_child(test, "xd.pl".to_string(), node(VFSNodeKind::File { content: vec![] }));
+
+        assert_eq!(tree.path_of(VFSTree::ROOT), "/");
+        assert_eq!(tree.path_of(file), "/test/xd.pl");
+        assert_eq!(tree.lookup("/test/xd.pl"), Some(file));
+        assert_eq!(tree.lookup("/test/missing"), None);
+        assert_eq!(tree.lookup("/test/xd.pl/deeper"), None);
+
+        tree.remove(test);
+        assert_eq!(tree.lookup("/test"), None);
+        assert!(tree.children(VFSTree::ROOT).is_empty());
+    }
+
+    #[test]
+    fn test_hard_links() {
+        let mut tree = VFSTree::new("root".to_string(), "root".to_string(), 0o755);
+        let file = tree.add_child(VFSTree::ROOT, "a".to_string(), node(VFSNodeKind::File { content: vec![] }));
+        let link = tree.add_link(VFSTree::ROOT, "b".to_string(), file);
+
+        assert_eq!(tree.node_index(file), tree.node_index(link));
+        assert_eq!(tree.get(file).links, 2);
+
+        tree.get_mut(link).kind = VFSNodeKind::File { content: b"shared".to_vec() };
+        match &tree.get(file).kind {
+            VFSNodeKind::File { content } => assert_eq!(content, b"shared"),
+            _ => panic!("expected file"),
+        }
+
+        tree.remove(file);
+        assert_eq!(tree.get(link).links, 1);
+        assert_eq!(tree.path_of(link), "/b");
+    }
+
+    #[test]
+    fn test_move_entry() {
+        let mut tree = VFSTree::new("root".to_string(), "root".to_string(), 0o755);
+        let test = tree.add_child(VFSTree::ROOT, "test".to_string(), node(VFSNodeKind::Dir { children: vec![] }));
+        let file = tree.add_child(VFSTree::ROOT, "a".to_string(), node(VFSNodeKind::File { content: vec![] }));
+
+        tree.move_entry(file, test, "b".to_string());
+        assert_eq!(tree.path_of(file), "/test/b");
+        assert_eq!(tree.children(VFSTree::ROOT), &[test]);
+        assert!(tree.is_within(file, test));
+        assert!(!tree.is_within(test, file));
+    }
+}
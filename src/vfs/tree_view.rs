@@ -0,0 +1,171 @@
+use std::io::{Error, ErrorKind};
+
+use crate::vfs::permissions::{EXEC, READ};
+use crate::vfs::storage::VFS;
+use crate::vfs::tree::{NodeId, VFSNodeKind};
+
+/// Parsed `tree` command line: `-L <depth>`, `-d` dirs only, `-u`/`-g`
+/// owner and group next to every name.
+#[derive(Debug, Default)]
+pub struct TreeOptions {
+    paths: Vec<String>,
+    max_depth: Option<usize>,
+    dirs_only: bool,
+    owner: bool,
+    group: bool,
+}
+
+impl TreeOptions {
+    pub fn parse(args: &[String]) -> Result<Self, Error> {
+        let mut options = TreeOptions::default();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-L" => {
+                    let depth = args
+                        .next()
+                        .and_then(|depth| depth.parse().ok())
+                        .filter(|depth| *depth > 0)
+                        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "-L needs a depth greater than 0"))?;
+                    options.max_depth = Some(depth);
+                }
+                "-d" => options.dirs_only = true,
+                "-u" => options.owner = true,
+                "-g" => options.group = true,
+                flag if flag.starts_with('-') => {
+                    return Err(Error::new(ErrorKind::InvalidInput, format!("invalid option {}", flag)))
+                }
+                path => options.paths.push(path.to_string()),
+            }
+        }
+        if options.paths.is_empty() {
+            options.paths.push(".".to_string());
+        }
+
+        Ok(options)
+    }
+}
+
+#[derive(Default)]
+struct TreeCounts {
+    dirs: usize,
+    files: usize,
+}
+
+impl VFS {
+    /// Draws every start path with box-drawing connectors and ends with the
+    /// `N directories, M files` summary. Links are shown, not followed.
+    pub fn render_tree(&self, options: &TreeOptions) -> Result<String, Error> {
+        let mut output = String::new();
+        let mut counts = TreeCounts::default();
+
+        for path in &options.paths {
+            let id = self.resolve(path, true)?;
+            output.push_str(&format!("{}{}\n", self.tree_annotation(id, options), path));
+            if self.tree.get(id).is_dir() {
+                self.render_children(id, options, "", 1, &mut counts, &mut output);
+            }
+        }
+
+        output.push_str(&format!(
+            "\n{} {}",
+            counts.dirs,
+            if counts.dirs == 1 { "directory" } else { "directories" }
+        ));
+        if !options.dirs_only {
+            output.push_str(&format!(", {} {}", counts.files, if counts.files == 1 { "file" } else { "files" }));
+        }
+        output.push('\n');
+
+        Ok(output)
+    }
+
+    fn tree_annotation(&self, id: NodeId, options: &TreeOptions) -> String {
+        let node = self.tree.get(id);
+        match (options.owner, options.group) {
+            (true, true) => format!("[{} {}]  ", node.owner, node.group),
+            (true, false) => format!("[{}]  ", node.owner),
+            (false, true) => format!("[{}]  ", node.group),
+            (false, false) => String::new(),
+        }
+    }
+
+    fn render_children(
+        &self,
+        dir: NodeId,
+        options: &TreeOptions,
+        prefix: &str,
+        depth: usize,
+        counts: &mut TreeCounts,
+        output: &mut String,
+    ) {
+        if self.check_access(dir, READ | EXEC).is_err() {
+            output.push_str(&format!("{}└── [error opening dir]\n", prefix));
+            return;
+        }
+
+        let mut children: Vec<NodeId> = self
+            .tree
+            .children(dir)
+            .iter()
+            .copied()
+            .filter(|&child| !options.dirs_only || self.tree.get(child).is_dir())
+            .collect();
+        children.sort_by(|a, b| self.tree.name(*a).cmp(self.tree.name(*b)));
+
+        for (index, &child) in children.iter().enumerate() {
+            let last = index + 1 == children.len();
+            let node = self.tree.get(child);
+            let name = match &node.kind {
+                VFSNodeKind::Symlink { target } => format!("{} -> {}", self.tree.name(child), target),
+                _ => self.tree.name(child).to_string(),
+            };
+            output.push_str(&format!(
+                "{}{}{}{}\n",
+                prefix,
+                if last { "└── " } else { "├── " },
+                self.tree_annotation(child, options),
+                name
+            ));
+
+            if node.is_dir() {
+                counts.dirs += 1;
+                if options.max_depth.is_none_or(|max| depth < max) {
+                    let prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+                    self.render_children(child, options, &prefix, depth + 1, counts, output);
+                }
+            } else {
+                counts.files += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::window::polina_vfs::SHELL_USER;
+
+    fn render(vfs: &VFS, args: &str) -> String {
+        let args: Vec<String> = args.split_whitespace().map(str::to_string).collect();
+        vfs.render_tree(&TreeOptions::parse(&args).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_render_tree() {
+        let mut vfs = VFS::new(SHELL_USER.to_string(), "./storage".to_string()).unwrap();
+        vfs.create_link("xd.pl", "/test/xd_link", true, false).unwrap();
+
+        assert_eq!(
+            render(&vfs, "/test"),
+            "/test\n├── second_dir\n│   └── example_file\n├── xd.pl\n└── xd_link -> xd.pl\n\n1 directory, 3 files\n"
+        );
+        assert_eq!(render(&vfs, "/test -d"), "/test\n└── second_dir\n\n1 directory\n");
+        assert_eq!(
+            render(&vfs, "/test -L 1 -u"),
+            "[root]  /test\n├── [root]  second_dir\n├── [root]  xd.pl\n└── [root]  xd_link -> xd.pl\n\n1 directory, 2 files\n"
+        );
+        assert!(TreeOptions::parse(&["-L".to_string(), "0".to_string()]).is_err());
+    }
+}
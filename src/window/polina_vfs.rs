@@ -1,29 +1,30 @@
 use clap::Parser;
 use iced::widget::{button, column, text, text_editor, Container};
-use iced::{alignment, Element, Length};
+use iced::{alignment, window, Element, Length, Subscription};
 use once_cell::sync::Lazy;
 
-use std::collections::HashMap;
+use std::path::Path;
 use std::process;
 
 use iced::widget::container;
 use iced::widget::text_editor::Action;
 use iced::widget::text_editor::Edit;
 
+use crate::handler::session::Session;
 use crate::handler::shell::{Commands, SystemCall};
-use crate::vfs::storage::{VFSArgs, VFSNode, VFS};
+use crate::vfs::storage::{VFSArgs, DEFAULT_STORAGE, VFS};
 
 
 pub const SHELL_USER: &str = "root";
 const OS_NAME: &str = "polina";
+// shown instead of the prefix while a command goes on over several lines
+const CONTINUATION_PROMPT: &str = "> ";
 
 
 pub struct MainWindow {
     text_data: text_editor::Content,
     args: VFSArgs,
-    user: String,
-    vfs: Option<VFS>,
-    history: Vec<String>,
+    session: Session,
     show_start_button: bool,
 }
 
@@ -31,31 +32,34 @@ pub struct MainWindow {
 pub enum Message {
     Edit(text_editor::Action),
     RunStartupScript,
+    CloseRequested,
 }
 
 impl MainWindow {
     pub fn new() -> MainWindow {
         let shell_args = VFSArgs::parse();
-        let vfs = shell_args
-            .storage
-            .clone()
-            .and_then(|path| VFS::new(SHELL_USER.to_string(), path).ok());
-
-        let vfs_user: String;
-
-        match vfs {
-            Some(ref vfs) => vfs_user = vfs.user.clone(),
-            None => vfs_user = "".to_string(),
-        }
+        // a storage that fails to load, or loads only in part, is reported
+        // above the first prompt
+        let path = shell_args.storage.clone().unwrap_or(DEFAULT_STORAGE.to_string());
+        let (vfs, load_error) = match VFS::new(SHELL_USER.to_string(), path.clone()) {
+            Ok(vfs) => {
+                let skipped: String = vfs
+                    .load_warnings()
+                    .iter()
+                    .map(|warning| format!("storage: skipped {}\n", warning))
+                    .collect();
+                (Some(vfs), skipped)
+            }
+            Err(err) => (None, format!("storage {}: {}\n", path, err)),
+        };
+        let session = Session::new(vfs);
 
         Self {
             text_data: text_editor::Content::with_text(
-                Lazy::new(|| MainWindow::get_shell_prefix(vfs_user.clone())).as_str(),
+                Lazy::new(|| format!("{}{}", load_error, MainWindow::get_shell_prefix(session.user.clone()))).as_str(),
             ),
             args: shell_args.clone(),
-            vfs,
-            user: vfs_user,
-            history: vec![],
+            session,
             show_start_button: shell_args.startapp.clone().is_some(),
         }
     }
@@ -64,6 +68,54 @@ impl MainWindow {
         format!("[{}@{}]# ", vfs_user, OS_NAME)
     }
 
+    /// What the line being typed starts after, the continuation prompt always
+    /// begins a line so `> ` inside a command doesn't count.
+    fn prompt_marker(&self) -> String {
+        if self.session.is_continuing() {
+            format!("\n{}", CONTINUATION_PROMPT)
+        } else {
+            MainWindow::get_shell_prefix(self.session.user.clone())
+        }
+    }
+
+    fn show_prompt(&mut self) {
+        let prompt = if self.session.is_continuing() {
+            CONTINUATION_PROMPT.to_string()
+        } else {
+            MainWindow::get_shell_prefix(self.session.user.clone())
+        };
+        self.custom_message(&prompt, None, None);
+    }
+
+    /// Carries out what the session left for the terminal.
+    fn show(&mut self, system_calls: Vec<SystemCall>) {
+        for system_call in system_calls {
+            match system_call {
+                SystemCall::Display(text) => self.custom_message(&text, None, None),
+                SystemCall::DisplayNewLine => self.custom_message(&"\n".to_string(), None, None),
+                SystemCall::Clear => self.text_data = text_editor::Content::new(),
+                SystemCall::Exit(status) => self.sync_and_exit(status.unwrap_or(0)),
+                // everything else is carried out by the session
+                _ => {}
+            }
+        }
+    }
+
+    /// Writes the VFS back on the way out, but only into a storage the user
+    /// named with `--storage`, the default one is left as it is.
+    fn sync_and_exit(&self, status: i32) -> ! {
+        if let (Some(vfs), Some(_)) = (self.session.vfs.as_ref(), &self.args.storage) {
+            if let Err(err) = vfs.sync() {
+                eprintln!("sync: {}", err);
+            }
+        }
+        process::exit(status);
+    }
+
+    pub fn subscription(&self) -> Subscription<Message> {
+        window::close_requests().map(|_| Message::CloseRequested)
+    }
+
     fn custom_message(&mut self, message: &String, start: Option<&String>, end: Option<&String>) {
         let full_message = format!(
             "{}{}{}",
@@ -84,19 +136,13 @@ impl MainWindow {
                 match message_action {
                     Action::Edit(data_type) => match data_type {
                         Edit::Backspace => {
-                            // TODO: very bad method (use Lazy::new), change it in future
+                            let prompt = self.prompt_marker();
                             let text = self
                                 .text_data
                                 .text()
-                                .split(
-                                    Lazy::new(|| MainWindow::get_shell_prefix(self.user.clone()))
-                                        .as_str(),
-                                )
+                                .split(prompt.as_str())
                                 .last()
-                                .unwrap_or(
-                                    Lazy::new(|| MainWindow::get_shell_prefix(self.user.clone()))
-                                        .as_str(),
-                                )
+                                .unwrap_or(prompt.as_str())
                                 .to_string();
 
                             if text.len() > 1 {
@@ -105,175 +151,11 @@ impl MainWindow {
                         }
                         Edit::Enter => {
                             // its bad method, user can write {TERM_PREFIX} and function return bad result
-                            let command = Commands::get_last_command_frame(
-                                Lazy::new(|| MainWindow::get_shell_prefix(self.user.clone()))
-                                    .as_str(),
-                                &self.text_data,
-                            );
-                            self.history.push(command.clone());
-
-                            if self.history.len() > 30 {
-                                self.history.remove(0);
-                            }
-
-                            let command_result = Commands::parse_from_string(command).execute();
-
-                            for system_call in command_result {
-                                match system_call {
-                                    SystemCall::ChangeDir(command_args) => {
-                                        if self.vfs.is_none() {
-                                            self.custom_message(
-                                                &format!("VFS storage not set"),
-                                                None,
-                                                Some(&"\n".to_string()),
-                                            );
-                                            return;
-                                        }
-                                        match self.vfs.as_mut() {
-                                            Some(vfs) => match vfs.change_dir(command_args) {
-                                                Ok(_) => {}
-                                                Err(err) => {
-                                                    self.custom_message(
-                                                        &format!("cd: {}", err.to_string()),
-                                                        None,
-                                                        Some(&"\n".to_string()),
-                                                    );
-                                                }
-                                            },
-                                            None => {}
-                                        }
-                                    }
-                                    SystemCall::ListDir(mut command_args) => {
-                                        let mut owner_info = false;
-                                        if command_args.contains(&"-l".to_string()) {
-                                            command_args.retain(|&ref x| x != "-l");
-                                            owner_info = true;
-                                        } 
-
-                                        let dirs_result = match self.vfs.as_mut() {
-                                            Some(vfs) => vfs.list_dir(command_args),
-                                            None => {
-                                                self.custom_message(
-                                                    &format!("VFS storage not set"),
-                                                    None,
-                                                    Some(&"\n".to_string()),
-                                                );
-                                                return;
-                                            }
-                                        };
-
-                                        let mut names_map: HashMap<String, String> = HashMap::new();
-
-                                        match dirs_result {
-                                            Ok(dirs) => {
-                                                for dir in dirs {
-                                                    match dir {
-                                                        VFSNode::File { name, owner }
-                                                        | VFSNode::Dir { name, owner, ..} => {
-                                                            names_map.insert(name.to_string(), owner.to_string());
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                            Err(err) => {
-                                                return self.custom_message(
-                                                    &format!("ls: {}", err.to_string()),
-                                                    None,
-                                                    Some(&"\n".to_string()),
-                                                );
-                                            }
-                                        }
-
-                                        if !names_map.is_empty() {
-                                            for (name, owner) in names_map {
-                                                if owner_info {
-                                                    self.custom_message(
-                                                        &format!("{} {}", owner, name),
-                                                        None,
-                                                        Some(&"\n".to_string()),
-                                                    );
-                                                } else {
-                                                    self.custom_message(
-                                                        &name.to_string(),
-                                                        None,
-                                                        Some(&" ".to_string()),
-                                                    );
-                                                }
-                                                
-                                            }
-                                            if !owner_info {
-                                                self.custom_message(&"\n".to_string(), None, None);
-                                            }
-                                        }
-                                    }
-                                    SystemCall::Whoami => {
-                                        self.custom_message(&self.user.clone(), None, None);
-                                    }
-                                    SystemCall::History => {
-                                        for (index, command) in
-                                            self.history.clone().iter().enumerate()
-                                        {
-                                            let mut command = command.clone(); 
-
-                                            if index + 1 == self.history.len() {
-                                                command = command.replace("\n", "");
-                                            }
-
-                                            self.custom_message(
-                                                &format!("{}: {}", index + 1, command).to_string(),
-                                                None,
-                                                None,
-                                            );
-                                        }
-                                    }
-                                    SystemCall::Display(log) => {
-                                        self.custom_message(&log, None, None);
-                                    }
-                                    SystemCall::Exit => {
-                                        process::exit(0); // exit code
-                                    }
-                                    SystemCall::Clear => {
-                                        self.text_data = text_editor::Content::new();
-                                    }
-                                    SystemCall::DisplayNewLine => {
-                                        self.custom_message(&"\n".to_string(), None, None);
-                                    }
-                                    SystemCall::ChangeOwner(command_args) => {
-                                        let user = command_args[0].clone();
-                                        let path = command_args[1].clone();
-                                       
-                                        match self.vfs.as_mut() {
-                                            Some(vfs) => {
-                                                match vfs.set_node_owner(path, user) {
-                                                    Err(error) => {
-                                                        self.custom_message(
-                                                            &format!("chown: {}", error),
-                                                            Some(&"\n".to_string()),
-                                                            None
-                                                        );
-                                                    },
-                                                    Ok(_) => {}
-                                                }
-                                            }
-                                            None => {
-                                                self.custom_message(
-                                                    &format!("VFS storage not set"),
-                                                    None,
-                                                    Some(&"\n".to_string()),
-                                                );
-                                                return;
-                                            }
-                                        }
-                                    }
-                                    
-                                    _ => {}
-                                }
-                            }
-                            self.custom_message(
-                                &MainWindow::get_shell_prefix(self.user.clone()),
-                                None,
-                                None,
-                            );
+                            let command =
+                                Commands::get_last_command_frame(&self.prompt_marker(), &self.text_data);
+                            let system_calls = self.session.run_line(&command);
+                            self.show(system_calls);
+                            self.show_prompt();
                         }
                         _ => {
                             self.text_data.perform(message_action.clone());
@@ -291,7 +173,17 @@ impl MainWindow {
                 self.text_data
                     .perform(text_editor::Action::Move(text_editor::Motion::End));
 
-                for command in self.args.get_init_commands() {
+                let name = self
+                    .args
+                    .startapp
+                    .as_deref()
+                    .and_then(|path| Path::new(path).file_name())
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                self.session.begin_script(&name, self.args.script_args.clone());
+
+                for (index, command) in self.args.get_init_commands().into_iter().enumerate() {
+                    self.session.set_script_line(index + 1);
                     if command.starts_with('#') {
                         continue;
                     }
@@ -300,14 +192,27 @@ impl MainWindow {
                         self.update(Message::Edit(text_editor::Action::Edit(Edit::Insert(char))));
                     }
                     self.update(Message::Edit(text_editor::Action::Edit(Edit::Enter)));
+
+                    // `set -e` and a command failed
+                    if self.session.is_script_stopped() {
+                        break;
+                    }
                 }
 
+                // an `if` or a loop the script never closed, then how it went
+                let system_calls = self.session.end_script();
+                self.show(system_calls);
+                self.show_prompt();
+
                 self.show_start_button = false;
             }
+            Message::CloseRequested => {
+                self.sync_and_exit(0);
+            }
         }
     }
 
-    pub fn view(&self) -> Element<Message> {
+    pub fn view(&self) -> Element<'_, Message> {
         let title = text("Polina VFS").size(20);
         let subtitle = text("Интерфейс для взаимодействия с виртуальной командной оболочкой")
             .size(16)
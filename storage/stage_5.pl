@@ -4,5 +4,6 @@ cd
 cd $PWD
 whoami
 history
+useradd -m ilya
 chown ilya /test
 ls -l